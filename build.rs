@@ -2,6 +2,30 @@ use std::error::Error;
 
 fn main() -> Result<(), Box<dyn Error>> {
     std::env::set_var("PROTOC", protobuf_src::protoc());
-    tonic_build::compile_protos("api/packet_streaming.proto")?;
+    // The bytes of the packets are shared with the capture pipeline instead of copied into the
+    // messages.
+    let mut config = prost_build::Config::new();
+    config.bytes(["."]);
+    // The metadata of the packets is also given as the structured fields of the JSON logs.
+    for message in [
+        "PacketMetadata",
+        "ArpMetadata",
+        "DnsMetadata",
+        "DnsQuestion",
+        "DnsAnswer",
+        "HttpMetadata",
+        "TlsMetadata",
+        "DhcpMetadata",
+    ] {
+        config.type_attribute(
+            format!("packet_streaming.{}", message),
+            "#[derive(serde::Serialize)]",
+        );
+    }
+    tonic_build::configure().compile_with_config(
+        config,
+        &["api/packet_streaming.proto"],
+        &["api"],
+    )?;
     Ok(())
 }
@@ -0,0 +1,368 @@
+use crate::sniffer::CAPTURE_TIMEOUT;
+use std::ffi::CString;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::atomic::{fence, AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Size of a block of the ring buffer, holding as many packets as fit in it.
+const BLOCK_SIZE: usize = 1 << 20;
+
+/// Number of blocks of the ring buffer of each socket.
+const BLOCK_COUNT: usize = 64;
+
+/// Size of the frames the kernel cuts the blocks into, only checked by the kernel with
+/// TPACKET_V3, whose packets have a variable length.
+const FRAME_SIZE: usize = 2048;
+
+const SOL_PACKET: libc::c_int = 263;
+const PACKET_RX_RING: libc::c_int = 5;
+const PACKET_STATISTICS: libc::c_int = 6;
+const PACKET_VERSION: libc::c_int = 10;
+const PACKET_FANOUT: libc::c_int = 18;
+const TPACKET_V3: libc::c_int = 2;
+const PACKET_FANOUT_HASH: u32 = 0;
+const PACKET_FANOUT_FLAG_DEFRAG: u32 = 0x8000;
+const TP_STATUS_KERNEL: u32 = 0;
+const TP_STATUS_USER: u32 = 1;
+const ETH_P_ALL: u16 = 0x0003;
+
+/// `tpacket_req3`, the layout of the ring buffer requested to the kernel.
+#[repr(C)]
+#[allow(dead_code)]
+struct RingRequest {
+    block_size: u32,
+    block_count: u32,
+    frame_size: u32,
+    frame_count: u32,
+    retire_timeout: u32,
+    sizeof_priv: u32,
+    feature_req_word: u32,
+}
+
+/// `tpacket_block_desc`, the header of a block, up to the fields read.
+#[repr(C)]
+#[allow(dead_code)]
+struct BlockHeader {
+    version: u32,
+    offset_to_priv: u32,
+    status: u32,
+    packet_count: u32,
+    first_packet: u32,
+}
+
+/// `tpacket3_hdr`, the header of a packet in a block, up to the fields read.
+#[repr(C)]
+#[allow(dead_code)]
+struct PacketHeader {
+    next_offset: u32,
+    sec: u32,
+    nsec: u32,
+    snaplen: u32,
+    len: u32,
+    status: u32,
+    mac: u16,
+    net: u16,
+}
+
+/// `tpacket_stats_v3`, the statistics of a socket since they were last read.
+#[repr(C)]
+#[allow(dead_code)]
+#[derive(Default)]
+struct SocketStats {
+    packets: u32,
+    drops: u32,
+    freeze_queue_count: u32,
+}
+
+/// `Counters` adds up the statistics of the sockets of a fanout group, which the kernel resets
+/// each time they are read.
+#[derive(Default)]
+struct Counters {
+    received: AtomicU64,
+    dropped: AtomicU64,
+}
+
+/// `Ring` is an AF_PACKET socket whose packets the kernel writes into a TPACKET_V3 ring buffer
+/// shared with the sniffer, which reads them without copying nor a system call per packet.
+///
+/// Properties:
+///
+/// * `socket`: The socket.
+/// * `map`: The ring buffer, mapped in memory.
+/// * `block`: The index of the block being read.
+/// * `next`: The offset of the next packet to read in the block and the number of packets left
+///   in it, or `None` if the block is not handed to the sniffer yet.
+/// * `header`: The pcap header of the last packet read.
+/// * `counters`: The statistics of the fanout group of the socket.
+pub struct Ring {
+    socket: OwnedFd,
+    map: *mut u8,
+    block: usize,
+    next: Option<(usize, u32)>,
+    header: pcap::PacketHeader,
+    counters: Arc<Counters>,
+}
+
+// The ring buffer is only read and handed back to the kernel by the thread owning the ring.
+unsafe impl Send for Ring {}
+
+/// It opens the AF_PACKET sockets capturing a network interface. Several sockets join a fanout
+/// group, the kernel sending all the packets of a connection to the same socket.
+///
+/// Arguments:
+///
+/// * `interface`: The name of the network interface.
+/// * `sockets`: The number of sockets.
+/// * `group`: The identifier of the fanout group, the process identifier if `None`. A single
+///   socket only joins a group whose identifier is given.
+///
+/// Returns:
+///
+/// The ring buffers of the sockets.
+pub fn open(interface: &str, sockets: usize, group: Option<u16>) -> io::Result<Vec<Ring>> {
+    let name = CString::new(interface)?;
+    let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+    if index == 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let fanout =
+        (sockets > 1 || group.is_some()).then(|| group.unwrap_or(std::process::id() as u16));
+    let counters = Arc::new(Counters::default());
+    (0..sockets.max(1))
+        .map(|_| Ring::open(index, fanout, counters.clone()))
+        .collect()
+}
+
+impl Ring {
+    /// It opens a socket bound to a network interface, with its ring buffer.
+    ///
+    /// Arguments:
+    ///
+    /// * `index`: The index of the network interface.
+    /// * `fanout`: The identifier of the fanout group the socket joins, if any.
+    /// * `counters`: The statistics of the fanout group.
+    fn open(index: u32, fanout: Option<u16>, counters: Arc<Counters>) -> io::Result<Self> {
+        // Without a protocol, the socket receives nothing until it is bound to the network
+        // interface, so that the packets of other interfaces never reach its ring buffer.
+        let fd = unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_RAW, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        set_option(&socket, PACKET_VERSION, &TPACKET_V3)?;
+        let request = RingRequest {
+            block_size: BLOCK_SIZE as u32,
+            block_count: BLOCK_COUNT as u32,
+            frame_size: FRAME_SIZE as u32,
+            frame_count: (BLOCK_SIZE / FRAME_SIZE * BLOCK_COUNT) as u32,
+            retire_timeout: CAPTURE_TIMEOUT.as_millis() as u32,
+            sizeof_priv: 0,
+            feature_req_word: 0,
+        };
+        set_option(&socket, PACKET_RX_RING, &request)?;
+
+        let map = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                BLOCK_SIZE * BLOCK_COUNT,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                socket.as_raw_fd(),
+                0,
+            )
+        };
+        if map == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        let ring = Ring {
+            socket,
+            map: map as *mut u8,
+            block: 0,
+            next: None,
+            header: pcap::PacketHeader {
+                ts: libc::timeval {
+                    tv_sec: 0,
+                    tv_usec: 0,
+                },
+                caplen: 0,
+                len: 0,
+            },
+            counters,
+        };
+
+        let mut address: libc::sockaddr_ll = unsafe { std::mem::zeroed() };
+        address.sll_family = libc::AF_PACKET as u16;
+        address.sll_protocol = ETH_P_ALL.to_be();
+        address.sll_ifindex = index as i32;
+        let bound = unsafe {
+            libc::bind(
+                ring.socket.as_raw_fd(),
+                &address as *const libc::sockaddr_ll as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+            )
+        };
+        if bound < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        if let Some(group) = fanout {
+            let mode = PACKET_FANOUT_HASH | PACKET_FANOUT_FLAG_DEFRAG;
+            set_option(&ring.socket, PACKET_FANOUT, &(group as u32 | mode << 16))?;
+        }
+        Ok(ring)
+    }
+
+    /// It reads the next packet of the ring buffer, waiting for the kernel to hand a block over
+    /// for at most the capture timeout. The block of the previous packet is handed back to the
+    /// kernel once all its packets are read.
+    ///
+    /// Returns:
+    ///
+    /// The packet, or `TimeoutExpired` if none arrived in time.
+    pub fn next_packet(&mut self) -> Result<pcap::Packet<'_>, pcap::Error> {
+        loop {
+            match self.next {
+                Some((_, 0)) => {
+                    fence(Ordering::Release);
+                    unsafe {
+                        std::ptr::write_volatile(
+                            &mut (*self.block_header()).status,
+                            TP_STATUS_KERNEL,
+                        )
+                    };
+                    self.block = (self.block + 1) % BLOCK_COUNT;
+                    self.next = None;
+                }
+                Some((offset, left)) => {
+                    let packet = unsafe { self.block_start().add(offset) };
+                    let header = unsafe { &*(packet as *const PacketHeader) };
+
+                    self.next = Some((offset + header.next_offset as usize, left - 1));
+                    self.header = pcap::PacketHeader {
+                        ts: libc::timeval {
+                            tv_sec: header.sec as libc::time_t,
+                            tv_usec: (header.nsec / 1000) as libc::suseconds_t,
+                        },
+                        caplen: header.snaplen,
+                        len: header.len,
+                    };
+                    let data = unsafe {
+                        std::slice::from_raw_parts(
+                            packet.add(header.mac as usize),
+                            header.snaplen as usize,
+                        )
+                    };
+                    return Ok(pcap::Packet::new(&self.header, data));
+                }
+                None => {
+                    let block = self.block_header();
+                    let status = unsafe { std::ptr::read_volatile(&(*block).status) };
+                    if status & TP_STATUS_USER != 0 {
+                        fence(Ordering::Acquire);
+                        let (first, count) =
+                            unsafe { ((*block).first_packet, (*block).packet_count) };
+                        self.next = Some((first as usize, count));
+                        continue;
+                    }
+                    self.wait()?;
+                }
+            }
+        }
+    }
+
+    /// It reads the statistics of the socket and adds them to the ones of its fanout group.
+    ///
+    /// Returns:
+    ///
+    /// The statistics of the fanout group since its sockets were opened.
+    pub fn stats(&self) -> Result<pcap::Stat, pcap::Error> {
+        let mut stats = SocketStats::default();
+        let mut len = std::mem::size_of::<SocketStats>() as libc::socklen_t;
+        let read = unsafe {
+            libc::getsockopt(
+                self.socket.as_raw_fd(),
+                SOL_PACKET,
+                PACKET_STATISTICS,
+                &mut stats as *mut SocketStats as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if read < 0 {
+            return Err(pcap::Error::IoError(io::Error::last_os_error().kind()));
+        }
+
+        let counters = &self.counters;
+        let received = counters
+            .received
+            .fetch_add(stats.packets as u64, Ordering::Relaxed)
+            + stats.packets as u64;
+        let dropped = counters
+            .dropped
+            .fetch_add(stats.drops as u64, Ordering::Relaxed)
+            + stats.drops as u64;
+        Ok(pcap::Stat {
+            received: received as u32,
+            dropped: dropped as u32,
+            if_dropped: 0,
+        })
+    }
+
+    /// It waits for the kernel to hand a block over, for at most the capture timeout.
+    fn wait(&self) -> Result<(), pcap::Error> {
+        let mut poll = libc::pollfd {
+            fd: self.socket.as_raw_fd(),
+            events: libc::POLLIN | libc::POLLERR,
+            revents: 0,
+        };
+        match unsafe { libc::poll(&mut poll, 1, CAPTURE_TIMEOUT.as_millis() as libc::c_int) } {
+            0 => Err(pcap::Error::TimeoutExpired),
+            result if result < 0 => match io::Error::last_os_error() {
+                err if err.kind() == io::ErrorKind::Interrupted => Err(pcap::Error::TimeoutExpired),
+                err => Err(pcap::Error::IoError(err.kind())),
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// It returns the start of the block being read.
+    fn block_start(&self) -> *mut u8 {
+        unsafe { self.map.add(self.block * BLOCK_SIZE) }
+    }
+
+    /// It returns the header of the block being read.
+    fn block_header(&self) -> *mut BlockHeader {
+        self.block_start() as *mut BlockHeader
+    }
+}
+
+impl Drop for Ring {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.map as *mut libc::c_void, BLOCK_SIZE * BLOCK_COUNT) };
+    }
+}
+
+/// It sets an option of an AF_PACKET socket.
+///
+/// Arguments:
+///
+/// * `socket`: The socket.
+/// * `name`: The name of the option.
+/// * `value`: The value of the option.
+fn set_option<T>(socket: &OwnedFd, name: libc::c_int, value: &T) -> io::Result<()> {
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            SOL_PACKET,
+            name,
+            value as *const T as *const libc::c_void,
+            std::mem::size_of::<T>() as libc::socklen_t,
+        )
+    };
+    match result {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
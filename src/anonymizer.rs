@@ -0,0 +1,528 @@
+use crate::checksum;
+use crate::dissector::{dhcp, dns, Application};
+use crate::error::Error;
+use crate::packet::PacketInfo;
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockEncrypt, KeyInit};
+use aes::Aes128;
+use pnet::packet::arp::MutableArpPacket;
+use pnet::packet::ethernet::{EtherTypes, MutableEthernetPacket};
+use pnet::packet::ip::{IpNextHeaderProtocol, IpNextHeaderProtocols};
+use pnet::packet::ipv4::MutableIpv4Packet;
+use pnet::packet::ipv6::MutableIpv6Packet;
+use pnet::packet::MutablePacket;
+use pnet::util::MacAddr;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Maximum number of addresses remembered by the anonymizer. The cache is cleared once it is
+/// reached, which only costs computing the forgotten addresses again.
+const MAX_CACHED_ADDRESSES: usize = 65536;
+
+/// `Anonymizer` rewrites the IP and MAC addresses of packets with the prefix-preserving Crypto-PAn
+/// scheme: two addresses sharing a prefix of `n` bits are mapped to addresses sharing a prefix of
+/// `n` bits too, and the same key always gives the same mapping.
+///
+/// Broadcast, multicast, unspecified and loopback addresses are kept, since they do not identify
+/// hosts. The addresses carried by DHCP and DHCPv6 messages and by DNS address records are
+/// rewritten too, both in the parsed packets and in their content. The addresses carried by other
+/// application protocols, or by messages split across several segments, are kept.
+///
+/// Properties:
+///
+/// * `cipher`: The AES cipher keyed with the first half of the key.
+/// * `pad`: The second half of the key encrypted, filling the bits which are not part of the
+///   prefix of an address.
+/// * `ips`: The IP addresses already anonymized.
+/// * `macs`: The MAC addresses already anonymized.
+#[derive(Clone)]
+pub struct Anonymizer {
+    cipher: Aes128,
+    pad: u128,
+    ips: HashMap<IpAddr, IpAddr>,
+    macs: HashMap<MacAddr, MacAddr>,
+}
+
+impl Anonymizer {
+    /// It creates an anonymizer from a 32 bytes key.
+    ///
+    /// Arguments:
+    ///
+    /// * `key`: The key, the first 16 bytes keying the cipher and the last 16 bytes the pad.
+    ///
+    /// Returns:
+    ///
+    /// A new instance of the Anonymizer struct.
+    pub fn new(key: &[u8; 32]) -> Self {
+        let cipher = Aes128::new(GenericArray::from_slice(&key[..16]));
+        let mut pad = *GenericArray::from_slice(&key[16..]);
+        cipher.encrypt_block(&mut pad);
+
+        Anonymizer {
+            cipher,
+            pad: u128::from_be_bytes(pad.into()),
+            ips: HashMap::new(),
+            macs: HashMap::new(),
+        }
+    }
+
+    /// It creates an anonymizer from a file containing the key as 64 hexadecimal digits.
+    ///
+    /// Arguments:
+    ///
+    /// * `path`: The path of the key file.
+    ///
+    /// Returns:
+    ///
+    /// A new instance of the Anonymizer struct.
+    pub fn from_file(path: &str) -> Result<Self, Error> {
+        let data = std::fs::read_to_string(path)?;
+        let digits = data.trim();
+        let mut key = [0u8; 32];
+
+        let length = key.len() * 2;
+        let invalid = || {
+            Error::Config(format!(
+                "The anonymization key must be {} hex digits",
+                length
+            ))
+        };
+        if digits.len() != length || !digits.is_ascii() {
+            return Err(invalid());
+        }
+        for (byte, digits) in key.iter_mut().zip(digits.as_bytes().chunks(2)) {
+            let digits = std::str::from_utf8(digits).map_err(|_| invalid())?;
+            *byte = u8::from_str_radix(digits, 16).map_err(|_| invalid())?;
+        }
+        Ok(Self::new(&key))
+    }
+
+    /// It anonymizes an IP address.
+    ///
+    /// Arguments:
+    ///
+    /// * `ip`: The original address.
+    ///
+    /// Returns:
+    ///
+    /// The anonymized address.
+    pub fn ip(&mut self, ip: IpAddr) -> IpAddr {
+        let kept = match ip {
+            IpAddr::V4(ip) => ip.is_broadcast() || ip.is_unspecified(),
+            IpAddr::V6(ip) => ip.is_unspecified(),
+        };
+        if kept || ip.is_multicast() || ip.is_loopback() {
+            return ip;
+        }
+        if let Some(anonymized) = self.ips.get(&ip) {
+            return *anonymized;
+        }
+
+        let anonymized = match ip {
+            IpAddr::V4(ip) => {
+                let bits = (u32::from(ip) as u128) << 96;
+                IpAddr::V4(Ipv4Addr::from((self.anonymize(bits, 32) >> 96) as u32))
+            }
+            IpAddr::V6(ip) => IpAddr::V6(Ipv6Addr::from(self.anonymize(u128::from(ip), 128))),
+        };
+        if self.ips.len() >= MAX_CACHED_ADDRESSES {
+            self.ips.clear();
+        }
+        self.ips.insert(ip, anonymized);
+        anonymized
+    }
+
+    /// It anonymizes a MAC address. Unicast addresses stay unicast, so that the vendor prefix of
+    /// two devices from the same manufacturer is still shared.
+    ///
+    /// Arguments:
+    ///
+    /// * `mac`: The original address.
+    ///
+    /// Returns:
+    ///
+    /// The anonymized address.
+    pub fn mac(&mut self, mac: MacAddr) -> MacAddr {
+        // The group bit is set for broadcast and multicast addresses.
+        if mac.0 & 0x01 != 0 || mac.is_zero() {
+            return mac;
+        }
+        if let Some(anonymized) = self.macs.get(&mac) {
+            return *anonymized;
+        }
+
+        let octets = [mac.0, mac.1, mac.2, mac.3, mac.4, mac.5];
+        let mut bits = [0u8; 16];
+        bits[..6].copy_from_slice(&octets);
+        let bits = self.anonymize(u128::from_be_bytes(bits), 48).to_be_bytes();
+        let anonymized = MacAddr::new(bits[0] & !0x01, bits[1], bits[2], bits[3], bits[4], bits[5]);
+
+        if self.macs.len() >= MAX_CACHED_ADDRESSES {
+            self.macs.clear();
+        }
+        self.macs.insert(mac, anonymized);
+        anonymized
+    }
+
+    /// It anonymizes the addresses of a parsed packet, so that the information sent along the
+    /// packets matches their content.
+    ///
+    /// Arguments:
+    ///
+    /// * `info`: The parsed packet.
+    pub fn anonymize_info(&mut self, info: &mut PacketInfo) {
+        info.src_mac = self.mac(info.src_mac);
+        info.dst_mac = self.mac(info.dst_mac);
+        info.src_ip = info.src_ip.map(|ip| self.ip(ip));
+        info.dst_ip = info.dst_ip.map(|ip| self.ip(ip));
+
+        if let Some(ref mut arp) = info.arp {
+            arp.sender_hw = self.mac(arp.sender_hw);
+            arp.target_hw = self.mac(arp.target_hw);
+            arp.sender_ip = self.ipv4(arp.sender_ip);
+            arp.target_ip = self.ipv4(arp.target_ip);
+        }
+        if let Some(Application::Dhcp(ref mut dhcp)) = info.app {
+            dhcp.client_mac = dhcp.client_mac.map(|mac| self.mac(mac));
+            dhcp.requested_ip = dhcp.requested_ip.map(|ip| self.ip(ip));
+            dhcp.assigned_ip = dhcp.assigned_ip.map(|ip| self.ip(ip));
+        }
+        if let Some(Application::Dns(ref mut dns)) = info.app {
+            for answer in &mut dns.answers {
+                if let Ok(ip) = answer.data.parse::<IpAddr>() {
+                    answer.data = self.ip(ip).to_string();
+                }
+            }
+        }
+    }
+
+    /// It rewrites the addresses of the Ethernet, IP and ARP headers of a frame, and the ones
+    /// carried by its DHCP, DHCPv6 and DNS messages, and updates the checksums covering them.
+    /// Headers cut by the capture are left untouched.
+    ///
+    /// Arguments:
+    ///
+    /// * `frame`: The Ethernet frame to anonymize.
+    pub fn anonymize_packet(&mut self, frame: &mut [u8]) {
+        let Some(mut ethernet) = MutableEthernetPacket::new(frame) else {
+            return;
+        };
+        let src = self.mac(ethernet.get_source());
+        let dst = self.mac(ethernet.get_destination());
+        ethernet.set_source(src);
+        ethernet.set_destination(dst);
+
+        match ethernet.get_ethertype() {
+            EtherTypes::Ipv4 => self.anonymize_ipv4(ethernet.payload_mut()),
+            EtherTypes::Ipv6 => self.anonymize_ipv6(ethernet.payload_mut()),
+            EtherTypes::Arp => self.anonymize_arp(ethernet.payload_mut()),
+            _ => {}
+        }
+    }
+
+    fn anonymize_ipv4(&mut self, packet: &mut [u8]) {
+        let Some(mut ipv4) = MutableIpv4Packet::new(packet) else {
+            return;
+        };
+        let old = [ipv4.get_source(), ipv4.get_destination()];
+        let new = old.map(|ip| self.ipv4(ip));
+        ipv4.set_source(new[0]);
+        ipv4.set_destination(new[1]);
+
+        let old: Vec<u8> = old.iter().flat_map(|ip| ip.octets()).collect();
+        let new: Vec<u8> = new.iter().flat_map(|ip| ip.octets()).collect();
+        ipv4.set_checksum(checksum::update(ipv4.get_checksum(), &old, &new));
+
+        // Only the first fragment of a packet carries the transport header.
+        let protocol = ipv4.get_next_level_protocol();
+        let header_len = ipv4.get_header_length() as usize * 4;
+        if ipv4.get_fragment_offset() == 0 {
+            if let Some(segment) = packet.get_mut(header_len..) {
+                checksum::update_transport(protocol, segment, &old, &new);
+                self.anonymize_payload(protocol, segment);
+            }
+        }
+    }
+
+    fn anonymize_ipv6(&mut self, packet: &mut [u8]) {
+        let Some(mut ipv6) = MutableIpv6Packet::new(packet) else {
+            return;
+        };
+        let old = [ipv6.get_source(), ipv6.get_destination()];
+        let new = old.map(|ip| match self.ip(IpAddr::V6(ip)) {
+            IpAddr::V6(ip) => ip,
+            IpAddr::V4(_) => ip,
+        });
+        ipv6.set_source(new[0]);
+        ipv6.set_destination(new[1]);
+
+        let old: Vec<u8> = old.iter().flat_map(|ip| ip.octets()).collect();
+        let new: Vec<u8> = new.iter().flat_map(|ip| ip.octets()).collect();
+        let protocol = ipv6.get_next_header();
+        let header_len = MutableIpv6Packet::minimum_packet_size();
+        if let Some(segment) = packet.get_mut(header_len..) {
+            checksum::update_transport(protocol, segment, &old, &new);
+            self.anonymize_payload(protocol, segment);
+        }
+    }
+
+    /// It rewrites the addresses carried by the DHCP, DHCPv6 or DNS message of a TCP or UDP
+    /// segment, and updates its checksum.
+    ///
+    /// Arguments:
+    ///
+    /// * `protocol`: The protocol of the IP payload.
+    /// * `segment`: The IP payload.
+    fn anonymize_payload(&mut self, protocol: IpNextHeaderProtocol, segment: &mut [u8]) {
+        let (header_len, checksum_offset) = match protocol {
+            IpNextHeaderProtocols::Udp => (8, 6),
+            IpNextHeaderProtocols::Tcp => match segment.get(12) {
+                Some(offset) if offset >> 4 >= 5 => ((offset >> 4) as usize * 4, 16),
+                _ => return,
+            },
+            _ => return,
+        };
+        if segment.len() < header_len {
+            return;
+        }
+        let port = |offset: usize| u16::from_be_bytes([segment[offset], segment[offset + 1]]);
+        let (src, dst) = (port(0), port(2));
+
+        let payload = &segment[header_len..];
+        let udp = protocol == IpNextHeaderProtocols::Udp;
+        let ranges = if udp && dhcp::PORTS.contains(&src) && dhcp::PORTS.contains(&dst) {
+            dhcp::address_ranges(payload)
+        } else if udp && dhcp::PORTS_V6.contains(&src) && dhcp::PORTS_V6.contains(&dst) {
+            dhcp::address_ranges_v6(payload)
+        } else if dns::PORTS.contains(&src) || dns::PORTS.contains(&dst) {
+            match udp {
+                true => dns::address_ranges(payload),
+                // DNS messages carried by TCP are prefixed with their length.
+                false => dns::address_ranges(payload.get(2..).unwrap_or_default())
+                    .into_iter()
+                    .map(|range| range.start + 2..range.end + 2)
+                    .collect(),
+            }
+        } else {
+            return;
+        };
+
+        let field = checksum_offset..checksum_offset + 2;
+        let mut sum = u16::from_be_bytes([segment[field.start], segment[field.start + 1]]);
+        for range in ranges {
+            let (start, end) = (header_len + range.start, header_len + range.end);
+            let new = match end - start {
+                4 => {
+                    let octets: [u8; 4] = segment[start..end].try_into().unwrap();
+                    self.ipv4(octets.into()).octets().to_vec()
+                }
+                16 => {
+                    let octets: [u8; 16] = segment[start..end].try_into().unwrap();
+                    match self.ip(IpAddr::V6(octets.into())) {
+                        IpAddr::V6(ip) => ip.octets().to_vec(),
+                        IpAddr::V4(_) => continue,
+                    }
+                }
+                6 => {
+                    let octets = &segment[start..end];
+                    let mac = MacAddr::new(
+                        octets[0], octets[1], octets[2], octets[3], octets[4], octets[5],
+                    );
+                    let mac = self.mac(mac);
+                    vec![mac.0, mac.1, mac.2, mac.3, mac.4, mac.5]
+                }
+                _ => continue,
+            };
+
+            // The checksum is updated over the whole 16 bits words holding the address.
+            let words = start & !1..((end + 1) & !1).min(segment.len());
+            let old = segment[words.clone()].to_vec();
+            segment[start..end].copy_from_slice(&new);
+            sum = checksum::update(sum, &old, &segment[words]);
+        }
+
+        // A null UDP checksum was not computed, and a computed one is never sent as zero.
+        let checksum = u16::from_be_bytes([segment[field.start], segment[field.start + 1]]);
+        if udp && checksum == 0 {
+            return;
+        }
+        let sum = match sum {
+            0 if udp => 0xffff,
+            sum => sum,
+        };
+        segment[field].copy_from_slice(&sum.to_be_bytes());
+    }
+
+    fn anonymize_arp(&mut self, packet: &mut [u8]) {
+        let Some(mut arp) = MutableArpPacket::new(packet) else {
+            return;
+        };
+        let sender_hw = self.mac(arp.get_sender_hw_addr());
+        let target_hw = self.mac(arp.get_target_hw_addr());
+        let sender_ip = self.ipv4(arp.get_sender_proto_addr());
+        let target_ip = self.ipv4(arp.get_target_proto_addr());
+
+        arp.set_sender_hw_addr(sender_hw);
+        arp.set_target_hw_addr(target_hw);
+        arp.set_sender_proto_addr(sender_ip);
+        arp.set_target_proto_addr(target_ip);
+    }
+
+    /// It anonymizes an IPv4 address.
+    fn ipv4(&mut self, ip: Ipv4Addr) -> Ipv4Addr {
+        match self.ip(IpAddr::V4(ip)) {
+            IpAddr::V4(ip) => ip,
+            IpAddr::V6(_) => ip,
+        }
+    }
+
+    /// It applies Crypto-PAn to the first bits of a value: each bit of the result is flipped
+    /// according to the encryption of the bits preceding it, completed by the pad.
+    ///
+    /// Arguments:
+    ///
+    /// * `bits`: The address, aligned on the most significant bit.
+    /// * `len`: The number of bits of the address.
+    ///
+    /// Returns:
+    ///
+    /// The anonymized address, aligned on the most significant bit.
+    fn anonymize(&self, bits: u128, len: u32) -> u128 {
+        let mut flips = 0u128;
+
+        for position in 0..len {
+            let prefix = u128::MAX.checked_shl(128 - position).unwrap_or(0);
+            let mut block =
+                GenericArray::from(((bits & prefix) | (self.pad & !prefix)).to_be_bytes());
+            self.cipher.encrypt_block(&mut block);
+            flips |= ((block[0] >> 7) as u128) << (127 - position);
+        }
+        bits ^ flips
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::PacketInfo;
+    use pnet::packet::ethernet::EthernetPacket;
+    use pnet::packet::ipv4::Ipv4Packet;
+    use pnet::packet::udp::{self, UdpPacket};
+    use pnet::packet::Packet;
+
+    /// The key of the sample of the reference implementation of Crypto-PAn.
+    const KEY: &[u8; 32] = &[
+        21, 34, 23, 141, 51, 164, 207, 128, 19, 10, 91, 22, 73, 144, 125, 16, 216, 152, 143, 131,
+        121, 121, 101, 39, 98, 87, 76, 45, 42, 132, 34, 2,
+    ];
+
+    fn ipv4(anonymizer: &mut Anonymizer, ip: &str) -> String {
+        anonymizer.ip(ip.parse().unwrap()).to_string()
+    }
+
+    /// It builds an IPv4 UDP frame with a computed checksum.
+    fn udp_frame(src_port: u16, dst_port: u16, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0x02, 0, 0, 0, 0, 0x02, 0x02, 0, 0, 0, 0, 0x01, 0x08, 0x00];
+        frame.extend_from_slice(&[0x45, 0, 0, 0, 0, 1, 0, 0, 64, 17, 0, 0]);
+        frame.extend_from_slice(&[192, 168, 1, 10, 192, 168, 1, 1]);
+        frame[16..18].copy_from_slice(&((28 + payload.len()) as u16).to_be_bytes());
+        frame.extend_from_slice(&src_port.to_be_bytes());
+        frame.extend_from_slice(&dst_port.to_be_bytes());
+        frame.extend_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+        frame.extend_from_slice(&[0, 1]);
+        frame.extend_from_slice(payload);
+        checksum::fix_checksums(&mut frame);
+        frame
+    }
+
+    fn udp_checksum_is_valid(frame: &[u8]) -> bool {
+        let ethernet = EthernetPacket::new(frame).unwrap();
+        let ip = Ipv4Packet::new(ethernet.payload()).unwrap();
+        let datagram = UdpPacket::new(ip.payload()).unwrap();
+
+        datagram.get_checksum()
+            == udp::ipv4_checksum(&datagram, &ip.get_source(), &ip.get_destination())
+    }
+
+    #[test]
+    fn matches_the_reference_implementation() {
+        let mut anonymizer = Anonymizer::new(KEY);
+
+        assert_eq!(ipv4(&mut anonymizer, "128.11.68.132"), "135.242.180.132");
+        assert_eq!(ipv4(&mut anonymizer, "129.118.74.4"), "134.136.186.123");
+        assert_eq!(ipv4(&mut anonymizer, "130.132.252.244"), "133.68.164.234");
+        assert_eq!(ipv4(&mut anonymizer, "141.223.7.43"), "141.167.8.160");
+    }
+
+    #[test]
+    fn preserves_prefixes() {
+        let mut anonymizer = Anonymizer::new(KEY);
+        let a = anonymizer.ip("10.1.2.3".parse().unwrap());
+        let b = anonymizer.ip("10.1.200.3".parse().unwrap());
+        let (IpAddr::V4(a), IpAddr::V4(b)) = (a, b) else {
+            panic!("IPv4 addresses must stay IPv4 addresses");
+        };
+
+        // The addresses share their first 16 bits, and differ on the 17th.
+        let common = (u32::from(a) ^ u32::from(b)).leading_zeros();
+        assert_eq!(common, 16);
+        assert_eq!(ipv4(&mut anonymizer, "255.255.255.255"), "255.255.255.255");
+        assert_eq!(ipv4(&mut anonymizer, "224.0.0.251"), "224.0.0.251");
+    }
+
+    #[test]
+    fn rewrites_dns_address_records() {
+        let mut message = vec![0x12, 0x34, 0x81, 0x80, 0, 1, 0, 1, 0, 0, 0, 0];
+        message.extend_from_slice(b"\x04host\x03lan\x00\x00\x01\x00\x01");
+        message.extend_from_slice(&[0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 192, 168, 1, 10]);
+        let mut frame = udp_frame(53, 40000, &message);
+        let mut info = PacketInfo::from(&frame).unwrap();
+        let mut anonymizer = Anonymizer::new(KEY);
+
+        anonymizer.anonymize_info(&mut info);
+        anonymizer.anonymize_packet(&mut frame);
+        let anonymized = ipv4(&mut anonymizer, "192.168.1.10");
+
+        let Some(Application::Dns(ref dns)) = info.app else {
+            panic!("The packet must be a DNS message");
+        };
+        assert_eq!(dns.answers[0].data, anonymized);
+        let record = &frame[frame.len() - 4..];
+        assert_eq!(
+            Ipv4Addr::new(record[0], record[1], record[2], record[3]).to_string(),
+            anonymized
+        );
+        assert!(udp_checksum_is_valid(&frame));
+    }
+
+    #[test]
+    fn rewrites_dhcp_addresses() {
+        let mut message = vec![0; 240];
+        message[..4].copy_from_slice(&[2, 1, 6, 0]);
+        message[16..20].copy_from_slice(&[192, 168, 1, 10]);
+        message[28..34].copy_from_slice(&[0x3c, 0x22, 0xfb, 0x01, 0x02, 0x03]);
+        message[236..240].copy_from_slice(&[0x63, 0x82, 0x53, 0x63]);
+        message.extend_from_slice(&[53, 1, 5, 54, 4, 192, 168, 1, 1, 255]);
+        let mut frame = udp_frame(67, 68, &message);
+        let mut info = PacketInfo::from(&frame).unwrap();
+        let mut anonymizer = Anonymizer::new(KEY);
+
+        anonymizer.anonymize_info(&mut info);
+        anonymizer.anonymize_packet(&mut frame);
+
+        let Some(Application::Dhcp(ref dhcp)) = info.app else {
+            panic!("The packet must be a DHCP message");
+        };
+        let payload = &frame[14 + 20 + 8..];
+        assert_eq!(
+            dhcp.assigned_ip,
+            Some(IpAddr::from(<[u8; 4]>::try_from(&payload[16..20]).unwrap()))
+        );
+        let mac = &payload[28..34];
+        let mac = MacAddr::new(mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]);
+        assert_eq!(dhcp.client_mac, Some(mac));
+        assert_ne!(payload[16..20], [192, 168, 1, 10]);
+        assert_ne!(payload[245..249], [192, 168, 1, 1]);
+        assert!(udp_checksum_is_valid(&frame));
+    }
+}
@@ -1,4 +1,9 @@
+use chrono::{DateTime, Local};
 use clap::{Parser, ValueEnum};
+use sniffer::{
+    parse_time, Backend, Blacklist, ExportProtocol, OutputFormat, RedactMode, StreamMode,
+    EXIT_CODES,
+};
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
 pub enum LogLevel {
@@ -8,36 +13,212 @@ pub enum LogLevel {
     Error,
 }
 
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
 #[derive(Parser, Debug)]
-#[clap(version, about, long_about = None)]
-/// This struct is the result of the deserialization of the CLI arguments.
+#[clap(version, about, long_about = None, after_help = EXIT_CODES)]
+/// This struct is the result of the deserialization of the CLI arguments, of the environment
+/// variables and of the configuration file.
 pub struct Args {
+    /// Read the options from a TOML configuration file, under the options given on the command
+    /// line or by environment variables
+    #[clap(long, value_parser, env = "SNIFFER_CONFIG")]
+    pub config: Option<String>,
+
     /// URL of the grpc server to send the pcap data stream
-    #[clap(short, long, value_parser)]
+    #[clap(short, long, value_parser, env = "SNIFFER_URL")]
     pub url: Option<String>,
 
+    /// Certificate authority used to check the certificate of the grpc server, as a PEM file
+    #[clap(long, value_parser, env = "SNIFFER_TLS_CA")]
+    pub tls_ca: Option<String>,
+
+    /// Client certificate sent to the grpc server, as a PEM file, given with its key
+    #[clap(long, value_parser, env = "SNIFFER_TLS_CERT")]
+    pub tls_cert: Option<String>,
+
+    /// Private key of the client certificate, as a PEM file, given with the certificate
+    #[clap(long, value_parser, env = "SNIFFER_TLS_KEY")]
+    pub tls_key: Option<String>,
+
+    /// Name expected in the certificate of the grpc server, instead of the host of its URL
+    #[clap(long, value_parser, env = "SNIFFER_TLS_DOMAIN")]
+    pub tls_domain: Option<String>,
+
+    /// Send the raw packets, their parsed metadata or both to the grpc server
+    #[arg(value_enum)]
+    #[clap(long, value_parser, default_value_t = StreamMode::Raw, env = "SNIFFER_STREAM_MODE")]
+    pub stream_mode: StreamMode,
+
     /// Read packets from a pcap file instead of a network interface
-    #[clap(short, long, value_parser)]
+    #[clap(short, long, value_parser, env = "SNIFFER_READ")]
     pub read: Option<String>,
 
     /// Write captured packets on a pcap file
-    #[clap(short, long, value_parser)]
+    #[clap(short, long, value_parser, env = "SNIFFER_WRITE")]
     pub write: Option<String>,
 
+    /// Write a description of each packet on this file, or on the standard output with "-"
+    #[clap(short, long, value_parser, env = "SNIFFER_OUTPUT")]
+    pub output: Option<String>,
+
+    /// Set the format of the packet descriptions
+    #[arg(value_enum)]
+    #[clap(long, value_parser, default_value_t = OutputFormat::Text, env = "SNIFFER_OUTPUT_FORMAT")]
+    pub output_format: OutputFormat,
+
+    /// Number of threads parsing, filtering, redacting and anonymizing packets, at most 4 by
+    /// default
+    #[clap(long, value_parser, env = "SNIFFER_WORKERS")]
+    pub workers: Option<usize>,
+
+    /// Show the packets, the top talkers, the protocols and the rates in a terminal UI, from which
+    /// the blacklist can be edited
+    #[clap(long, value_parser, env = "SNIFFER_TUI")]
+    pub tui: bool,
+
+    /// Dump the bytes of each packet in hex and ASCII under its description and its debug log
+    /// line, as saved and forwarded
+    #[clap(long, value_parser, env = "SNIFFER_HEXDUMP")]
+    pub hexdump: bool,
+
+    /// Only dump the transport layer payload of the packets, with --hexdump
+    #[clap(long, value_parser, env = "SNIFFER_HEXDUMP_PAYLOAD")]
+    pub hexdump_payload: bool,
+
+    /// Dump at most this number of bytes of each packet, with --hexdump
+    #[clap(long, value_parser, env = "SNIFFER_HEXDUMP_LIMIT")]
+    pub hexdump_limit: Option<usize>,
+
+    /// Stop after capturing this number of packets
+    #[clap(short, long, value_parser, env = "SNIFFER_COUNT")]
+    pub count: Option<u64>,
+
+    /// Stop after capturing for this number of seconds
+    #[clap(long, value_parser, env = "SNIFFER_DURATION")]
+    pub duration: Option<u64>,
+
+    /// Stop before capturing more than this number of bytes on the wire
+    #[clap(long, value_parser, env = "SNIFFER_MAX_BYTES")]
+    pub max_bytes: Option<u64>,
+
+    /// Stop at this time, such as "2023-06-01 18:30:00" or "18:30"
+    #[clap(long, value_parser = parse_time, env = "SNIFFER_UNTIL")]
+    pub until: Option<DateTime<Local>>,
+
+    /// Remove the payload of saved and forwarded packets, keeping their headers
+    #[arg(value_enum)]
+    #[clap(long, value_parser, env = "SNIFFER_REDACT")]
+    pub redact: Option<RedactMode>,
+
+    /// Keep at most this number of bytes of saved and forwarded packets
+    #[clap(long, value_parser, env = "SNIFFER_SNAPLEN")]
+    pub snaplen: Option<usize>,
+
+    /// Anonymize the IP and MAC addresses of saved and forwarded packets with the key of this
+    /// file, written as 64 hex digits
+    #[clap(long, value_parser, env = "SNIFFER_ANONYMIZE_KEY")]
+    pub anonymize_key: Option<String>,
+
+    /// Log a summary of the capture every this number of seconds, on top of the one logged when
+    /// the capture ends
+    #[clap(long, value_parser, env = "SNIFFER_STATS_INTERVAL")]
+    pub stats_interval: Option<u64>,
+
+    /// Serve Prometheus metrics on http://<address>/metrics
+    #[clap(long, value_parser, env = "SNIFFER_METRICS")]
+    pub metrics: Option<String>,
+
     /// Set the verbosity level
     #[arg(value_enum)]
-    #[clap(short, long, value_parser, default_value_t = LogLevel::Info)]
+    #[clap(short, long, value_parser, default_value_t = LogLevel::Info, env = "SNIFFER_VERBOSITY")]
     pub verbosity: LogLevel,
 
+    /// Set the format of the logs, on the standard output and in the log file
+    #[arg(value_enum)]
+    #[clap(long, value_parser, default_value_t = LogFormat::Text, env = "SNIFFER_LOG_FORMAT")]
+    pub log_format: LogFormat,
+
     /// If set, the logs will be save on the provided file
-    #[clap(short, long, value_parser)]
+    #[clap(short, long, value_parser, env = "SNIFFER_LOGFILE")]
     pub logfile: Option<String>,
 
+    /// Rotate the log file once it reaches this number of bytes
+    #[clap(long, value_parser, env = "SNIFFER_LOG_MAX_SIZE")]
+    pub log_max_size: Option<u64>,
+
+    /// Rotate the log file every this number of seconds
+    #[clap(long, value_parser, env = "SNIFFER_LOG_ROTATE_INTERVAL")]
+    pub log_rotate_interval: Option<u64>,
+
+    /// Number of rotated log files kept
+    #[clap(long, value_parser, default_value_t = 5, env = "SNIFFER_LOG_KEEP")]
+    pub log_keep: usize,
+
     /// Use a specific network interface instead of the default one
-    #[clap(short, long, value_parser)]
+    #[clap(short, long, value_parser, env = "SNIFFER_INTERFACE")]
     pub interface: Option<String>,
 
+    /// Capture the network interface with libpcap, or with Linux AF_PACKET ring buffers when the
+    /// sniffer is built with the af-packet feature
+    #[arg(value_enum)]
+    #[clap(long, value_parser, default_value_t = Backend::Pcap, env = "SNIFFER_BACKEND")]
+    pub backend: Backend,
+
+    /// Number of AF_PACKET sockets sharing the network interface, each read by its own capture
+    /// thread
+    #[clap(long, value_parser, default_value_t = 1, env = "SNIFFER_FANOUT")]
+    pub fanout: usize,
+
+    /// Identifier of the AF_PACKET fanout group, shared with other processes capturing the same
+    /// network interface, the process identifier by default
+    #[clap(long, value_parser, env = "SNIFFER_FANOUT_GROUP")]
+    pub fanout_group: Option<u16>,
+
     /// Use a blacklist configuration file to filter packets
-    #[clap(short, long, value_parser)]
+    #[clap(short, long, value_parser, env = "SNIFFER_BLACKLIST")]
     pub blacklist: Option<String>,
+
+    /// Blacklist given inline in the configuration file
+    #[clap(skip)]
+    pub inline_blacklist: Option<Blacklist>,
+
+    /// Group packets into bidirectional flows and log a record when a flow expires
+    #[clap(long, value_parser, env = "SNIFFER_FLOWS")]
+    pub flows: bool,
+
+    /// Seconds without packets after which a flow expires
+    #[clap(
+        long,
+        value_parser,
+        default_value_t = 15,
+        env = "SNIFFER_FLOW_IDLE_TIMEOUT"
+    )]
+    pub flow_idle_timeout: u64,
+
+    /// Seconds after which a flow still receiving packets is reported
+    #[clap(
+        long,
+        value_parser,
+        default_value_t = 1800,
+        env = "SNIFFER_FLOW_ACTIVE_TIMEOUT"
+    )]
+    pub flow_active_timeout: u64,
+
+    /// Maximum number of flows tracked at once, the least recently seen ending first
+    #[clap(long, value_parser, default_value_t = 65536, env = "SNIFFER_FLOW_MAX")]
+    pub flow_max: usize,
+
+    /// Export expired flows to the collector at this host:port address, enabling flow tracking
+    #[clap(long, value_parser, env = "SNIFFER_EXPORT")]
+    pub export: Option<String>,
+
+    /// Set the protocol used to export flows
+    #[arg(value_enum)]
+    #[clap(long, value_parser, default_value_t = ExportProtocol::Ipfix, env = "SNIFFER_EXPORT_PROTOCOL")]
+    pub export_protocol: ExportProtocol,
 }
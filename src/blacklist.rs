@@ -0,0 +1,608 @@
+use crate::dissector::Application;
+use crate::packet::PacketInfo;
+use pnet::packet::tcp::TcpFlags;
+use pnet::util::MacAddr;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+/// Maximum number of connections remembered as matching a host rule. Once it is reached, the
+/// connections idle for too long are forgotten, then the least recently seen ones.
+const MAX_BLOCKED_CONNECTIONS: usize = 65536;
+
+/// Time without packets after which a connection matching a host rule is forgotten, in packet
+/// time, so that its endpoints can be reused by another connection.
+const BLOCKED_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Both endpoints of a connection, the lowest first so that both directions share the same key.
+type Connection = ((IpAddr, u16), (IpAddr, u16));
+
+/// `Blacklist` is the result of the deserialization of the blacklist configuration file.
+///
+/// Properties:
+///
+/// * `from`: The MAC addresses, IP addresses and `ip:port` pairs ignored as packet sources.
+/// * `into`: The MAC addresses, IP addresses and `ip:port` pairs ignored as packet destinations.
+/// * `domains`: The domain names whose DNS messages are ignored, `*.` matching any subdomain.
+/// * `hosts`: The HTTP hosts and TLS server names whose requests are ignored, `*.` matching any
+///   subdomain. The following packets of a matching connection are ignored as well.
+/// * `from_macs`: The MAC addresses of `from`, parsed once, with their rule.
+/// * `into_macs`: The MAC addresses of `into`, parsed once, with their rule.
+/// * `from_addresses`: The IP addresses and `ip:port` pairs of `from`, parsed once, with their
+///   rule.
+/// * `into_addresses`: The IP addresses and `ip:port` pairs of `into`, parsed once, with their
+///   rule.
+/// * `domain_names`: The names of `domains`, lowercased and without their trailing dot, with their
+///   rule.
+/// * `host_names`: The names of `hosts`, lowercased and without their trailing dot, with their
+///   rule.
+/// * `version`: The number of edits of the rules, telling the connection caches to forget the
+///   connections of the host rules removed.
+#[derive(serde::Deserialize, Clone, Debug, Default)]
+#[serde(from = "Rules")]
+pub struct Blacklist {
+    from: Vec<String>,
+    into: Vec<String>,
+    domains: Vec<String>,
+    hosts: Vec<String>,
+    from_macs: Vec<(MacAddr, String)>,
+    into_macs: Vec<(MacAddr, String)>,
+    from_addresses: Vec<(IpAddr, Option<u16>, String)>,
+    into_addresses: Vec<(IpAddr, Option<u16>, String)>,
+    domain_names: Vec<(String, String)>,
+    host_names: Vec<(String, String)>,
+    version: u64,
+}
+
+/// `Connections` remembers the connections whose HTTP host or TLS server name matched a rule, so
+/// that their following packets are ignored as well. Each worker of a pipeline has its own, the
+/// packets of a connection all going to the same worker.
+///
+/// Properties:
+///
+/// * `blocked`: The connections which matched a host rule, with the rule.
+/// * `version`: The version of the blacklist the connections were checked against.
+#[derive(Clone, Debug, Default)]
+pub struct Connections {
+    blocked: HashMap<Connection, Blocked>,
+    version: u64,
+}
+
+/// `Rules` is the blacklist configuration file, as written, from which a `Blacklist` is loaded.
+///
+/// Properties:
+///
+/// * `from`: The MAC addresses, IP addresses and `ip:port` pairs ignored as packet sources.
+/// * `into`: The MAC addresses, IP addresses and `ip:port` pairs ignored as packet destinations.
+/// * `domains`: The domain names whose DNS messages are ignored.
+/// * `hosts`: The HTTP hosts and TLS server names whose requests are ignored.
+#[derive(serde::Deserialize)]
+struct Rules {
+    from: Vec<String>,
+    into: Vec<String>,
+    #[serde(default)]
+    domains: Vec<String>,
+    #[serde(default)]
+    hosts: Vec<String>,
+}
+
+/// `Blocked` is a connection whose HTTP host or TLS server name matched a rule.
+///
+/// Properties:
+///
+/// * `rule`: The rule matched.
+/// * `last_seen`: The timestamp of the last packet of the connection, since the UNIX epoch.
+#[derive(Clone, Debug)]
+struct Blocked {
+    rule: String,
+    last_seen: Duration,
+}
+
+impl From<Rules> for Blacklist {
+    fn from(rules: Rules) -> Self {
+        let mut blacklist = Blacklist {
+            from: rules.from,
+            into: rules.into,
+            domains: rules.domains,
+            hosts: rules.hosts,
+            ..Default::default()
+        };
+        blacklist.parse_rules();
+        blacklist
+    }
+}
+
+impl Blacklist {
+    /// It checks if a packet matches one of the rules of the blacklist.
+    ///
+    /// Arguments:
+    ///
+    /// * `info`: The parsed packet.
+    /// * `connections`: The connections which matched a host rule earlier, updated with the one
+    ///   of the packet if it matches one.
+    /// * `ts`: The timestamp of the packet, since the UNIX epoch.
+    ///
+    /// Returns:
+    ///
+    /// The rule matched, such as `from 192.168.1.20`, or `None` if the packet must be kept.
+    pub fn contains(
+        &self,
+        info: &PacketInfo,
+        connections: &mut Connections,
+        ts: Duration,
+    ) -> Option<String> {
+        if connections.version != self.version {
+            connections.version = self.version;
+            connections.blocked.retain(|_, blocked| {
+                let value = blocked.rule.strip_prefix("host ");
+                self.hosts.iter().any(|host| Some(host.as_str()) == value)
+            });
+        }
+        let connection = Self::connection(info);
+        if let Some(rule) =
+            connection.and_then(|connection| connections.blocked_rule(connection, info, ts))
+        {
+            return Some(rule);
+        }
+        if let Some(rule) = Self::contains_mac(&self.from_macs, info.src_mac) {
+            return Some(format!("from {}", rule));
+        }
+        if let Some(rule) = Self::contains_mac(&self.into_macs, info.dst_mac) {
+            return Some(format!("into {}", rule));
+        }
+        if let Some(rule) = Self::contains_address(&self.from_addresses, info.src_ip, info.src_port)
+        {
+            return Some(format!("from {}", rule));
+        }
+        if let Some(rule) = Self::contains_address(&self.into_addresses, info.dst_ip, info.dst_port)
+        {
+            return Some(format!("into {}", rule));
+        }
+        match info.app {
+            Some(Application::Dns(ref dns)) => dns
+                .names()
+                .find_map(|name| Self::contains_domain(&self.domain_names, name))
+                .map(|rule| format!("domain {}", rule)),
+            Some(ref app) => {
+                let host = app.host()?;
+                let rule = format!("host {}", Self::contains_domain(&self.host_names, host)?);
+
+                if let Some(connection) = connection {
+                    if connections.blocked.len() >= MAX_BLOCKED_CONNECTIONS {
+                        connections.forget(ts);
+                    }
+                    let blocked = Blocked {
+                        rule: rule.clone(),
+                        last_seen: ts,
+                    };
+                    connections.blocked.insert(connection, blocked);
+                }
+                Some(rule)
+            }
+            None => None,
+        }
+    }
+
+    /// It lists the rules of the blacklist, labelled the way `contains` reports them, such as
+    /// `from 192.168.1.20` or `host *.example.com`.
+    pub fn rules(&self) -> Vec<String> {
+        let lists = [
+            ("from", &self.from),
+            ("into", &self.into),
+            ("domain", &self.domains),
+            ("host", &self.hosts),
+        ];
+        lists
+            .iter()
+            .flat_map(|(kind, rules)| rules.iter().map(move |rule| format!("{} {}", kind, rule)))
+            .collect()
+    }
+
+    /// It adds a rule to the blacklist.
+    ///
+    /// Arguments:
+    ///
+    /// * `rule`: The rule, such as `from 192.168.1.20`, `into 10.0.0.1:53`, `domain *.example.com`
+    ///   or `host example.com`.
+    pub fn add(&mut self, rule: &str) -> Result<(), String> {
+        let (rules, value) = self.rules_of(rule)?;
+
+        if !rules.contains(&value) {
+            rules.push(value);
+        }
+        self.parse_rules();
+        self.version += 1;
+        Ok(())
+    }
+
+    /// It removes a rule from the blacklist, letting the connections it blocked through again once
+    /// their cache notices it.
+    ///
+    /// Arguments:
+    ///
+    /// * `rule`: The rule, as listed by `rules`.
+    pub fn remove(&mut self, rule: &str) -> Result<(), String> {
+        let (rules, value) = self.rules_of(rule)?;
+
+        rules.retain(|rule| *rule != value);
+        self.parse_rules();
+        self.version += 1;
+        Ok(())
+    }
+
+    /// It parses the addresses of the `from` and `into` rules and normalizes the names of the
+    /// `domain` and `host` rules, once the rules are loaded or edited, instead of for each packet.
+    fn parse_rules(&mut self) {
+        let macs = |rules: &[String]| {
+            rules
+                .iter()
+                .filter_map(|rule| Some((rule.parse::<MacAddr>().ok()?, rule.clone())))
+                .collect()
+        };
+        let addresses = |rules: &[String]| {
+            rules
+                .iter()
+                .filter_map(|rule| match rule.parse::<IpAddr>() {
+                    Ok(ip) => Some((ip, None, rule.clone())),
+                    Err(_) => {
+                        let endpoint = rule.parse::<SocketAddr>().ok()?;
+                        Some((endpoint.ip(), Some(endpoint.port()), rule.clone()))
+                    }
+                })
+                .collect()
+        };
+        let names = |rules: &[String]| {
+            rules
+                .iter()
+                .map(|rule| {
+                    let name = rule.trim_end_matches('.').to_ascii_lowercase();
+                    (name, rule.clone())
+                })
+                .collect()
+        };
+        self.from_macs = macs(&self.from);
+        self.into_macs = macs(&self.into);
+        self.from_addresses = addresses(&self.from);
+        self.into_addresses = addresses(&self.into);
+        self.domain_names = names(&self.domains);
+        self.host_names = names(&self.hosts);
+    }
+
+    /// It finds the list a rule belongs to from its kind, and the value of the rule.
+    fn rules_of(&mut self, rule: &str) -> Result<(&mut Vec<String>, String), String> {
+        let invalid = || format!("Invalid rule: {}", rule);
+        let (kind, value) = rule.trim().split_once(' ').ok_or_else(invalid)?;
+        let value = value.trim();
+        if value.is_empty() {
+            return Err(invalid());
+        }
+
+        let rules = match kind {
+            "from" => &mut self.from,
+            "into" => &mut self.into,
+            "domain" => &mut self.domains,
+            "host" => &mut self.hosts,
+            _ => return Err(format!("Unknown rule kind: {}", kind)),
+        };
+        Ok((rules, value.to_string()))
+    }
+
+    /// It returns the endpoints of the connection a packet belongs to, if it has ports.
+    fn connection(info: &PacketInfo) -> Option<Connection> {
+        let src = (info.src_ip?, info.src_port?);
+        let dst = (info.dst_ip?, info.dst_port?);
+
+        Some((src.min(dst), src.max(dst)))
+    }
+
+    /// It checks if a MAC address is part of a list of parsed rules, whatever the case of their
+    /// hex digits, and returns the rule matched.
+    fn contains_mac(rules: &[(MacAddr, String)], mac: MacAddr) -> Option<&String> {
+        rules
+            .iter()
+            .find(|(address, _)| *address == mac)
+            .map(|(_, rule)| rule)
+    }
+
+    /// It checks if an IP address, or an `ip:port` pair, is part of a list of parsed rules, and
+    /// returns the rule matched. The `ip:port` pairs of IPv6 addresses are written `[ip]:port`.
+    fn contains_address(
+        rules: &[(IpAddr, Option<u16>, String)],
+        ip: Option<IpAddr>,
+        port: Option<u16>,
+    ) -> Option<&String> {
+        let ip = ip?;
+
+        rules
+            .iter()
+            .find(|(address, rule_port, _)| {
+                *address == ip && rule_port.is_none_or(|rule_port| Some(rule_port) == port)
+            })
+            .map(|(_, _, rule)| rule)
+    }
+
+    /// It checks if a domain name matches one of a list of normalized rules, ignoring the case
+    /// and the trailing dot of fully qualified names, and returns the rule matched.
+    fn contains_domain<'a>(rules: &'a [(String, String)], name: &str) -> Option<&'a String> {
+        let name = name.trim_end_matches('.').as_bytes();
+
+        rules
+            .iter()
+            .find(|(pattern, _)| match pattern.strip_prefix("*.") {
+                Some(suffix) => {
+                    name.len() > suffix.len() && {
+                        let (prefix, end) = name.split_at(name.len() - suffix.len());
+                        prefix.ends_with(b".") && end.eq_ignore_ascii_case(suffix.as_bytes())
+                    }
+                }
+                None => name.eq_ignore_ascii_case(pattern.as_bytes()),
+            })
+            .map(|(_, rule)| rule)
+    }
+}
+
+impl Connections {
+    /// It returns the rule a connection matched earlier, forgetting it if the connection has been
+    /// idle for too long or if the packet opens a new TCP connection between the same endpoints.
+    ///
+    /// Arguments:
+    ///
+    /// * `connection`: The endpoints of the connection of the packet.
+    /// * `info`: The parsed packet.
+    /// * `ts`: The timestamp of the packet, since the UNIX epoch.
+    fn blocked_rule(
+        &mut self,
+        connection: Connection,
+        info: &PacketInfo,
+        ts: Duration,
+    ) -> Option<String> {
+        let blocked = self.blocked.get_mut(&connection)?;
+        let opening = info
+            .tcp_flags
+            .is_some_and(|flags| flags & (TcpFlags::SYN | TcpFlags::ACK) == TcpFlags::SYN);
+
+        if opening || ts.saturating_sub(blocked.last_seen) >= BLOCKED_IDLE_TIMEOUT {
+            self.blocked.remove(&connection);
+            return None;
+        }
+        blocked.last_seen = blocked.last_seen.max(ts);
+        Some(blocked.rule.clone())
+    }
+
+    /// It makes room for a blocked connection, forgetting the idle connections and, if too few
+    /// were, the least recently seen ones.
+    ///
+    /// Arguments:
+    ///
+    /// * `now`: The current time, since the UNIX epoch.
+    fn forget(&mut self, now: Duration) {
+        self.blocked
+            .retain(|_, blocked| now.saturating_sub(blocked.last_seen) < BLOCKED_IDLE_TIMEOUT);
+
+        let room = MAX_BLOCKED_CONNECTIONS / 16;
+        if self.blocked.len() + room <= MAX_BLOCKED_CONNECTIONS {
+            return;
+        }
+        let count = self.blocked.len() + room - MAX_BLOCKED_CONNECTIONS;
+        let mut oldest: Vec<(Duration, Connection)> = self
+            .blocked
+            .iter()
+            .map(|(connection, blocked)| (blocked.last_seen, *connection))
+            .collect();
+        oldest.select_nth_unstable_by_key(count - 1, |(last_seen, _)| *last_seen);
+        for (_, connection) in &oldest[..count] {
+            self.blocked.remove(connection);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dissector::http::HttpInfo;
+    use crate::packet::Protocol;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use std::net::Ipv4Addr;
+
+    fn tcp(flags: u16, host: Option<&str>) -> PacketInfo {
+        PacketInfo {
+            protocol: Protocol::Ip(IpNextHeaderProtocols::Tcp),
+            src_mac: MacAddr::zero(),
+            dst_mac: MacAddr::zero(),
+            src_ip: Some(Ipv4Addr::new(10, 0, 0, 1).into()),
+            dst_ip: Some(Ipv4Addr::new(10, 0, 0, 2).into()),
+            src_port: Some(40000),
+            dst_port: Some(80),
+            ip_protocol: Some(6),
+            tcp_flags: Some(flags),
+            len: 64,
+            arp: None,
+            app: host.map(|host| {
+                Application::Http(HttpInfo {
+                    method: "GET".to_string(),
+                    host: Some(host.to_string()),
+                    path: "/".to_string(),
+                })
+            }),
+        }
+    }
+
+    fn blacklist() -> Blacklist {
+        let mut blacklist = Blacklist::default();
+        blacklist.add("host *.example.com").unwrap();
+        blacklist
+    }
+
+    #[test]
+    fn mac_rules_are_parsed_when_loaded_and_edited() {
+        let mut blacklist: Blacklist =
+            toml::from_str("from = [\"02:AB:00:00:00:01\"]\ninto = []").unwrap();
+        let mut connections = Connections::default();
+        let mut info = tcp(TcpFlags::ACK, None);
+        info.src_mac = MacAddr::new(0x02, 0xab, 0, 0, 0, 1);
+        info.dst_mac = MacAddr::new(0x02, 0xab, 0, 0, 0, 2);
+
+        let rule = Some("from 02:AB:00:00:00:01".to_string());
+        assert_eq!(
+            blacklist.contains(&info, &mut connections, Duration::ZERO),
+            rule
+        );
+
+        blacklist.remove("from 02:AB:00:00:00:01").unwrap();
+        assert_eq!(
+            blacklist.contains(&info, &mut connections, Duration::ZERO),
+            None
+        );
+        blacklist.add("into 02:ab:00:00:00:02").unwrap();
+        let rule = Some("into 02:ab:00:00:00:02".to_string());
+        assert_eq!(
+            blacklist.contains(&info, &mut connections, Duration::ZERO),
+            rule
+        );
+    }
+
+    #[test]
+    fn host_rule_blocks_the_rest_of_the_connection() {
+        let blacklist = blacklist();
+        let mut connections = Connections::default();
+        let rule = Some("host *.example.com".to_string());
+
+        assert_eq!(
+            blacklist.contains(
+                &tcp(TcpFlags::ACK, Some("www.example.com")),
+                &mut connections,
+                Duration::ZERO
+            ),
+            rule
+        );
+        assert_eq!(
+            blacklist.contains(
+                &tcp(TcpFlags::ACK, None),
+                &mut connections,
+                Duration::from_secs(1)
+            ),
+            rule
+        );
+
+        let mut other = tcp(TcpFlags::ACK, Some("example.org"));
+        other.src_port = Some(40001);
+        assert_eq!(
+            blacklist.contains(&other, &mut connections, Duration::ZERO),
+            None
+        );
+    }
+
+    #[test]
+    fn blocked_connections_expire() {
+        let blacklist = blacklist();
+        let mut connections = Connections::default();
+        blacklist.contains(
+            &tcp(TcpFlags::ACK, Some("www.example.com")),
+            &mut connections,
+            Duration::ZERO,
+        );
+
+        let later = BLOCKED_IDLE_TIMEOUT + Duration::from_secs(1);
+        assert_eq!(
+            blacklist.contains(&tcp(TcpFlags::ACK, None), &mut connections, later),
+            None
+        );
+    }
+
+    #[test]
+    fn new_connection_between_the_same_endpoints_is_not_blocked() {
+        let blacklist = blacklist();
+        let mut connections = Connections::default();
+        blacklist.contains(
+            &tcp(TcpFlags::ACK, Some("www.example.com")),
+            &mut connections,
+            Duration::ZERO,
+        );
+
+        let ts = Duration::from_secs(1);
+        assert_eq!(
+            blacklist.contains(&tcp(TcpFlags::SYN, None), &mut connections, ts),
+            None
+        );
+        assert_eq!(
+            blacklist.contains(&tcp(TcpFlags::ACK, None), &mut connections, ts),
+            None
+        );
+    }
+
+    #[test]
+    fn full_cache_forgets_the_least_recently_seen_connections() {
+        let blacklist = blacklist();
+        let mut connections = Connections::default();
+        for port in 0..=u16::MAX {
+            let mut info = tcp(TcpFlags::ACK, Some("www.example.com"));
+            info.src_port = Some(port);
+            blacklist.contains(&info, &mut connections, Duration::from_millis(port as u64));
+        }
+        let mut info = tcp(TcpFlags::ACK, Some("www.example.com"));
+        info.dst_port = Some(8080);
+        blacklist.contains(&info, &mut connections, Duration::from_secs(70));
+        assert!(connections.blocked.len() < MAX_BLOCKED_CONNECTIONS);
+
+        let ts = Duration::from_secs(70);
+        let mut oldest = tcp(TcpFlags::ACK, None);
+        oldest.src_port = Some(0);
+        assert_eq!(blacklist.contains(&oldest, &mut connections, ts), None);
+        let mut newest = tcp(TcpFlags::ACK, None);
+        newest.src_port = Some(u16::MAX);
+        assert!(blacklist.contains(&newest, &mut connections, ts).is_some());
+    }
+
+    #[test]
+    fn removed_host_rules_let_their_connections_through() {
+        let mut blacklist = blacklist();
+        blacklist.add("host other.org").unwrap();
+        let mut connections = Connections::default();
+        blacklist.contains(
+            &tcp(TcpFlags::ACK, Some("www.example.com")),
+            &mut connections,
+            Duration::ZERO,
+        );
+
+        let ts = Duration::from_secs(1);
+        blacklist.remove("host other.org").unwrap();
+        assert!(blacklist
+            .contains(&tcp(TcpFlags::ACK, None), &mut connections, ts)
+            .is_some());
+        blacklist.remove("host *.example.com").unwrap();
+        assert_eq!(
+            blacklist.contains(&tcp(TcpFlags::ACK, None), &mut connections, ts),
+            None
+        );
+    }
+
+    #[test]
+    fn address_and_domain_rules_are_parsed_when_loaded_and_edited() {
+        let mut blacklist: Blacklist =
+            toml::from_str("from = [\"10.0.0.1:40000\"]\ninto = [\"[fe80::2]:53\"]").unwrap();
+        let mut connections = Connections::default();
+        let ts = Duration::ZERO;
+
+        let rule = Some("from 10.0.0.1:40000".to_string());
+        assert_eq!(
+            blacklist.contains(&tcp(0, None), &mut connections, ts),
+            rule
+        );
+        let mut info = tcp(0, None);
+        info.src_port = Some(40001);
+        assert_eq!(blacklist.contains(&info, &mut connections, ts), None);
+        info.dst_ip = Some("fe80::2".parse().unwrap());
+        info.dst_port = Some(53);
+        let rule = Some("into [fe80::2]:53".to_string());
+        assert_eq!(blacklist.contains(&info, &mut connections, ts), rule);
+
+        blacklist.add("host *.Example.COM.").unwrap();
+        let mut info = tcp(TcpFlags::ACK, Some("WWW.example.com"));
+        info.src_port = Some(40001);
+        let rule = Some("host *.Example.COM.".to_string());
+        assert_eq!(blacklist.contains(&info, &mut connections, ts), rule);
+        let mut info = tcp(TcpFlags::ACK, Some("wwwexample.com"));
+        info.src_port = Some(40002);
+        assert_eq!(blacklist.contains(&info, &mut connections, ts), None);
+    }
+}
@@ -0,0 +1,258 @@
+use crate::packet;
+use pnet::packet::ethernet::{EtherTypes, MutableEthernetPacket};
+use pnet::packet::ip::{IpNextHeaderProtocol, IpNextHeaderProtocols};
+use pnet::packet::ipv4::{self, MutableIpv4Packet};
+use pnet::packet::ipv6::MutableIpv6Packet;
+use pnet::packet::tcp::{self, MutableTcpPacket};
+use pnet::packet::udp::{self, MutableUdpPacket};
+use pnet::packet::MutablePacket;
+use std::net::IpAddr;
+
+/// It recomputes the IPv4 header checksum and the TCP or UDP checksum of an Ethernet frame after
+/// its content was rewritten. The frame must be complete, since the transport checksum covers the
+/// whole segment. Fragments only get their IPv4 header checksum recomputed, since their payload is
+/// a part of a segment, or none of its header for the fragments after the first.
+///
+/// Arguments:
+///
+/// * `frame`: The Ethernet frame to fix.
+pub fn fix_checksums(frame: &mut [u8]) {
+    let Some(mut ethernet) = MutableEthernetPacket::new(frame) else {
+        return;
+    };
+
+    match ethernet.get_ethertype() {
+        EtherTypes::Ipv4 => {
+            let Some(mut ipv4) = MutableIpv4Packet::new(ethernet.payload_mut()) else {
+                return;
+            };
+            ipv4.set_checksum(ipv4::checksum(&ipv4.to_immutable()));
+            if packet::is_fragment(&ipv4.to_immutable()) {
+                return;
+            }
+
+            let src = IpAddr::V4(ipv4.get_source());
+            let dst = IpAddr::V4(ipv4.get_destination());
+            let protocol = ipv4.get_next_level_protocol();
+            fix_transport(protocol, src, dst, ipv4.payload_mut());
+        }
+        EtherTypes::Ipv6 => {
+            let Some(mut ipv6) = MutableIpv6Packet::new(ethernet.payload_mut()) else {
+                return;
+            };
+
+            let src = IpAddr::V6(ipv6.get_source());
+            let dst = IpAddr::V6(ipv6.get_destination());
+            let protocol = ipv6.get_next_header();
+            // A fragment header is followed by a part of a segment only.
+            if protocol == IpNextHeaderProtocols::Ipv6Frag {
+                return;
+            }
+            fix_transport(protocol, src, dst, ipv6.payload_mut());
+        }
+        _ => {}
+    }
+}
+
+/// It updates a checksum after some of the bytes it covers were replaced, without going through
+/// the whole data again, as described by RFC 1624. It works on packets cut by the capture, whose
+/// checksum cannot be recomputed.
+///
+/// Arguments:
+///
+/// * `checksum`: The checksum before the rewrite.
+/// * `old`: The replaced bytes, of an even length.
+/// * `new`: The bytes written in their place.
+///
+/// Returns:
+///
+/// The checksum after the rewrite.
+pub fn update(checksum: u16, old: &[u8], new: &[u8]) -> u16 {
+    let words = |data: &[u8]| {
+        data.chunks(2)
+            .map(|word| u16::from_be_bytes([word[0], word.get(1).copied().unwrap_or(0)]))
+            .collect::<Vec<u16>>()
+    };
+
+    let mut sum = !checksum as u32;
+    for word in words(old) {
+        sum += !word as u32;
+    }
+    for word in words(new) {
+        sum += word as u32;
+    }
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// It updates the checksum of a TCP, UDP or ICMPv6 segment after the addresses of the
+/// pseudo-header of its IP packet were replaced. Segments too short to hold their checksum are
+/// left untouched.
+///
+/// Arguments:
+///
+/// * `protocol`: The protocol of the IP payload.
+/// * `segment`: The IP payload.
+/// * `old`: The replaced addresses, source first.
+/// * `new`: The addresses written in their place.
+pub fn update_transport(
+    protocol: IpNextHeaderProtocol,
+    segment: &mut [u8],
+    old: &[u8],
+    new: &[u8],
+) {
+    let offset = match protocol {
+        IpNextHeaderProtocols::Tcp => 16,
+        IpNextHeaderProtocols::Udp => 6,
+        IpNextHeaderProtocols::Icmpv6 => 2,
+        _ => return,
+    };
+    let Some(field) = segment.get_mut(offset..offset + 2) else {
+        return;
+    };
+
+    let checksum = u16::from_be_bytes([field[0], field[1]]);
+    let udp = protocol == IpNextHeaderProtocols::Udp;
+    // A null UDP checksum was not computed, and a computed one is never sent as zero.
+    if udp && checksum == 0 {
+        return;
+    }
+    let checksum = match update(checksum, old, new) {
+        0 if udp => 0xffff,
+        checksum => checksum,
+    };
+    field.copy_from_slice(&checksum.to_be_bytes());
+}
+
+/// It recomputes the checksum of a TCP or UDP segment, using the pseudo-header of its IP packet.
+fn fix_transport(protocol: IpNextHeaderProtocol, src: IpAddr, dst: IpAddr, payload: &mut [u8]) {
+    match protocol {
+        IpNextHeaderProtocols::Tcp => {
+            let Some(mut segment) = MutableTcpPacket::new(payload) else {
+                return;
+            };
+            let checksum = match (src, dst) {
+                (IpAddr::V4(src), IpAddr::V4(dst)) => {
+                    tcp::ipv4_checksum(&segment.to_immutable(), &src, &dst)
+                }
+                (IpAddr::V6(src), IpAddr::V6(dst)) => {
+                    tcp::ipv6_checksum(&segment.to_immutable(), &src, &dst)
+                }
+                _ => return,
+            };
+            segment.set_checksum(checksum);
+        }
+        IpNextHeaderProtocols::Udp => {
+            let Some(mut datagram) = MutableUdpPacket::new(payload) else {
+                return;
+            };
+            let checksum = match (src, dst) {
+                // A null checksum means that the sender did not compute it over IPv4.
+                (IpAddr::V4(_), IpAddr::V4(_)) if datagram.get_checksum() == 0 => return,
+                (IpAddr::V4(src), IpAddr::V4(dst)) => {
+                    udp::ipv4_checksum(&datagram.to_immutable(), &src, &dst)
+                }
+                (IpAddr::V6(src), IpAddr::V6(dst)) => {
+                    udp::ipv6_checksum(&datagram.to_immutable(), &src, &dst)
+                }
+                _ => return,
+            };
+            datagram.set_checksum(checksum);
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// It adds up 16-bit words in one's complement, so that data holding a valid checksum adds up
+    /// to 0xffff.
+    fn sum(parts: &[&[u8]]) -> u16 {
+        let mut sum = 0u32;
+        for part in parts {
+            for word in part.chunks(2) {
+                sum += u16::from_be_bytes([word[0], word.get(1).copied().unwrap_or(0)]) as u32;
+            }
+        }
+        while sum >> 16 != 0 {
+            sum = (sum & 0xffff) + (sum >> 16);
+        }
+        sum as u16
+    }
+
+    /// It builds an Ethernet frame carrying an IPv4 packet, with blank checksums.
+    fn ipv4(protocol: u8, segment: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0x02, 0, 0, 0, 0, 2, 0x02, 0, 0, 0, 0, 1, 0x08, 0x00];
+        frame.extend_from_slice(&[0x45, 0]);
+        frame.extend_from_slice(&((20 + segment.len()) as u16).to_be_bytes());
+        frame.extend_from_slice(&[0, 1, 0x40, 0, 64, protocol, 0, 0]);
+        frame.extend_from_slice(&[192, 168, 1, 10, 192, 168, 1, 20]);
+        frame.extend_from_slice(segment);
+        frame
+    }
+
+    /// It checks the IPv4 header checksum and the checksum of the segment of a frame.
+    fn is_valid(frame: &[u8]) -> bool {
+        let (header, segment) = frame[14..].split_at(20);
+        let len = (segment.len() as u16).to_be_bytes();
+        let pseudo = [&header[12..20], &[0, header[9]], &len[..], segment];
+        sum(&[header]) == 0xffff && sum(&pseudo) == 0xffff
+    }
+
+    #[test]
+    fn fixes_tcp_and_udp_checksums() {
+        let mut tcp = vec![
+            0x9c, 0x40, 0, 80, 0, 0, 0, 1, 0, 0, 0, 0, 0x50, 0x02, 0xff, 0xff,
+        ];
+        tcp.extend_from_slice(&[0xde, 0xad, 0, 0, b'h', b'i', b'!']);
+        let mut udp = vec![0x9c, 0x40, 0, 53, 0, 11, 0xde, 0xad, b'h', b'i', b'!'];
+
+        for frame in [ipv4(6, &tcp), ipv4(17, &udp)].iter_mut() {
+            assert!(!is_valid(frame));
+            fix_checksums(frame);
+            assert!(is_valid(frame));
+        }
+
+        // A UDP checksum that was not computed stays so.
+        udp[6..8].fill(0);
+        let mut frame = ipv4(17, &udp);
+        fix_checksums(&mut frame);
+        assert_eq!(frame[40..42], [0, 0]);
+    }
+
+    #[test]
+    fn updates_match_a_recomputation() {
+        let mut frame = ipv4(
+            17,
+            &[0x9c, 0x40, 0, 53, 0, 11, 0xde, 0xad, b'h', b'i', b'!'],
+        );
+        fix_checksums(&mut frame);
+        let checksum = u16::from_be_bytes([frame[40], frame[41]]);
+
+        let old = frame[26..34].to_vec();
+        let new = [10, 1, 2, 3, 172, 16, 254, 1];
+        frame[26..34].copy_from_slice(&new);
+        let updated = update(checksum, &old, &new);
+        fix_checksums(&mut frame);
+
+        assert_eq!(updated, u16::from_be_bytes([frame[40], frame[41]]));
+    }
+
+    #[test]
+    fn updates_of_transport_checksums_keep_null_udp_checksums() {
+        let old = [192, 168, 1, 10];
+        let new = [10, 0, 0, 1];
+        let mut udp = [0x9c, 0x40, 0, 53, 0, 8, 0, 0];
+        update_transport(IpNextHeaderProtocols::Udp, &mut udp, &old, &new);
+        assert_eq!(udp[6..8], [0, 0]);
+
+        let mut tcp = [0; 20];
+        tcp[16..18].copy_from_slice(&0x1234u16.to_be_bytes());
+        update_transport(IpNextHeaderProtocols::Tcp, &mut tcp, &old, &new);
+        assert_eq!(tcp[16..18], update(0x1234, &old, &new).to_be_bytes());
+    }
+}
@@ -0,0 +1,53 @@
+use crate::error::Error;
+use crate::packet_streaming::packet_streaming_client::PacketStreamingClient;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity};
+
+/// `TlsOptions` holds the certificates used to connect to the grpc server over TLS.
+///
+/// Properties:
+///
+/// * `ca`: The certificate authority checking the certificate of the server, as a PEM file.
+/// * `cert`: The client certificate, as a PEM file, sent along with its key.
+/// * `key`: The private key of the client certificate, as a PEM file.
+/// * `domain`: The name expected in the certificate of the server, instead of the host of its URL.
+#[derive(Clone, Debug, Default)]
+pub struct TlsOptions {
+    pub ca: Option<String>,
+    pub cert: Option<String>,
+    pub key: Option<String>,
+    pub domain: Option<String>,
+}
+
+/// It connects to the grpc server, over TLS when its URL uses `https` or when a TLS option is set.
+///
+/// Arguments:
+///
+/// * `url`: The URL of the grpc server.
+/// * `tls`: The TLS certificates.
+///
+/// Returns:
+///
+/// The client of the grpc server.
+pub async fn connect(url: &str, tls: &TlsOptions) -> Result<PacketStreamingClient<Channel>, Error> {
+    let mut endpoint = Endpoint::from_shared(url.to_owned())
+        .map_err(|err| Error::Config(format!("Invalid grpc server URL {}: {}", url, err)))?;
+
+    let enabled = tls.ca.is_some() || tls.cert.is_some() || tls.domain.is_some();
+    if enabled || url.starts_with("https://") {
+        let mut config = ClientTlsConfig::new();
+        if let Some(ref ca) = tls.ca {
+            config = config.ca_certificate(Certificate::from_pem(std::fs::read(ca)?));
+        }
+        if let (Some(ref cert), Some(ref key)) = (&tls.cert, &tls.key) {
+            config = config.identity(Identity::from_pem(
+                std::fs::read(cert)?,
+                std::fs::read(key)?,
+            ));
+        }
+        if let Some(ref domain) = tls.domain {
+            config = config.domain_name(domain);
+        }
+        endpoint = endpoint.tls_config(config)?;
+    }
+    Ok(PacketStreamingClient::new(endpoint.connect().await?))
+}
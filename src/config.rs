@@ -0,0 +1,220 @@
+use crate::args::Args;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches};
+use sniffer::{Blacklist, Error};
+use std::ffi::OsString;
+
+/// It parses the options of the sniffer. Options given on the command line take precedence over
+/// environment variables, which take precedence over the configuration file given by `--config`.
+///
+/// The configuration file is a TOML file whose keys are the long names of the options, such as
+/// `interface = "eth0"` or `flow-idle-timeout = 30`. Tables can be used to group options, their
+/// names being ignored, except for a `[blacklist]` table which gives the blacklist inline. Flags
+/// are set with `true`.
+///
+/// Returns:
+///
+/// The options of the sniffer.
+pub fn parse() -> Result<Args, Error> {
+    parse_from(std::env::args_os().collect())
+}
+
+/// It parses the options of the sniffer from a command line, merging them with the environment
+/// variables and the configuration file.
+///
+/// Arguments:
+///
+/// * `command_line`: The name of the program followed by its options.
+///
+/// Returns:
+///
+/// The options of the sniffer.
+fn parse_from(command_line: Vec<OsString>) -> Result<Args, Error> {
+    let matches = Args::command().get_matches_from(&command_line);
+    let args = Args::from_arg_matches(&matches)?;
+    let Some(ref path) = args.config else {
+        return check(args);
+    };
+
+    let data = std::fs::read_to_string(path)
+        .map_err(|err| Error::Config(format!("Could not read the configuration file: {}", err)))?;
+    let table: toml::Table = toml::from_str(&data)
+        .map_err(|err| Error::Config(format!("Could not parse the configuration file: {}", err)))?;
+
+    let mut argv: Vec<OsString> = command_line.iter().take(1).cloned().collect();
+    let mut inline_blacklist = None;
+    collect(&table, &matches, &mut argv, &mut inline_blacklist)?;
+    argv.extend(command_line.into_iter().skip(1));
+
+    let matches = Args::command()
+        .try_get_matches_from(argv)
+        .map_err(|err| Error::Config(format!("Invalid configuration file: {}", err)))?;
+    let mut args = Args::from_arg_matches(&matches)?;
+    args.inline_blacklist = inline_blacklist;
+    check(args)
+}
+
+/// It checks the options which only make sense together, once the ones of the configuration file
+/// are merged with the ones given on the command line or by environment variables.
+///
+/// Arguments:
+///
+/// * `args`: The merged options.
+///
+/// Returns:
+///
+/// The options, if they are consistent.
+fn check(args: Args) -> Result<Args, Error> {
+    if args.tls_cert.is_some() != args.tls_key.is_some() {
+        return Err(Error::Config(
+            "--tls-cert and --tls-key must be given together".to_string(),
+        ));
+    }
+    if (args.hexdump_payload || args.hexdump_limit.is_some()) && !args.hexdump {
+        return Err(Error::Config(
+            "--hexdump-payload and --hexdump-limit require --hexdump".to_string(),
+        ));
+    }
+    Ok(args)
+}
+
+/// It turns the entries of a configuration table into command line options, skipping the ones
+/// already given on the command line or by environment variables.
+///
+/// Arguments:
+///
+/// * `table`: The configuration table.
+/// * `matches`: The options given on the command line or by environment variables.
+/// * `argv`: The command line options the entries are added to.
+/// * `inline_blacklist`: The blacklist given inline, if any.
+fn collect(
+    table: &toml::Table,
+    matches: &ArgMatches,
+    argv: &mut Vec<OsString>,
+    inline_blacklist: &mut Option<Blacklist>,
+) -> Result<(), Error> {
+    let command = Args::command();
+
+    for (key, value) in table {
+        let id = key.replace('-', "_");
+
+        if let toml::Value::Table(table) = value {
+            if id == "blacklist" {
+                if !given(matches, &id) {
+                    let blacklist: Blacklist = toml::Value::Table(table.clone())
+                        .try_into()
+                        .map_err(|err| Error::Blacklist(err.to_string()))?;
+                    *inline_blacklist = Some(blacklist);
+                }
+            } else {
+                collect(table, matches, argv, inline_blacklist)?;
+            }
+            continue;
+        }
+
+        let long = command
+            .get_arguments()
+            .find(|arg| arg.get_id() == id.as_str() && id != "config")
+            .and_then(|arg| arg.get_long())
+            .ok_or_else(|| {
+                Error::Config(format!("Unknown option in the configuration file: {}", key))
+            })?;
+        if given(matches, &id) {
+            continue;
+        }
+
+        match value {
+            toml::Value::Boolean(true) => argv.push(format!("--{}", long).into()),
+            toml::Value::Boolean(false) => {}
+            toml::Value::String(value) => argv.push(format!("--{}={}", long, value).into()),
+            toml::Value::Integer(value) => argv.push(format!("--{}={}", long, value).into()),
+            toml::Value::Float(value) => argv.push(format!("--{}={}", long, value).into()),
+            toml::Value::Datetime(value) => argv.push(format!("--{}={}", long, value).into()),
+            _ => {
+                return Err(Error::Config(format!(
+                    "Unsupported value for the option {}",
+                    key
+                )))
+            }
+        }
+    }
+    Ok(())
+}
+
+/// It checks if an option was given on the command line or by an environment variable.
+fn given(matches: &ArgMatches, id: &str) -> bool {
+    matches!(
+        matches.value_source(id),
+        Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// It writes a configuration file, then parses a command line reading it.
+    fn parse_with(name: &str, config: &str, options: &[&str]) -> Result<Args, Error> {
+        let path =
+            std::env::temp_dir().join(format!("sniffer-{}-{}.toml", name, std::process::id()));
+        std::fs::write(&path, config).unwrap();
+
+        let mut command_line: Vec<OsString> = vec!["sniffer".into(), "--config".into()];
+        command_line.push(path.clone().into());
+        command_line.extend(options.iter().map(OsString::from));
+        let args = parse_from(command_line);
+        std::fs::remove_file(path).ok();
+        args
+    }
+
+    #[test]
+    fn the_command_line_and_the_environment_override_the_configuration_file() {
+        std::env::set_var("SNIFFER_FLOW_IDLE_TIMEOUT", "7");
+        std::env::set_var("SNIFFER_FLOW_ACTIVE_TIMEOUT", "100");
+        let args = parse_with(
+            "precedence",
+            r#"
+                workers = 3
+                flow-idle-timeout = 30
+                flow-active-timeout = 60
+
+                [capture]
+                interface = "eth9"
+                flows = true
+
+                [blacklist]
+                from = ["10.0.0.1"]
+                into = []
+                domains = []
+                hosts = []
+            "#,
+            &["--workers", "2", "--flow-active-timeout", "200"],
+        );
+        std::env::remove_var("SNIFFER_FLOW_IDLE_TIMEOUT");
+        std::env::remove_var("SNIFFER_FLOW_ACTIVE_TIMEOUT");
+        let args = args.unwrap();
+
+        assert_eq!(args.workers, Some(2));
+        assert_eq!(args.flow_active_timeout, 200);
+        assert_eq!(args.flow_idle_timeout, 7);
+        assert_eq!(args.interface.as_deref(), Some("eth9"));
+        assert!(args.flows);
+        assert_eq!(args.inline_blacklist.unwrap().rules(), ["from 10.0.0.1"]);
+        assert_eq!(args.flow_max, 65536);
+    }
+
+    #[test]
+    fn invalid_configuration_files_are_rejected() {
+        let unknown = parse_with("unknown", "interfaces = \"eth0\"", &[]);
+        assert!(matches!(unknown, Err(Error::Config(err)) if err.contains("interfaces")));
+
+        let invalid = parse_with("invalid", "workers = \"many\"", &[]);
+        assert!(matches!(invalid, Err(Error::Config(_))));
+
+        let alone = parse_with("alone", "tls-cert = \"client.pem\"", &[]);
+        assert!(matches!(alone, Err(Error::Config(err)) if err.contains("--tls-key")));
+
+        let blacklist = parse_with("blacklist", "[blacklist]\nfrom = \"10.0.0.1\"", &[]);
+        assert!(matches!(blacklist, Err(Error::Blacklist(_))));
+    }
+}
@@ -0,0 +1,58 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// `Counters` contains the counters of a capture, updated by the threads of its pipeline. They are
+/// handed to the sniffer by its builder, so that an application embedding it can read them while
+/// the capture runs, such as the `sniffer` binary exposing them on its `/metrics` endpoint.
+///
+/// Properties:
+///
+/// * `captured`: The number of packets read from the capture.
+/// * `captured_bytes`: The number of bytes on the wire of the packets read from the capture.
+/// * `parsed`: The number of packets parsed.
+/// * `unparseable`: The number of packets which could not be parsed.
+/// * `blacklisted`: The number of packets ignored because of the blacklist.
+/// * `saved`: The number of packets written to the savefile.
+/// * `sent`: The number of packets sent to the gRPC server.
+/// * `pcap_received`: The number of packets received by the capture, as reported by libpcap.
+/// * `pcap_dropped`: The number of packets dropped because the capture buffer was full.
+/// * `pcap_if_dropped`: The number of packets dropped by the network interface or its driver.
+/// * `pipeline_stalls`: The number of times the capture waited for the processing pipeline.
+/// * `pipeline_stall_micros`: The time the capture spent waiting for the processing pipeline.
+/// * `pipeline_queued`: The number of packets captured and not yet delivered by the pipeline.
+#[derive(Default, Debug)]
+pub struct Counters {
+    pub captured: AtomicU64,
+    pub captured_bytes: AtomicU64,
+    pub parsed: AtomicU64,
+    pub unparseable: AtomicU64,
+    pub blacklisted: AtomicU64,
+    pub saved: AtomicU64,
+    pub sent: AtomicU64,
+    pub pcap_received: AtomicU64,
+    pub pcap_dropped: AtomicU64,
+    pub pcap_if_dropped: AtomicU64,
+    pub pipeline_stalls: AtomicU64,
+    pub pipeline_stall_micros: AtomicU64,
+    pub pipeline_queued: AtomicU64,
+}
+
+/// It increments a counter.
+pub(crate) fn inc(counter: &AtomicU64) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+impl Counters {
+    /// It stores the statistics of the capture, which libpcap only provides for live captures.
+    ///
+    /// Arguments:
+    ///
+    /// * `stats`: The statistics returned by `Capture::stats()`.
+    pub fn set_pcap_stats(&self, stats: &pcap::Stat) {
+        self.pcap_received
+            .store(stats.received as u64, Ordering::Relaxed);
+        self.pcap_dropped
+            .store(stats.dropped as u64, Ordering::Relaxed);
+        self.pcap_if_dropped
+            .store(stats.if_dropped as u64, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,123 @@
+use colored::Colorize;
+use pnet::packet::arp::{ArpOperations, ArpPacket};
+use pnet::util::MacAddr;
+use std::net::Ipv4Addr;
+
+/// `ArpInfo` contains the decoded fields of an ARP packet.
+///
+/// Properties:
+///
+/// * `operation`: The ARP operation code, `1` for a request and `2` for a reply.
+/// * `sender_hw`: The hardware address of the sender.
+/// * `sender_ip`: The protocol address of the sender.
+/// * `target_hw`: The hardware address of the target.
+/// * `target_ip`: The protocol address of the target.
+/// * `gratuitous`: Whether the packet announces the address of its sender instead of resolving one.
+pub struct ArpInfo {
+    pub operation: u16,
+    pub sender_hw: MacAddr,
+    pub sender_ip: Ipv4Addr,
+    pub target_hw: MacAddr,
+    pub target_ip: Ipv4Addr,
+    pub gratuitous: bool,
+}
+
+impl ArpInfo {
+    /// It decodes the fields of an ARP packet.
+    ///
+    /// Arguments:
+    ///
+    /// * `arp`: The ARP packet.
+    ///
+    /// Returns:
+    ///
+    /// An `ArpInfo` with the gratuitous flag set when the sender announces its own address. A packet
+    /// sent from 0.0.0.0, such as an RFC 5227 probe, announces nothing.
+    pub fn from(arp: &ArpPacket) -> Self {
+        let sender_ip = arp.get_sender_proto_addr();
+        let target_ip = arp.get_target_proto_addr();
+
+        ArpInfo {
+            operation: arp.get_operation().0,
+            sender_hw: arp.get_sender_hw_addr(),
+            sender_ip,
+            target_hw: arp.get_target_hw_addr(),
+            target_ip,
+            gratuitous: sender_ip == target_ip && !sender_ip.is_unspecified(),
+        }
+    }
+
+    /// It returns the name of the ARP operation.
+    pub fn operation_name(&self) -> &'static str {
+        match self.operation {
+            1 => "request",
+            2 => "reply",
+            3 => "rarp-request",
+            4 => "rarp-reply",
+            _ => "unknown",
+        }
+    }
+}
+
+impl std::fmt::Display for ArpInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.operation {
+            op if op == ArpOperations::Request.0 => write!(
+                f,
+                "{} who-has {} tell {} ({})",
+                "ARP".cyan(),
+                self.target_ip,
+                self.sender_ip,
+                self.sender_hw
+            )?,
+            op if op == ArpOperations::Reply.0 => write!(
+                f,
+                "{} {} is-at {} to {}",
+                "ARP".cyan(),
+                self.sender_ip,
+                self.sender_hw,
+                self.target_hw
+            )?,
+            _ => write!(f, "{} {}", "ARP".cyan(), self.operation_name())?,
+        }
+        if self.gratuitous {
+            write!(f, " {}", "gratuitous".yellow())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arp(sender_ip: [u8; 4], target_ip: [u8; 4]) -> ArpInfo {
+        let mut data = vec![0, 1, 0x08, 0x00, 6, 4, 0, 1, 0x02, 0, 0, 0, 0, 0x01];
+        data.extend_from_slice(&sender_ip);
+        data.extend_from_slice(&[0; 6]);
+        data.extend_from_slice(&target_ip);
+
+        ArpInfo::from(&ArpPacket::new(&data).unwrap())
+    }
+
+    #[test]
+    fn decodes_requests() {
+        let info = arp([192, 168, 1, 10], [192, 168, 1, 1]);
+
+        assert_eq!(info.operation_name(), "request");
+        assert_eq!(info.sender_hw, MacAddr::new(0x02, 0, 0, 0, 0, 0x01));
+        assert_eq!(info.sender_ip, Ipv4Addr::new(192, 168, 1, 10));
+        assert_eq!(info.target_ip, Ipv4Addr::new(192, 168, 1, 1));
+        assert!(!info.gratuitous);
+    }
+
+    #[test]
+    fn detects_gratuitous_announcements() {
+        assert!(arp([192, 168, 1, 10], [192, 168, 1, 10]).gratuitous);
+    }
+
+    #[test]
+    fn probes_are_not_gratuitous() {
+        assert!(!arp([0, 0, 0, 0], [0, 0, 0, 0]).gratuitous);
+    }
+}
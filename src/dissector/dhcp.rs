@@ -0,0 +1,442 @@
+use colored::Colorize;
+use pnet::util::MacAddr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::ops::Range;
+
+/// Ports used by DHCP servers and clients.
+pub const PORTS: [u16; 2] = [67, 68];
+
+/// Ports used by DHCPv6 servers and clients.
+pub const PORTS_V6: [u16; 2] = [546, 547];
+
+const MAGIC_COOKIE: [u8; 4] = [0x63, 0x82, 0x53, 0x63];
+
+/// `DhcpVersion` tells whether a message is a DHCP or a DHCPv6 message.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DhcpVersion {
+    V4,
+    V6,
+}
+
+/// `DhcpInfo` contains the fields of a DHCP or DHCPv6 message describing a device joining the
+/// network.
+///
+/// Properties:
+///
+/// * `version`: Whether the message is a DHCP or a DHCPv6 message.
+/// * `message_type`: The name of the message type, such as `DISCOVER` or `SOLICIT`.
+/// * `client_mac`: The hardware address of the client.
+/// * `requested_ip`: The address requested by the client.
+/// * `assigned_ip`: The address assigned to the client by the server.
+/// * `hostname`: The host name of the client.
+pub struct DhcpInfo {
+    pub version: DhcpVersion,
+    pub message_type: &'static str,
+    pub client_mac: Option<MacAddr>,
+    pub requested_ip: Option<IpAddr>,
+    pub assigned_ip: Option<IpAddr>,
+    pub hostname: Option<String>,
+}
+
+impl DhcpInfo {
+    /// It parses a DHCP message, made of a BOOTP header followed by DHCP options.
+    ///
+    /// Arguments:
+    ///
+    /// * `data`: The UDP payload.
+    ///
+    /// Returns:
+    ///
+    /// A `DhcpInfo` if the payload is a DHCP message.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < 240 || data[236..240] != MAGIC_COOKIE {
+            return None;
+        }
+
+        let hlen = data[2] as usize;
+        let client_mac = (data[1] == 1 && hlen == 6)
+            .then(|| MacAddr::new(data[28], data[29], data[30], data[31], data[32], data[33]));
+        let yiaddr = Ipv4Addr::new(data[16], data[17], data[18], data[19]);
+
+        let mut info = DhcpInfo {
+            version: DhcpVersion::V4,
+            message_type: "BOOTP",
+            client_mac,
+            requested_ip: None,
+            assigned_ip: (!yiaddr.is_unspecified()).then_some(yiaddr.into()),
+            hostname: None,
+        };
+
+        let mut options = &data[240..];
+        while let Some((&code, rest)) = options.split_first() {
+            match code {
+                0 => {
+                    options = rest;
+                    continue;
+                }
+                255 => break,
+                _ => {}
+            }
+            let Some((&len, rest)) = rest.split_first() else {
+                break;
+            };
+            let Some(value) = rest.get(..len as usize) else {
+                break;
+            };
+            options = &rest[len as usize..];
+
+            match code {
+                12 => info.hostname = Some(String::from_utf8_lossy(value).into_owned()),
+                50 if len == 4 => {
+                    info.requested_ip =
+                        Some(Ipv4Addr::new(value[0], value[1], value[2], value[3]).into())
+                }
+                53 if len == 1 => {
+                    info.message_type = match value[0] {
+                        1 => "DISCOVER",
+                        2 => "OFFER",
+                        3 => "REQUEST",
+                        4 => "DECLINE",
+                        5 => "ACK",
+                        6 => "NAK",
+                        7 => "RELEASE",
+                        8 => "INFORM",
+                        _ => "UNKNOWN",
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Some(info)
+    }
+
+    /// It parses a DHCPv6 message. Relayed messages are not unwrapped.
+    ///
+    /// Arguments:
+    ///
+    /// * `data`: The UDP payload.
+    ///
+    /// Returns:
+    ///
+    /// A `DhcpInfo` if the payload is a DHCPv6 message.
+    pub fn parse_v6(data: &[u8]) -> Option<Self> {
+        let message_type = match *data.first()? {
+            1 => "SOLICIT",
+            2 => "ADVERTISE",
+            3 => "REQUEST",
+            4 => "CONFIRM",
+            5 => "RENEW",
+            6 => "REBIND",
+            7 => "REPLY",
+            8 => "RELEASE",
+            9 => "DECLINE",
+            10 => "RECONFIGURE",
+            11 => "INFORMATION-REQUEST",
+            12 => "RELAY-FORW",
+            13 => "RELAY-REPL",
+            _ => return None,
+        };
+
+        let mut info = DhcpInfo {
+            version: DhcpVersion::V6,
+            message_type,
+            client_mac: None,
+            requested_ip: None,
+            assigned_ip: None,
+            hostname: None,
+        };
+        if message_type.starts_with("RELAY") {
+            return Some(info);
+        }
+
+        // Addresses in messages sent by the client are requested, the others are assigned.
+        let from_client = matches!(data[0], 1 | 3 | 4 | 5 | 6 | 8 | 9 | 11);
+
+        for (code, value) in Options6(data.get(4..)?) {
+            match code {
+                // Client Identifier, holding a DUID based on a link-layer address.
+                1 => info.client_mac = duid_mac(value),
+                // Identity Association for Non-temporary Addresses.
+                3 => {
+                    let address = Options6(value.get(12..).unwrap_or_default())
+                        .find(|(code, value)| *code == 5 && value.len() >= 16)
+                        .map(|(_, value)| {
+                            let octets: [u8; 16] = value[..16].try_into().unwrap();
+                            IpAddr::from(Ipv6Addr::from(octets))
+                        });
+                    if from_client {
+                        info.requested_ip = address;
+                    } else {
+                        info.assigned_ip = address;
+                    }
+                }
+                // Client FQDN, the domain name being encoded as a sequence of labels.
+                39 => {
+                    let mut labels = Vec::new();
+                    let mut name = value.get(1..).unwrap_or_default();
+                    while let Some((&len, rest)) = name.split_first() {
+                        let Some(label) = rest.get(..len as usize).filter(|_| len > 0) else {
+                            break;
+                        };
+                        labels.push(String::from_utf8_lossy(label).into_owned());
+                        name = &rest[len as usize..];
+                    }
+                    if !labels.is_empty() {
+                        info.hostname = Some(labels.join("."));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Some(info)
+    }
+}
+
+/// It finds the addresses of a DHCP message, so that they can be rewritten: the addresses of its
+/// BOOTP header, the hardware address of the client, and the addresses of the requested address,
+/// server identifier, router, DNS server and client identifier options.
+///
+/// Arguments:
+///
+/// * `data`: The UDP payload.
+///
+/// Returns:
+///
+/// The ranges of the message holding the addresses, of 4 bytes for IPv4 addresses and 6 bytes for
+/// MAC addresses.
+pub fn address_ranges(data: &[u8]) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    if data.len() < 240 || data[236..240] != MAGIC_COOKIE {
+        return ranges;
+    }
+
+    ranges.extend([12..16, 16..20, 20..24, 24..28]);
+    if data[1] == 1 && data[2] == 6 {
+        ranges.push(28..34);
+    }
+
+    let mut offset = 240;
+    while let Some(&code) = data.get(offset) {
+        match code {
+            0 => {
+                offset += 1;
+                continue;
+            }
+            255 => break,
+            _ => {}
+        }
+        let Some(&len) = data.get(offset + 1) else {
+            break;
+        };
+        let (start, len) = (offset + 2, len as usize);
+        if start + len > data.len() {
+            break;
+        }
+        match code {
+            3 | 6 | 50 | 54 if len % 4 == 0 => ranges.extend(
+                (start..start + len)
+                    .step_by(4)
+                    .map(|start| start..start + 4),
+            ),
+            // A client identifier made of an Ethernet hardware type and address.
+            61 if len == 7 && data[start] == 1 => ranges.push(start + 1..start + 7),
+            _ => {}
+        }
+        offset = start + len;
+    }
+    ranges
+}
+
+/// It finds the addresses of a DHCPv6 message, so that they can be rewritten: the link-layer
+/// addresses of the client and server identifiers, and the addresses of the identity
+/// associations. Relayed messages are not unwrapped.
+///
+/// Arguments:
+///
+/// * `data`: The UDP payload.
+///
+/// Returns:
+///
+/// The ranges of the message holding the addresses, of 16 bytes for IPv6 addresses and 6 bytes for
+/// MAC addresses.
+pub fn address_ranges_v6(data: &[u8]) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    if !matches!(data.first(), Some(1..=11)) {
+        return ranges;
+    }
+
+    let mut offset = 4;
+    for (code, value) in Options6(data.get(4..).unwrap_or_default()) {
+        let start = offset + 4;
+        offset = start + value.len();
+        match code {
+            1 | 2 => {
+                if let Some(mac) = duid_mac_offset(value) {
+                    ranges.push(start + mac..start + mac + 6);
+                }
+            }
+            // Identity Associations for Non-temporary and Temporary Addresses.
+            3 | 4 => {
+                let mut sub_offset = start + if code == 3 { 12 } else { 4 };
+                for (code, address) in Options6(data.get(sub_offset..offset).unwrap_or_default()) {
+                    if code == 5 && address.len() >= 16 {
+                        ranges.push(sub_offset + 4..sub_offset + 20);
+                    }
+                    sub_offset += 4 + address.len();
+                }
+            }
+            _ => {}
+        }
+    }
+    ranges
+}
+
+/// `Options6` iterates over the code and value of DHCPv6 options.
+struct Options6<'a>(&'a [u8]);
+
+impl<'a> Iterator for Options6<'a> {
+    type Item = (u16, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let header = self.0.get(..4)?;
+        let code = u16::from_be_bytes([header[0], header[1]]);
+        let len = u16::from_be_bytes([header[2], header[3]]) as usize;
+        let value = self.0.get(4..4 + len)?;
+
+        self.0 = &self.0[4 + len..];
+        Some((code, value))
+    }
+}
+
+/// It extracts the Ethernet address of a DUID-LLT or DUID-LL.
+fn duid_mac(duid: &[u8]) -> Option<MacAddr> {
+    let offset = duid_mac_offset(duid)?;
+    let address = &duid[offset..offset + 6];
+
+    Some(MacAddr::new(
+        address[0], address[1], address[2], address[3], address[4], address[5],
+    ))
+}
+
+/// It finds the offset of the Ethernet address of a DUID-LLT or DUID-LL.
+fn duid_mac_offset(duid: &[u8]) -> Option<usize> {
+    let kind = u16::from_be_bytes(duid.get(..2)?.try_into().ok()?);
+    let offset = match kind {
+        1 => 8,
+        3 => 4,
+        _ => return None,
+    };
+
+    duid.get(offset..offset + 6).map(|_| offset)
+}
+
+impl std::fmt::Display for DhcpInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self.version {
+            DhcpVersion::V4 => "DHCP",
+            DhcpVersion::V6 => "DHCPv6",
+        };
+        write!(f, "{} {}", name.cyan(), self.message_type)?;
+
+        if let Some(ref mac) = self.client_mac {
+            write!(f, " client {}", mac)?;
+        }
+        if let Some(ref hostname) = self.hostname {
+            write!(f, " ({})", hostname)?;
+        }
+        if let Some(ref ip) = self.requested_ip {
+            write!(f, " requested {}", ip)?;
+        }
+        if let Some(ref ip) = self.assigned_ip {
+            write!(f, " assigned {}", ip)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// It builds a DHCP REQUEST from 02:00:00:00:00:01 for 192.168.1.10, named `laptop`.
+    fn request() -> Vec<u8> {
+        let mut message = vec![0; 240];
+        message[..3].copy_from_slice(&[1, 1, 6]);
+        message[28..34].copy_from_slice(&[0x02, 0, 0, 0, 0, 0x01]);
+        message[236..240].copy_from_slice(&MAGIC_COOKIE);
+        message.extend_from_slice(&[53, 1, 3, 0, 50, 4, 192, 168, 1, 10]);
+        message.extend_from_slice(&[12, 6]);
+        message.extend_from_slice(b"laptop");
+        message.extend_from_slice(&[54, 4, 192, 168, 1, 1, 255]);
+        message
+    }
+
+    /// It builds a DHCPv6 REPLY to the client 02:00:00:00:00:01, assigning 2001:db8::10.
+    fn reply() -> Vec<u8> {
+        let mut message = vec![7, 0x12, 0x34, 0x56];
+        message.extend_from_slice(&[0, 1, 0, 10, 0, 3, 0, 1, 0x02, 0, 0, 0, 0, 0x01]);
+        message.extend_from_slice(&[0, 3, 0, 40, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0]);
+        message.extend_from_slice(&[0, 5, 0, 24, 0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0]);
+        message.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0x10, 0, 0, 0, 60, 0, 0, 0, 90]);
+        message.extend_from_slice(&[0, 39, 0, 8, 0, 6]);
+        message.extend_from_slice(b"laptop");
+        message
+    }
+
+    #[test]
+    fn parses_requests() {
+        let dhcp = DhcpInfo::parse(&request()).unwrap();
+
+        assert_eq!(dhcp.version, DhcpVersion::V4);
+        assert_eq!(dhcp.message_type, "REQUEST");
+        assert_eq!(dhcp.client_mac, Some(MacAddr::new(0x02, 0, 0, 0, 0, 0x01)));
+        assert_eq!(
+            dhcp.requested_ip,
+            Some(Ipv4Addr::new(192, 168, 1, 10).into())
+        );
+        assert_eq!(dhcp.assigned_ip, None);
+        assert_eq!(dhcp.hostname.as_deref(), Some("laptop"));
+    }
+
+    #[test]
+    fn rejects_bootp_without_the_magic_cookie() {
+        let mut message = request();
+        message[236] = 0;
+
+        assert!(DhcpInfo::parse(&message).is_none());
+        assert!(address_ranges(&message).is_empty());
+    }
+
+    #[test]
+    fn parses_dhcpv6_replies() {
+        let dhcp = DhcpInfo::parse_v6(&reply()).unwrap();
+
+        assert_eq!(dhcp.version, DhcpVersion::V6);
+        assert_eq!(dhcp.message_type, "REPLY");
+        assert_eq!(dhcp.client_mac, Some(MacAddr::new(0x02, 0, 0, 0, 0, 0x01)));
+        assert_eq!(dhcp.assigned_ip, Some("2001:db8::10".parse().unwrap()));
+        assert_eq!(dhcp.hostname.as_deref(), Some("laptop"));
+    }
+
+    #[test]
+    fn finds_the_addresses_of_the_options() {
+        let message = request();
+        let ranges = address_ranges(&message);
+
+        assert!(ranges.contains(&(28..34)));
+        assert!(ranges
+            .iter()
+            .any(|range| message[range.clone()] == [192, 168, 1, 10]));
+        assert!(ranges
+            .iter()
+            .any(|range| message[range.clone()] == [192, 168, 1, 1]));
+
+        let message = reply();
+        let ranges = address_ranges_v6(&message);
+        assert_eq!(ranges.len(), 2);
+        assert_eq!(message[ranges[0].clone()], [0x02, 0, 0, 0, 0, 0x01]);
+        assert_eq!(message[ranges[1].start..ranges[1].start + 2], [0x20, 0x01]);
+    }
+}
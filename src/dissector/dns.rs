@@ -0,0 +1,431 @@
+use colored::Colorize;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::ops::Range;
+
+/// Ports carrying DNS messages: unicast DNS, mDNS and LLMNR.
+pub const PORTS: [u16; 3] = [53, 5353, 5355];
+
+/// Maximum number of compression pointers followed while reading a name, to avoid looping forever
+/// on malicious messages.
+const MAX_POINTERS: usize = 16;
+
+/// `DnsType` is the type of a DNS question or resource record.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DnsType(pub u16);
+
+/// `DnsRcode` is the response code of a DNS message.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DnsRcode(pub u8);
+
+/// `DnsQuestion` is an entry of the question section of a DNS message.
+///
+/// Properties:
+///
+/// * `name`: The queried domain name.
+/// * `qtype`: The queried record type.
+pub struct DnsQuestion {
+    pub name: String,
+    pub qtype: DnsType,
+}
+
+/// `DnsAnswer` is an entry of the answer section of a DNS message.
+///
+/// Properties:
+///
+/// * `name`: The domain name the record belongs to.
+/// * `rtype`: The type of the record.
+/// * `ttl`: The time to live of the record, in seconds.
+/// * `data`: The record data, formatted according to its type.
+pub struct DnsAnswer {
+    pub name: String,
+    pub rtype: DnsType,
+    pub ttl: u32,
+    pub data: String,
+}
+
+/// `DnsInfo` contains the decoded fields of a DNS, mDNS or LLMNR message.
+///
+/// Properties:
+///
+/// * `id`: The transaction ID of the message.
+/// * `response`: Whether the message is a response or a query.
+/// * `rcode`: The response code of the message.
+/// * `questions`: The question section of the message.
+/// * `answers`: The answer section of the message.
+pub struct DnsInfo {
+    pub id: u16,
+    pub response: bool,
+    pub rcode: DnsRcode,
+    pub questions: Vec<DnsQuestion>,
+    pub answers: Vec<DnsAnswer>,
+}
+
+impl DnsInfo {
+    /// It parses a DNS message as carried by UDP.
+    ///
+    /// Arguments:
+    ///
+    /// * `data`: The UDP payload.
+    ///
+    /// Returns:
+    ///
+    /// A `DnsInfo` if the payload looks like a DNS message.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < 12 {
+            return None;
+        }
+
+        let flags = u16::from_be_bytes([data[2], data[3]]);
+        let qdcount = u16::from_be_bytes([data[4], data[5]]);
+        let ancount = u16::from_be_bytes([data[6], data[7]]);
+
+        // A question count of zero or above a few dozen is very unlikely to be real DNS traffic.
+        if (qdcount == 0 && ancount == 0) || qdcount > 32 {
+            return None;
+        }
+
+        let mut offset = 12;
+        let mut questions = Vec::with_capacity(qdcount as usize);
+        for _ in 0..qdcount {
+            let (name, next) = read_name(data, offset)?;
+            let qtype = read_u16(data, next)?;
+            offset = next + 4;
+            questions.push(DnsQuestion {
+                name,
+                qtype: DnsType(qtype),
+            });
+        }
+
+        // Answers are decoded on a best-effort basis: a truncated record stops the decoding but
+        // keeps what has already been read.
+        let mut answers = Vec::new();
+        for _ in 0..ancount {
+            match read_answer(data, offset) {
+                Some((answer, next)) => {
+                    answers.push(answer);
+                    offset = next;
+                }
+                None => break,
+            }
+        }
+
+        Some(DnsInfo {
+            id: u16::from_be_bytes([data[0], data[1]]),
+            response: flags & 0x8000 != 0,
+            rcode: DnsRcode((flags & 0x000f) as u8),
+            questions,
+            answers,
+        })
+    }
+
+    /// It parses a DNS message as carried by TCP, which prefixes the message with its length.
+    ///
+    /// Arguments:
+    ///
+    /// * `data`: The TCP payload.
+    ///
+    /// Returns:
+    ///
+    /// A `DnsInfo` if the payload looks like a DNS message.
+    pub fn parse_tcp(data: &[u8]) -> Option<Self> {
+        let len = read_u16(data, 0)? as usize;
+        let message = data.get(2..)?;
+
+        Self::parse(&message[..len.min(message.len())])
+    }
+
+    /// It returns the queried domain names of the message.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.questions.iter().map(|question| question.name.as_str())
+    }
+}
+
+/// It finds the addresses of the A and AAAA records of a DNS message, in all of its sections, so
+/// that they can be rewritten. A truncated record stops the search.
+///
+/// Arguments:
+///
+/// * `data`: The DNS message.
+///
+/// Returns:
+///
+/// The ranges of the message holding the addresses.
+pub fn address_ranges(data: &[u8]) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let Some(qdcount) = read_u16(data, 4) else {
+        return ranges;
+    };
+    let records: usize = [6, 8, 10]
+        .iter()
+        .filter_map(|offset| read_u16(data, *offset))
+        .map(|count| count as usize)
+        .sum();
+
+    let mut offset = 12;
+    for _ in 0..qdcount {
+        let Some((_, next)) = read_name(data, offset) else {
+            return ranges;
+        };
+        offset = next + 4;
+    }
+    for _ in 0..records {
+        let Some((_, next)) = read_name(data, offset) else {
+            break;
+        };
+        let (Some(rtype), Some(rdlength)) = (read_u16(data, next), read_u16(data, next + 8)) else {
+            break;
+        };
+        let start = next + 10;
+        let end = start + rdlength as usize;
+        if end > data.len() {
+            break;
+        }
+        if matches!((rtype, rdlength), (1, 4) | (28, 16)) {
+            ranges.push(start..end);
+        }
+        offset = end;
+    }
+    ranges
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+/// It reads a possibly compressed domain name.
+///
+/// Arguments:
+///
+/// * `data`: The whole DNS message, used to resolve compression pointers.
+/// * `offset`: The offset of the name in the message.
+///
+/// Returns:
+///
+/// The name in dotted notation and the offset of the first byte following it.
+fn read_name(data: &[u8], mut offset: usize) -> Option<(String, usize)> {
+    let mut name = String::new();
+    let mut next = None;
+    let mut pointers = 0;
+
+    loop {
+        let len = *data.get(offset)? as usize;
+
+        match len & 0xc0 {
+            0x00 if len == 0 => break,
+            0x00 => {
+                let label = data.get(offset + 1..offset + 1 + len)?;
+                if !name.is_empty() {
+                    name.push('.');
+                }
+                name.push_str(&String::from_utf8_lossy(label));
+                offset += 1 + len;
+            }
+            0xc0 => {
+                pointers += 1;
+                if pointers > MAX_POINTERS {
+                    return None;
+                }
+                let pointer = read_u16(data, offset)? & 0x3fff;
+                next.get_or_insert(offset + 2);
+                offset = pointer as usize;
+            }
+            _ => return None,
+        }
+    }
+
+    if name.is_empty() {
+        name.push('.');
+    }
+    Some((name, next.unwrap_or(offset + 1)))
+}
+
+/// It reads a resource record and formats its data according to its type.
+fn read_answer(data: &[u8], offset: usize) -> Option<(DnsAnswer, usize)> {
+    let (name, offset) = read_name(data, offset)?;
+    let rtype = DnsType(read_u16(data, offset)?);
+    let ttl = read_u32(data, offset + 4)?;
+    let rdlength = read_u16(data, offset + 8)? as usize;
+    let start = offset + 10;
+    let rdata = data.get(start..start + rdlength)?;
+
+    let data = match rtype.0 {
+        1 if rdlength == 4 => Ipv4Addr::new(rdata[0], rdata[1], rdata[2], rdata[3]).to_string(),
+        28 if rdlength == 16 => {
+            let octets: [u8; 16] = rdata.try_into().ok()?;
+            Ipv6Addr::from(octets).to_string()
+        }
+        2 | 5 | 12 => read_name(data, start)?.0,
+        15 => format!(
+            "{} {}",
+            read_u16(data, start)?,
+            read_name(data, start + 2)?.0
+        ),
+        16 => {
+            let mut strings = Vec::new();
+            let mut cursor = 0;
+            while cursor < rdata.len() {
+                let len = rdata[cursor] as usize;
+                let string = rdata.get(cursor + 1..cursor + 1 + len)?;
+                strings.push(format!("\"{}\"", String::from_utf8_lossy(string)));
+                cursor += 1 + len;
+            }
+            strings.join(" ")
+        }
+        _ => format!("<{} bytes>", rdlength),
+    };
+
+    Some((
+        DnsAnswer {
+            name,
+            rtype,
+            ttl,
+            data,
+        },
+        start + rdlength,
+    ))
+}
+
+impl std::fmt::Display for DnsType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            1 => write!(f, "A"),
+            2 => write!(f, "NS"),
+            5 => write!(f, "CNAME"),
+            6 => write!(f, "SOA"),
+            12 => write!(f, "PTR"),
+            15 => write!(f, "MX"),
+            16 => write!(f, "TXT"),
+            28 => write!(f, "AAAA"),
+            33 => write!(f, "SRV"),
+            41 => write!(f, "OPT"),
+            47 => write!(f, "NSEC"),
+            64 => write!(f, "SVCB"),
+            65 => write!(f, "HTTPS"),
+            255 => write!(f, "ANY"),
+            other => write!(f, "TYPE{}", other),
+        }
+    }
+}
+
+impl std::fmt::Display for DnsRcode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            0 => write!(f, "NOERROR"),
+            1 => write!(f, "FORMERR"),
+            2 => write!(f, "SERVFAIL"),
+            3 => write!(f, "NXDOMAIN"),
+            4 => write!(f, "NOTIMP"),
+            5 => write!(f, "REFUSED"),
+            other => write!(f, "RCODE{}", other),
+        }
+    }
+}
+
+impl std::fmt::Display for DnsInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let questions = self
+            .questions
+            .iter()
+            .map(|question| format!("{} {}", question.qtype, question.name))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        if !self.response {
+            return write!(f, "{} {:#06x} {}", "DNS?".cyan(), self.id, questions);
+        }
+
+        write!(
+            f,
+            "{} {:#06x} {} {}",
+            "DNS".cyan(),
+            self.id,
+            self.rcode,
+            questions
+        )?;
+        if !self.answers.is_empty() {
+            let answers = self
+                .answers
+                .iter()
+                .map(|answer| {
+                    format!(
+                        "{} {} {} {}",
+                        answer.name, answer.ttl, answer.rtype, answer.data
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            write!(f, " {} {}", "=>".blue(), answers)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// It builds a response to `www.example.com A`, answered by a CNAME to `example.com` and an
+    /// A record, both with compressed names, and an AAAA record in the additional section.
+    fn response() -> Vec<u8> {
+        let mut message = vec![0xbe, 0xef, 0x81, 0x80, 0, 1, 0, 2, 0, 0, 0, 1];
+        message.extend_from_slice(b"\x03www\x07example\x03com\x00\x00\x01\x00\x01");
+        message.extend_from_slice(&[0xc0, 12, 0, 5, 0, 1, 0, 0, 0, 60, 0, 2, 0xc0, 16]);
+        message.extend_from_slice(&[0xc0, 16, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 93, 184, 216, 34]);
+        message.extend_from_slice(&[0xc0, 16, 0, 28, 0, 1, 0, 0, 0, 60, 0, 16]);
+        message.extend_from_slice(&[0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        message
+    }
+
+    #[test]
+    fn parses_compressed_responses() {
+        let dns = DnsInfo::parse(&response()).unwrap();
+
+        assert_eq!(dns.id, 0xbeef);
+        assert!(dns.response);
+        assert_eq!(dns.rcode, DnsRcode(0));
+        assert_eq!(dns.names().collect::<Vec<_>>(), ["www.example.com"]);
+        assert_eq!(dns.questions[0].qtype, DnsType(1));
+        assert_eq!(dns.answers.len(), 2);
+        assert_eq!(dns.answers[0].rtype, DnsType(5));
+        assert_eq!(dns.answers[0].data, "example.com");
+        assert_eq!(dns.answers[1].name, "example.com");
+        assert_eq!(dns.answers[1].data, "93.184.216.34");
+    }
+
+    #[test]
+    fn parses_messages_carried_by_tcp() {
+        let message = response();
+        let mut segment = (message.len() as u16).to_be_bytes().to_vec();
+        segment.extend_from_slice(&message);
+
+        let dns = DnsInfo::parse_tcp(&segment).unwrap();
+        assert_eq!(dns.answers[1].data, "93.184.216.34");
+    }
+
+    #[test]
+    fn rejects_looping_names() {
+        let mut message = vec![0, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+        message.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1]);
+
+        assert!(DnsInfo::parse(&message).is_none());
+    }
+
+    #[test]
+    fn finds_the_addresses_of_all_sections() {
+        let message = response();
+        let ranges = address_ranges(&message);
+
+        assert_eq!(ranges.len(), 2);
+        assert_eq!(message[ranges[0].clone()], [93, 184, 216, 34]);
+        assert_eq!(ranges[1].len(), 16);
+        assert_eq!(ranges[1].end, message.len());
+    }
+}
@@ -0,0 +1,115 @@
+use colored::Colorize;
+
+/// Methods recognized at the beginning of an HTTP/1.x request line.
+const METHODS: [&str; 9] = [
+    "GET", "POST", "PUT", "DELETE", "HEAD", "OPTIONS", "PATCH", "CONNECT", "TRACE",
+];
+
+/// `HttpInfo` contains the request line and the host of a cleartext HTTP/1.x request.
+///
+/// Properties:
+///
+/// * `method`: The method of the request.
+/// * `host`: The value of the `Host` header, if it is part of the packet.
+/// * `path`: The target of the request.
+pub struct HttpInfo {
+    pub method: String,
+    pub host: Option<String>,
+    pub path: String,
+}
+
+impl HttpInfo {
+    /// It parses the beginning of an HTTP/1.x request.
+    ///
+    /// Arguments:
+    ///
+    /// * `data`: The TCP payload.
+    ///
+    /// Returns:
+    ///
+    /// An `HttpInfo` if the payload starts with an HTTP/1.x request line.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let method = METHODS.iter().find(|method| {
+            data.starts_with(method.as_bytes()) && data.get(method.len()) == Some(&b' ')
+        })?;
+
+        let head = match data.windows(4).position(|window| window == b"\r\n\r\n") {
+            Some(end) => &data[..end],
+            None => data,
+        };
+        let head = String::from_utf8_lossy(head);
+        let mut lines = head.split("\r\n");
+
+        let mut request_line = lines.next()?.split(' ');
+        let path = request_line.nth(1)?;
+        if !request_line.next()?.starts_with("HTTP/1.") {
+            return None;
+        }
+
+        let host = lines.find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.eq_ignore_ascii_case("host")
+                .then(|| value.trim().to_string())
+        });
+
+        Some(HttpInfo {
+            method: method.to_string(),
+            host,
+            path: path.to_string(),
+        })
+    }
+
+    /// It returns the host of the request without its port.
+    pub fn hostname(&self) -> Option<&str> {
+        let host = self.host.as_deref()?;
+
+        // Bracketed IPv6 literals contain colons which are not a port separator.
+        if let Some(end) = host.find(']') {
+            return Some(&host[..=end]);
+        }
+        Some(host.split_once(':').map_or(host, |(name, _)| name))
+    }
+}
+
+impl std::fmt::Display for HttpInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {}{}",
+            "HTTP".cyan(),
+            self.method,
+            self.host.as_deref().unwrap_or(""),
+            self.path
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_request_lines_and_hosts() {
+        let http =
+            HttpInfo::parse(b"GET /index.html HTTP/1.1\r\nhost: example.com:8080\r\n\r\n").unwrap();
+
+        assert_eq!(http.method, "GET");
+        assert_eq!(http.path, "/index.html");
+        assert_eq!(http.host.as_deref(), Some("example.com:8080"));
+        assert_eq!(http.hostname(), Some("example.com"));
+    }
+
+    #[test]
+    fn keeps_the_colons_of_ipv6_hosts() {
+        let http = HttpInfo::parse(b"POST / HTTP/1.0\r\nHost: [2001:db8::1]:80\r\n").unwrap();
+
+        assert_eq!(http.hostname(), Some("[2001:db8::1]"));
+    }
+
+    #[test]
+    fn rejects_other_payloads() {
+        assert!(HttpInfo::parse(b"GETTING / HTTP/1.1\r\n").is_none());
+        assert!(HttpInfo::parse(b"GET / SPDY/3\r\n").is_none());
+        assert!(HttpInfo::parse(b"\x16\x03\x01").is_none());
+    }
+}
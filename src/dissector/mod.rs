@@ -0,0 +1,103 @@
+pub mod arp;
+pub mod dhcp;
+pub mod dns;
+pub mod http;
+pub mod tls;
+
+use dhcp::DhcpInfo;
+use dns::DnsInfo;
+use http::HttpInfo;
+use tls::TlsInfo;
+
+/// `Transport` is the transport layer protocol an application payload was carried by.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Transport {
+    Tcp,
+    Udp,
+}
+
+/// `Application` contains the decoded application layer of a packet, if its protocol is known.
+pub enum Application {
+    Dhcp(DhcpInfo),
+    Dns(DnsInfo),
+    Http(HttpInfo),
+    Tls(TlsInfo),
+}
+
+/// It tries to decode the application layer of a packet based on its transport ports.
+///
+/// Arguments:
+///
+/// * `transport`: The transport layer protocol of the packet.
+/// * `src_port`: The source port of the packet.
+/// * `dst_port`: The destination port of the packet.
+/// * `payload`: The transport layer payload.
+///
+/// Returns:
+///
+/// The decoded application layer, if any.
+pub fn dissect(
+    transport: Transport,
+    src_port: u16,
+    dst_port: u16,
+    payload: &[u8],
+) -> Option<Application> {
+    if payload.is_empty() {
+        return None;
+    }
+
+    if transport == Transport::Udp {
+        let is_dhcp = |ports: &[u16]| ports.contains(&src_port) && ports.contains(&dst_port);
+        if is_dhcp(&dhcp::PORTS) {
+            return DhcpInfo::parse(payload).map(Application::Dhcp);
+        }
+        if is_dhcp(&dhcp::PORTS_V6) {
+            return DhcpInfo::parse_v6(payload).map(Application::Dhcp);
+        }
+    }
+
+    let is_dns = |port| dns::PORTS.contains(&port);
+    if is_dns(src_port) || is_dns(dst_port) {
+        let dns = match transport {
+            Transport::Udp => DnsInfo::parse(payload),
+            Transport::Tcp => DnsInfo::parse_tcp(payload),
+        };
+        return dns.map(Application::Dns);
+    }
+
+    // HTTP requests and TLS handshakes are recognized by their content since they are commonly
+    // served on non-standard ports.
+    if transport == Transport::Tcp {
+        if let Some(tls) = TlsInfo::parse(payload) {
+            return Some(Application::Tls(tls));
+        }
+        if let Some(http) = HttpInfo::parse(payload) {
+            return Some(Application::Http(http));
+        }
+    }
+
+    None
+}
+
+impl Application {
+    /// It returns the name of the service the packet is addressed to: the `Host` header of an HTTP
+    /// request or the SNI of a TLS ClientHello.
+    pub fn host(&self) -> Option<&str> {
+        match self {
+            Application::Http(http) => http.hostname(),
+            Application::Tls(tls) => tls.sni.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Application {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Application::Dhcp(dhcp) => write!(f, "{}", dhcp),
+            Application::Dns(dns) => write!(f, "{}", dns),
+            Application::Http(http) => write!(f, "{}", http),
+            Application::Tls(tls) => write!(f, "{}", tls),
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use colored::Colorize;
+
+const CONTENT_TYPE_HANDSHAKE: u8 = 0x16;
+const HANDSHAKE_CLIENT_HELLO: u8 = 0x01;
+const EXTENSION_SERVER_NAME: u16 = 0x0000;
+const EXTENSION_ALPN: u16 = 0x0010;
+
+/// `TlsInfo` contains the fields of a TLS ClientHello identifying the requested service.
+///
+/// Properties:
+///
+/// * `sni`: The server name of the Server Name Indication extension.
+/// * `alpn`: The protocols offered in the Application-Layer Protocol Negotiation extension.
+pub struct TlsInfo {
+    pub sni: Option<String>,
+    pub alpn: Vec<String>,
+}
+
+/// `Reader` is a cursor over a byte slice reading big-endian, length-prefixed TLS fields.
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.data.len() < len {
+            return None;
+        }
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        let bytes = self.bytes(2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u24(&mut self) -> Option<usize> {
+        let bytes = self.bytes(3)?;
+        Some(u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]) as usize)
+    }
+
+    /// It reads a field prefixed by its length on one byte.
+    fn vec8(&mut self) -> Option<Reader<'a>> {
+        let len = self.u8()? as usize;
+        Some(Reader {
+            data: self.bytes(len)?,
+        })
+    }
+
+    /// It reads a field prefixed by its length on two bytes.
+    fn vec16(&mut self) -> Option<Reader<'a>> {
+        let len = self.u16()? as usize;
+        Some(Reader {
+            data: self.bytes(len)?,
+        })
+    }
+
+    /// It reads a field prefixed by its length on two bytes, keeping what is available when the
+    /// field is cut by the end of the packet.
+    fn vec16_partial(&mut self) -> Option<Reader<'a>> {
+        let len = (self.u16()? as usize).min(self.data.len());
+        Some(Reader {
+            data: self.bytes(len)?,
+        })
+    }
+}
+
+impl TlsInfo {
+    /// It parses a TLS record containing a ClientHello.
+    ///
+    /// Arguments:
+    ///
+    /// * `data`: The TCP payload.
+    ///
+    /// Returns:
+    ///
+    /// A `TlsInfo` if the payload starts with a ClientHello. Extensions cut by the end of the
+    /// segment are ignored.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let mut record = Reader { data };
+
+        if record.u8()? != CONTENT_TYPE_HANDSHAKE || record.u8()? != 0x03 {
+            return None;
+        }
+        record.bytes(1)?;
+        let mut handshake = record.vec16_partial()?;
+
+        if handshake.u8()? != HANDSHAKE_CLIENT_HELLO {
+            return None;
+        }
+        let len = handshake.u24()?.min(handshake.data.len());
+        let mut hello = Reader {
+            data: handshake.bytes(len)?,
+        };
+
+        // Legacy version, random, session ID, cipher suites and compression methods.
+        hello.bytes(2 + 32)?;
+        hello.vec8()?;
+        hello.vec16()?;
+        hello.vec8()?;
+
+        let mut info = TlsInfo {
+            sni: None,
+            alpn: Vec::new(),
+        };
+        let Some(mut extensions) = hello.vec16_partial() else {
+            return Some(info);
+        };
+
+        while let (Some(kind), Some(mut extension)) = (extensions.u16(), extensions.vec16()) {
+            let Some(mut list) = extension.vec16() else {
+                continue;
+            };
+
+            match kind {
+                EXTENSION_SERVER_NAME => {
+                    while let (Some(kind), Some(name)) = (list.u8(), list.vec16()) {
+                        if kind == 0 {
+                            info.sni = Some(String::from_utf8_lossy(name.data).into_owned());
+                        }
+                    }
+                }
+                EXTENSION_ALPN => {
+                    while let Some(protocol) = list.vec8() {
+                        info.alpn
+                            .push(String::from_utf8_lossy(protocol.data).into_owned());
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Some(info)
+    }
+}
+
+impl std::fmt::Display for TlsInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} ClientHello {}",
+            "TLS".cyan(),
+            self.sni.as_deref().unwrap_or("-")
+        )?;
+        if !self.alpn.is_empty() {
+            write!(f, " [{}]", self.alpn.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// It prefixes a field with its length on two bytes.
+    fn vec16(data: &[u8]) -> Vec<u8> {
+        let mut field = (data.len() as u16).to_be_bytes().to_vec();
+        field.extend_from_slice(data);
+        field
+    }
+
+    /// It builds a record holding a ClientHello for `example.com`, offering `h2` and `http/1.1`.
+    fn client_hello() -> Vec<u8> {
+        let mut name = vec![0];
+        name.extend_from_slice(&vec16(b"example.com"));
+        let mut extensions = vec![0, 0];
+        extensions.extend_from_slice(&vec16(&vec16(&name)));
+        extensions.extend_from_slice(&[0, 0x10]);
+        extensions.extend_from_slice(&vec16(&vec16(b"\x02h2\x08http/1.1")));
+
+        let mut hello = vec![3, 3];
+        hello.extend_from_slice(&[0; 32]);
+        hello.push(0);
+        hello.extend_from_slice(&vec16(&[0x13, 0x01]));
+        hello.extend_from_slice(&[1, 0]);
+        hello.extend_from_slice(&vec16(&extensions));
+
+        let mut handshake = vec![HANDSHAKE_CLIENT_HELLO];
+        handshake.extend_from_slice(&(hello.len() as u32).to_be_bytes()[1..]);
+        handshake.extend_from_slice(&hello);
+        let mut record = vec![CONTENT_TYPE_HANDSHAKE, 3, 1];
+        record.extend_from_slice(&vec16(&handshake));
+        record
+    }
+
+    #[test]
+    fn parses_server_names_and_protocols() {
+        let tls = TlsInfo::parse(&client_hello()).unwrap();
+
+        assert_eq!(tls.sni.as_deref(), Some("example.com"));
+        assert_eq!(tls.alpn, ["h2", "http/1.1"]);
+    }
+
+    #[test]
+    fn keeps_the_extensions_before_the_end_of_the_segment() {
+        let record = client_hello();
+        let tls = TlsInfo::parse(&record[..record.len() - 4]).unwrap();
+
+        assert_eq!(tls.sni.as_deref(), Some("example.com"));
+        assert!(tls.alpn.is_empty());
+    }
+
+    #[test]
+    fn rejects_other_records() {
+        let mut record = client_hello();
+        record[5] = 0x02;
+
+        assert!(TlsInfo::parse(&record).is_none());
+        assert!(TlsInfo::parse(b"GET / HTTP/1.1\r\n").is_none());
+    }
+}
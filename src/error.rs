@@ -0,0 +1,129 @@
+use std::fmt::{self, Display};
+use std::io;
+
+/// Exit codes of the sniffer, shown by `--help`.
+pub const EXIT_CODES: &str = "Exit codes:
+  0    The capture ended
+  2    Invalid command line option
+  65   Invalid blacklist
+  69   Capture error, such as a missing or lost network interface
+  70   Internal error
+  74   I/O error, such as an unreadable input file or an unwritable log, output or savefile
+  76   gRPC error, such as an unreachable server or a broken stream
+  78   Invalid configuration
+  128+ Killed by a second SIGINT (130) or SIGTERM (143)";
+
+/// Exit code of the sniffer when it panics, which is a bug.
+pub const EXIT_INTERNAL: i32 = 70;
+
+/// `Error` is what can make the sniffer fail, each kind with its own exit code, so that scripts
+/// supervising it can tell a bad configuration from a lost network interface.
+#[derive(Debug)]
+pub enum Error {
+    /// Conflicting or invalid options, or an invalid configuration file.
+    Config(String),
+    /// A blacklist file or table which could not be read or parsed.
+    Blacklist(String),
+    /// A capture which could not be opened or read, such as a missing network interface.
+    Capture(pcap::Error),
+    /// A file or a socket which could not be opened, read or written.
+    Io(io::Error),
+    /// A gRPC server which could not be reached, or a stream which broke.
+    Grpc(String),
+    /// A thread which panicked, which is a bug.
+    Internal(String),
+}
+
+impl Error {
+    /// It returns the exit code of the sniffer when it fails with this error, as listed by
+    /// `EXIT_CODES`.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Config(_) => 78,
+            Error::Blacklist(_) => 65,
+            Error::Capture(_) => 69,
+            Error::Io(_) => 74,
+            Error::Grpc(_) => 76,
+            Error::Internal(_) => EXIT_INTERNAL,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Config(err) => write!(f, "Invalid configuration: {}", err),
+            Error::Blacklist(err) => write!(f, "Invalid blacklist: {}", err),
+            Error::Capture(err) => write!(f, "Capture error: {}", err),
+            Error::Io(err) => write!(f, "I/O error: {}", err),
+            Error::Grpc(err) => write!(f, "gRPC error: {}", err),
+            Error::Internal(err) => write!(f, "Internal error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Capture(err) => Some(err),
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<pcap::Error> for Error {
+    fn from(err: pcap::Error) -> Self {
+        Error::Capture(err)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<clap::Error> for Error {
+    fn from(err: clap::Error) -> Self {
+        Error::Config(err.to_string())
+    }
+}
+
+impl From<tonic::transport::Error> for Error {
+    fn from(err: tonic::transport::Error) -> Self {
+        Error::Grpc(err.to_string())
+    }
+}
+
+impl From<tonic::Status> for Error {
+    fn from(status: tonic::Status) -> Self {
+        Error::Grpc(status.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_kind_of_error_has_its_documented_exit_code() {
+        let errors = [
+            (Error::Config("conflict".to_string()), 78),
+            (Error::Blacklist("unreadable".to_string()), 65),
+            (Error::from(pcap::Error::NoMorePackets), 69),
+            (Error::from(io::Error::other("unwritable")), 74),
+            (Error::from(tonic::Status::unavailable("unreachable")), 76),
+            (Error::Internal("panicked".to_string()), EXIT_INTERNAL),
+        ];
+
+        for (err, code) in errors {
+            assert_eq!(err.exit_code(), code, "{}", err);
+            assert!(
+                EXIT_CODES.contains(&format!("\n  {} ", code)),
+                "{} is not documented",
+                code
+            );
+        }
+    }
+}
@@ -0,0 +1,441 @@
+use crate::flow::{EndReason, FlowCounters, FlowRecord};
+use clap::ValueEnum;
+use std::io;
+use std::net::{IpAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// `ExportProtocol` is the protocol used to export flows to a collector.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum ExportProtocol {
+    Ipfix,
+    Netflow9,
+}
+
+/// Template IDs of the IPv4 and IPv6 flow records.
+const TEMPLATE_IPV4: u16 = 256;
+const TEMPLATE_IPV6: u16 = 257;
+
+/// Templates are sent again after this interval, since the collector may have been restarted and
+/// UDP gives no way to know it.
+const TEMPLATE_REFRESH: Duration = Duration::from_secs(60);
+
+/// Maximum size of the data records of an export packet, leaving room for the message header,
+/// the templates and the set headers below the usual path MTU.
+const MAX_RECORDS_SIZE: usize = 1400 - 256;
+
+/// Information elements shared by IPFIX and NetFlow v9.
+const OCTET_DELTA_COUNT: u16 = 1;
+const PACKET_DELTA_COUNT: u16 = 2;
+const PROTOCOL_IDENTIFIER: u16 = 4;
+const TCP_CONTROL_BITS: u16 = 6;
+const SOURCE_TRANSPORT_PORT: u16 = 7;
+const SOURCE_IPV4_ADDRESS: u16 = 8;
+const DESTINATION_TRANSPORT_PORT: u16 = 11;
+const DESTINATION_IPV4_ADDRESS: u16 = 12;
+const LAST_SWITCHED: u16 = 21;
+const FIRST_SWITCHED: u16 = 22;
+const SOURCE_IPV6_ADDRESS: u16 = 27;
+const DESTINATION_IPV6_ADDRESS: u16 = 28;
+const FLOW_END_REASON: u16 = 136;
+const FLOW_START_MILLISECONDS: u16 = 152;
+const FLOW_END_MILLISECONDS: u16 = 153;
+
+/// `Exporter` sends expired flow records to an IPFIX or NetFlow v9 collector over UDP. Each
+/// direction of a bidirectional flow is exported as its own record.
+///
+/// Properties:
+///
+/// * `socket`: The UDP socket connected to the collector.
+/// * `protocol`: The export protocol.
+/// * `sequence`: The sequence number of the next message, counting data records for IPFIX and
+///   export packets for NetFlow v9.
+/// * `last_template`: The time the templates were last sent.
+/// * `boot`: The system uptime origin of NetFlow v9, since the UNIX epoch: the time the exporter
+///   was created, or the start of the earliest flow exported if it is older, such as the flows of
+///   a capture file.
+pub struct Exporter {
+    socket: UdpSocket,
+    protocol: ExportProtocol,
+    sequence: u32,
+    last_template: Option<Instant>,
+    boot: Duration,
+}
+
+/// `Direction` is one direction of a flow record, exported as a single record.
+struct Direction<'a> {
+    record: &'a FlowRecord,
+    src: (IpAddr, u16),
+    dst: (IpAddr, u16),
+    counters: &'a FlowCounters,
+}
+
+impl Exporter {
+    /// It creates an exporter sending to a collector.
+    ///
+    /// Arguments:
+    ///
+    /// * `collector`: The `host:port` address of the collector.
+    /// * `protocol`: The export protocol.
+    ///
+    /// Returns:
+    ///
+    /// A new instance of the Exporter struct.
+    pub fn new(collector: &str, protocol: ExportProtocol) -> io::Result<Self> {
+        let address = collector.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Could not resolve the collector address {}", collector),
+            )
+        })?;
+        let socket = match address {
+            std::net::SocketAddr::V4(_) => UdpSocket::bind("0.0.0.0:0")?,
+            std::net::SocketAddr::V6(_) => UdpSocket::bind("[::]:0")?,
+        };
+        socket.connect(address)?;
+
+        Ok(Exporter {
+            socket,
+            protocol,
+            sequence: 0,
+            last_template: None,
+            boot: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default(),
+        })
+    }
+
+    /// It exports flow records, splitting them into as many messages as needed.
+    ///
+    /// Arguments:
+    ///
+    /// * `records`: The expired flow records.
+    pub fn export(&mut self, records: &[FlowRecord]) -> io::Result<()> {
+        if let Some(first_seen) = records.iter().map(|record| record.first_seen).min() {
+            self.boot = self.boot.min(first_seen);
+        }
+        let directions = records.iter().flat_map(|record| {
+            let forward = Direction {
+                record,
+                src: record.src,
+                dst: record.dst,
+                counters: &record.forward,
+            };
+            let reverse = Direction {
+                record,
+                src: record.dst,
+                dst: record.src,
+                counters: &record.reverse,
+            };
+            [forward, reverse]
+                .into_iter()
+                .filter(|direction| direction.counters.packets > 0)
+        });
+
+        let mut sets: Vec<(u16, Vec<u8>)> = Vec::new();
+        let mut size = 0;
+        let mut count = 0;
+
+        for direction in directions {
+            let template = match direction.src.0 {
+                IpAddr::V4(_) => TEMPLATE_IPV4,
+                IpAddr::V6(_) => TEMPLATE_IPV6,
+            };
+            let mut data = Vec::new();
+            for (field, len) in self.fields(template) {
+                self.write_field(&mut data, field, len, &direction);
+            }
+
+            if size + data.len() > MAX_RECORDS_SIZE {
+                self.send(&sets, count)?;
+                sets.clear();
+                size = 0;
+                count = 0;
+            }
+            size += data.len();
+            count += 1;
+            match sets.iter_mut().find(|(id, _)| *id == template) {
+                Some((_, set)) => set.extend_from_slice(&data),
+                None => sets.push((template, data)),
+            }
+        }
+
+        if count > 0 {
+            self.send(&sets, count)?;
+        }
+        Ok(())
+    }
+
+    /// It returns the fields of a template, as information element IDs and lengths.
+    fn fields(&self, template: u16) -> Vec<(u16, u16)> {
+        let mut fields = match template {
+            TEMPLATE_IPV4 => vec![(SOURCE_IPV4_ADDRESS, 4), (DESTINATION_IPV4_ADDRESS, 4)],
+            _ => vec![(SOURCE_IPV6_ADDRESS, 16), (DESTINATION_IPV6_ADDRESS, 16)],
+        };
+        fields.extend([
+            (SOURCE_TRANSPORT_PORT, 2),
+            (DESTINATION_TRANSPORT_PORT, 2),
+            (PROTOCOL_IDENTIFIER, 1),
+            (PACKET_DELTA_COUNT, 8),
+            (OCTET_DELTA_COUNT, 8),
+        ]);
+        match self.protocol {
+            ExportProtocol::Ipfix => fields.extend([
+                (TCP_CONTROL_BITS, 2),
+                (FLOW_START_MILLISECONDS, 8),
+                (FLOW_END_MILLISECONDS, 8),
+                (FLOW_END_REASON, 1),
+            ]),
+            ExportProtocol::Netflow9 => fields.extend([
+                (TCP_CONTROL_BITS, 1),
+                (FIRST_SWITCHED, 4),
+                (LAST_SWITCHED, 4),
+            ]),
+        }
+        fields
+    }
+
+    /// It encodes the value of a field of a record.
+    fn write_field(&self, data: &mut Vec<u8>, field: u16, len: u16, direction: &Direction) {
+        let record = direction.record;
+
+        match (field, len) {
+            (SOURCE_IPV4_ADDRESS | SOURCE_IPV6_ADDRESS, _) => write_ip(data, direction.src.0),
+            (DESTINATION_IPV4_ADDRESS | DESTINATION_IPV6_ADDRESS, _) => {
+                write_ip(data, direction.dst.0)
+            }
+            (SOURCE_TRANSPORT_PORT, _) => data.extend(direction.src.1.to_be_bytes()),
+            (DESTINATION_TRANSPORT_PORT, _) => data.extend(direction.dst.1.to_be_bytes()),
+            (PROTOCOL_IDENTIFIER, _) => data.push(record.protocol),
+            (PACKET_DELTA_COUNT, _) => data.extend(direction.counters.packets.to_be_bytes()),
+            (OCTET_DELTA_COUNT, _) => data.extend(direction.counters.bytes.to_be_bytes()),
+            (TCP_CONTROL_BITS, 1) => data.push(direction.counters.tcp_flags as u8),
+            (TCP_CONTROL_BITS, _) => data.extend(direction.counters.tcp_flags.to_be_bytes()),
+            (FLOW_START_MILLISECONDS, _) => {
+                data.extend((record.first_seen.as_millis() as u64).to_be_bytes())
+            }
+            (FLOW_END_MILLISECONDS, _) => {
+                data.extend((record.last_seen.as_millis() as u64).to_be_bytes())
+            }
+            (FLOW_END_REASON, _) => data.push(match record.end_reason {
+                EndReason::IdleTimeout => 0x01,
+                EndReason::ActiveTimeout => 0x02,
+                EndReason::EndOfFlow => 0x03,
+                EndReason::ForcedEnd => 0x04,
+            }),
+            (FIRST_SWITCHED, _) => data.extend(self.uptime(record.first_seen).to_be_bytes()),
+            (LAST_SWITCHED, _) => data.extend(self.uptime(record.last_seen).to_be_bytes()),
+            (_, len) => data.resize(data.len() + len as usize, 0),
+        }
+    }
+
+    /// It converts a timestamp into milliseconds of system uptime, the way NetFlow v9 expresses
+    /// flow times. Like the uptime of a router, it wraps around after 49 days, the collectors only
+    /// comparing it to the uptime of the message header.
+    fn uptime(&self, ts: Duration) -> u32 {
+        ts.saturating_sub(self.boot).as_millis() as u32
+    }
+
+    /// It builds an export packet from data sets and sends it, prefixed with the templates when
+    /// they are due.
+    ///
+    /// Arguments:
+    ///
+    /// * `sets`: The data sets, as template IDs and encoded records.
+    /// * `count`: The number of data records of the sets.
+    fn send(&mut self, sets: &[(u16, Vec<u8>)], count: u32) -> io::Result<()> {
+        let now = SystemTime::now();
+        let send_templates = match self.last_template {
+            Some(last) => last.elapsed() >= TEMPLATE_REFRESH,
+            None => true,
+        };
+
+        let mut body = Vec::new();
+        let mut records = count;
+        if send_templates {
+            let mut set = Vec::new();
+            for template in [TEMPLATE_IPV4, TEMPLATE_IPV6] {
+                let fields = self.fields(template);
+                set.extend(template.to_be_bytes());
+                set.extend((fields.len() as u16).to_be_bytes());
+                for (field, len) in fields {
+                    set.extend(field.to_be_bytes());
+                    set.extend(len.to_be_bytes());
+                }
+            }
+            let set_id: u16 = match self.protocol {
+                ExportProtocol::Ipfix => 2,
+                ExportProtocol::Netflow9 => 0,
+            };
+            self.write_set(&mut body, set_id, &set);
+            records += 2;
+            self.last_template = Some(Instant::now());
+        }
+        for (template, data) in sets {
+            self.write_set(&mut body, *template, data);
+        }
+
+        let unix_secs = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as u32;
+        let mut message = Vec::with_capacity(body.len() + 20);
+        match self.protocol {
+            ExportProtocol::Ipfix => {
+                message.extend(10u16.to_be_bytes());
+                message.extend(((body.len() + 16) as u16).to_be_bytes());
+                message.extend(unix_secs.to_be_bytes());
+                message.extend(self.sequence.to_be_bytes());
+                message.extend(0u32.to_be_bytes());
+                self.sequence = self.sequence.wrapping_add(count);
+            }
+            ExportProtocol::Netflow9 => {
+                let since_epoch = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+                message.extend(9u16.to_be_bytes());
+                message.extend((records as u16).to_be_bytes());
+                message.extend(self.uptime(since_epoch).to_be_bytes());
+                message.extend(unix_secs.to_be_bytes());
+                message.extend(self.sequence.to_be_bytes());
+                message.extend(0u32.to_be_bytes());
+                self.sequence = self.sequence.wrapping_add(1);
+            }
+        }
+        message.extend(body);
+
+        self.socket.send(&message)?;
+        Ok(())
+    }
+
+    /// It appends a set to a message, padding it to a multiple of 4 bytes for NetFlow v9.
+    fn write_set(&self, message: &mut Vec<u8>, id: u16, data: &[u8]) {
+        let padding = match self.protocol {
+            ExportProtocol::Ipfix => 0,
+            ExportProtocol::Netflow9 => (4 - data.len() % 4) % 4,
+        };
+
+        message.extend(id.to_be_bytes());
+        message.extend(((data.len() + padding + 4) as u16).to_be_bytes());
+        message.extend_from_slice(data);
+        message.resize(message.len() + padding, 0);
+    }
+}
+
+fn write_ip(data: &mut Vec<u8>, ip: IpAddr) {
+    match ip {
+        IpAddr::V4(ip) => data.extend(ip.octets()),
+        IpAddr::V6(ip) => data.extend(ip.octets()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flow::TcpState;
+    use std::net::Ipv4Addr;
+
+    /// It exports records to a local collector and returns the message it received.
+    fn export(protocol: ExportProtocol, records: &[FlowRecord]) -> Vec<u8> {
+        let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+        collector
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let address = collector.local_addr().unwrap().to_string();
+        let mut exporter = Exporter::new(&address, protocol).unwrap();
+
+        exporter.export(records).unwrap();
+        let mut message = vec![0; 65536];
+        let len = collector.recv(&mut message).unwrap();
+        message.truncate(len);
+        message
+    }
+
+    fn record(first_seen: Duration, last_seen: Duration) -> FlowRecord {
+        FlowRecord {
+            protocol: 6,
+            src: (Ipv4Addr::new(10, 0, 0, 1).into(), 40000),
+            dst: (Ipv4Addr::new(10, 0, 0, 2).into(), 443),
+            forward: FlowCounters {
+                packets: 3,
+                bytes: 180,
+                tcp_flags: 0x1b,
+            },
+            reverse: FlowCounters::default(),
+            first_seen,
+            last_seen,
+            tcp_state: Some(TcpState::Closed),
+            end_reason: EndReason::EndOfFlow,
+        }
+    }
+
+    fn u32_at(message: &[u8], offset: usize) -> u32 {
+        u32::from_be_bytes(message[offset..offset + 4].try_into().unwrap())
+    }
+
+    /// It returns the IDs and lengths of the sets following the header of a message.
+    fn sets(message: &[u8], header: usize) -> Vec<(u16, usize)> {
+        let mut sets = Vec::new();
+        let mut offset = header;
+        while offset < message.len() {
+            let id = u16::from_be_bytes([message[offset], message[offset + 1]]);
+            let len = u16::from_be_bytes([message[offset + 2], message[offset + 3]]) as usize;
+            sets.push((id, len));
+            offset += len;
+        }
+        assert_eq!(offset, message.len());
+        sets
+    }
+
+    #[test]
+    fn ipfix_messages_hold_their_templates_and_records() {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let message = export(ExportProtocol::Ipfix, &[record(now, now)]);
+
+        assert_eq!(u16::from_be_bytes([message[0], message[1]]), 10);
+        assert_eq!(
+            u16::from_be_bytes([message[2], message[3]]) as usize,
+            message.len()
+        );
+        let ids: Vec<u16> = sets(&message, 16).iter().map(|(id, _)| *id).collect();
+        assert_eq!(ids, [2, TEMPLATE_IPV4]);
+    }
+
+    #[test]
+    fn netflow9_messages_count_their_templates_and_records() {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let mut answered = record(now, now);
+        answered.reverse = FlowCounters {
+            packets: 2,
+            bytes: 120,
+            tcp_flags: 0x12,
+        };
+        let message = export(ExportProtocol::Netflow9, &[answered]);
+
+        assert_eq!(u16::from_be_bytes([message[0], message[1]]), 9);
+        // Both templates, then a record per direction.
+        assert_eq!(u16::from_be_bytes([message[2], message[3]]), 4);
+        let sets = sets(&message, 20);
+        assert_eq!(sets[0].0, 0);
+        assert_eq!(sets[1].0, TEMPLATE_IPV4);
+        assert!(sets.iter().all(|(_, len)| len % 4 == 0));
+    }
+
+    #[test]
+    fn netflow9_times_of_old_flows_do_not_wrap() {
+        // A flow of a capture file read a year after it was captured.
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let first_seen = now - Duration::from_secs(365 * 86400);
+        let last_seen = first_seen + Duration::from_secs(2);
+        let message = export(ExportProtocol::Netflow9, &[record(first_seen, last_seen)]);
+
+        // The data set follows the header and the template set.
+        let templates = u16::from_be_bytes([message[22], message[23]]) as usize;
+        let record = 20 + templates + 4;
+        let fields = 4 + 4 + 2 + 2 + 1 + 8 + 8 + 1;
+        let (first, last) = (
+            u32_at(&message, record + fields),
+            u32_at(&message, record + fields + 4),
+        );
+
+        assert_eq!(first, 0);
+        assert_eq!(last, 2000);
+        let uptime = u32_at(&message, 4) as u64;
+        let expected = (now - first_seen).as_millis() as u64 % (1 << 32);
+        assert!(uptime >= expected && uptime - expected < 5000);
+    }
+}
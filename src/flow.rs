@@ -0,0 +1,459 @@
+use crate::packet::PacketInfo;
+use colored::Colorize;
+use pnet::packet::tcp::TcpFlags;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// Interval between two sweeps of the table looking for expired flows, in the time of the capture.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Fraction of the flows ended at once, the least recently seen first, when the table is full and
+/// none of its flows has expired.
+const EVICTION_SHARE: usize = 16;
+
+/// `FlowKey` identifies a bidirectional flow. The endpoints are sorted so that both directions of
+/// a conversation share the same key.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct FlowKey {
+    pub protocol: u8,
+    pub lower: (IpAddr, u16),
+    pub upper: (IpAddr, u16),
+}
+
+/// `TcpState` is the state of a TCP connection as seen from the packets of its flow.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TcpState {
+    SynSent,
+    SynReceived,
+    Established,
+    Closing,
+    Closed,
+    Reset,
+}
+
+/// `EndReason` tells why a flow record was emitted.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EndReason {
+    IdleTimeout,
+    ActiveTimeout,
+    EndOfFlow,
+    ForcedEnd,
+}
+
+/// `FlowCounters` contains the traffic counters of one direction of a flow.
+///
+/// Properties:
+///
+/// * `packets`: The number of packets.
+/// * `bytes`: The number of bytes on the wire.
+/// * `tcp_flags`: The union of the TCP flags seen.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct FlowCounters {
+    pub packets: u64,
+    pub bytes: u64,
+    pub tcp_flags: u16,
+}
+
+/// `FlowRecord` describes a flow, or a part of it for long-lived flows, once it has expired.
+///
+/// Properties:
+///
+/// * `protocol`: The protocol number of the IP payload.
+/// * `src`: The address and port of the endpoint which sent the first packet of the flow.
+/// * `dst`: The address and port of the other endpoint.
+/// * `forward`: The counters of the packets sent by `src`.
+/// * `reverse`: The counters of the packets sent by `dst`.
+/// * `first_seen`: The timestamp of the first packet, since the UNIX epoch.
+/// * `last_seen`: The timestamp of the last packet, since the UNIX epoch.
+/// * `tcp_state`: The state of the connection if the flow is a TCP flow.
+/// * `end_reason`: The reason why the record was emitted.
+#[derive(Clone, Debug)]
+pub struct FlowRecord {
+    pub protocol: u8,
+    pub src: (IpAddr, u16),
+    pub dst: (IpAddr, u16),
+    pub forward: FlowCounters,
+    pub reverse: FlowCounters,
+    pub first_seen: Duration,
+    pub last_seen: Duration,
+    pub tcp_state: Option<TcpState>,
+    pub end_reason: EndReason,
+}
+
+/// `FlowTable` groups packets into bidirectional flows by 5-tuple and emits a record for each
+/// flow that expires.
+///
+/// Properties:
+///
+/// * `flows`: The active flows, indexed by their key.
+/// * `idle_timeout`: The time after which a flow without packets expires.
+/// * `active_timeout`: The time after which a flow still receiving packets is reported and its
+///   counters reset.
+/// * `max_flows`: The maximum number of active flows, so that a flood or a scan can't grow the
+///   table without bound.
+/// * `last_sweep`: The time of the last sweep of the table.
+pub struct FlowTable {
+    flows: HashMap<FlowKey, FlowRecord>,
+    idle_timeout: Duration,
+    active_timeout: Duration,
+    max_flows: usize,
+    last_sweep: Duration,
+}
+
+impl FlowTable {
+    /// It creates an empty flow table.
+    ///
+    /// Arguments:
+    ///
+    /// * `idle_timeout`: The time after which a flow without packets expires.
+    /// * `active_timeout`: The time after which a flow still receiving packets is reported.
+    /// * `max_flows`: The maximum number of active flows, at least 1.
+    ///
+    /// Returns:
+    ///
+    /// A new instance of the FlowTable struct.
+    pub fn new(idle_timeout: Duration, active_timeout: Duration, max_flows: usize) -> Self {
+        FlowTable {
+            flows: HashMap::new(),
+            idle_timeout,
+            active_timeout,
+            max_flows: max_flows.max(1),
+            last_sweep: Duration::ZERO,
+        }
+    }
+
+    /// It accounts a packet in its flow, creating the flow if needed. The flows timed out are
+    /// expired by `sweep`, which a quiet capture calls without packets.
+    ///
+    /// Arguments:
+    ///
+    /// * `info`: The parsed packet. Packets without IP addresses are not part of any flow.
+    /// * `ts`: The timestamp of the packet, since the UNIX epoch.
+    /// * `len`: The length of the packet on the wire.
+    ///
+    /// Returns:
+    ///
+    /// The records of the flows that were ended to make room for a new flow.
+    pub fn update(&mut self, info: &PacketInfo, ts: Duration, len: u32) -> Vec<FlowRecord> {
+        let mut records = Vec::new();
+
+        if let (Some(src_ip), Some(dst_ip), Some(protocol)) =
+            (info.src_ip, info.dst_ip, info.ip_protocol)
+        {
+            let src = (src_ip, info.src_port.unwrap_or(0));
+            let dst = (dst_ip, info.dst_port.unwrap_or(0));
+            let key = FlowKey {
+                protocol,
+                lower: src.min(dst),
+                upper: src.max(dst),
+            };
+            if self.flows.len() >= self.max_flows && !self.flows.contains_key(&key) {
+                records = self.evict(ts);
+            }
+
+            let flow = self.flows.entry(key).or_insert_with(|| FlowRecord {
+                protocol,
+                src,
+                dst,
+                forward: FlowCounters::default(),
+                reverse: FlowCounters::default(),
+                first_seen: ts,
+                last_seen: ts,
+                tcp_state: None,
+                end_reason: EndReason::IdleTimeout,
+            });
+
+            let forward = flow.src == src;
+            let counters = if forward {
+                &mut flow.forward
+            } else {
+                &mut flow.reverse
+            };
+            counters.packets += 1;
+            counters.bytes += len as u64;
+            flow.last_seen = flow.last_seen.max(ts);
+
+            if let Some(flags) = info.tcp_flags {
+                counters.tcp_flags |= flags;
+                let mut state = Self::next_state(flow.tcp_state, flags, forward);
+
+                let fin = |counters: &FlowCounters| counters.tcp_flags & TcpFlags::FIN != 0;
+                if state == TcpState::Closing && fin(&flow.forward) && fin(&flow.reverse) {
+                    state = TcpState::Closed;
+                }
+                flow.tcp_state = Some(state);
+            }
+        }
+        records
+    }
+
+    /// It expires the flows timed out, at most once per sweep interval, so that it can be called
+    /// for each packet and on each tick of the capture.
+    ///
+    /// Arguments:
+    ///
+    /// * `now`: The current time, since the UNIX epoch: the wall-clock time when capturing on a
+    ///   network interface, the time of the last packet when reading a file.
+    ///
+    /// Returns:
+    ///
+    /// The records of the flows that expired.
+    pub fn sweep(&mut self, now: Duration) -> Vec<FlowRecord> {
+        if now.saturating_sub(self.last_sweep) < SWEEP_INTERVAL {
+            return Vec::new();
+        }
+        self.last_sweep = now;
+        self.expire(now)
+    }
+
+    /// It makes room in a full table, expiring the flows timed out and, if too few did, ending the
+    /// least recently seen flows, so that the next new flows don't sweep the table again.
+    ///
+    /// Arguments:
+    ///
+    /// * `now`: The current time, since the UNIX epoch.
+    ///
+    /// Returns:
+    ///
+    /// The records of the flows removed.
+    fn evict(&mut self, now: Duration) -> Vec<FlowRecord> {
+        let mut records = self.expire(now);
+        let room = (self.max_flows / EVICTION_SHARE).max(1);
+        if self.flows.len() + room <= self.max_flows {
+            return records;
+        }
+
+        let count = self.flows.len() + room - self.max_flows;
+        let mut oldest: Vec<(Duration, FlowKey)> = self
+            .flows
+            .iter()
+            .map(|(key, flow)| (flow.last_seen, *key))
+            .collect();
+        oldest.select_nth_unstable_by_key(count - 1, |(last_seen, _)| *last_seen);
+        for (_, key) in &oldest[..count] {
+            if let Some(mut flow) = self.flows.remove(key) {
+                flow.end_reason = EndReason::ForcedEnd;
+                records.push(flow);
+            }
+        }
+        records
+    }
+
+    /// It removes the flows which are closed or timed out.
+    ///
+    /// Arguments:
+    ///
+    /// * `now`: The current time, since the UNIX epoch.
+    ///
+    /// Returns:
+    ///
+    /// The records of the flows that expired.
+    pub fn expire(&mut self, now: Duration) -> Vec<FlowRecord> {
+        let mut records = Vec::new();
+        let (idle_timeout, active_timeout) = (self.idle_timeout, self.active_timeout);
+
+        self.flows.retain(|_, flow| {
+            let end_reason = if matches!(flow.tcp_state, Some(TcpState::Closed | TcpState::Reset)) {
+                EndReason::EndOfFlow
+            } else if now.saturating_sub(flow.last_seen) >= idle_timeout {
+                EndReason::IdleTimeout
+            } else if now.saturating_sub(flow.first_seen) >= active_timeout {
+                // Long-lived flows are reported periodically and keep being tracked.
+                let mut record = flow.clone();
+                record.end_reason = EndReason::ActiveTimeout;
+                records.push(record);
+
+                flow.forward = FlowCounters::default();
+                flow.reverse = FlowCounters::default();
+                flow.first_seen = now;
+                return true;
+            } else {
+                return true;
+            };
+
+            let mut record = flow.clone();
+            record.end_reason = end_reason;
+            records.push(record);
+            false
+        });
+        records
+    }
+
+    /// It removes all the flows, for instance when the capture ends.
+    ///
+    /// Returns:
+    ///
+    /// The records of all the flows.
+    pub fn flush(&mut self) -> Vec<FlowRecord> {
+        self.flows
+            .drain()
+            .map(|(_, mut flow)| {
+                flow.end_reason = EndReason::ForcedEnd;
+                flow
+            })
+            .collect()
+    }
+
+    /// It computes the state of a TCP connection after one of its segments.
+    ///
+    /// Arguments:
+    ///
+    /// * `state`: The state of the connection, `None` for the first segment of the flow.
+    /// * `flags`: The flags of the segment.
+    /// * `forward`: Whether the segment was sent by the endpoint which opened the flow.
+    ///
+    /// Returns:
+    ///
+    /// The new state of the connection.
+    fn next_state(state: Option<TcpState>, flags: u16, forward: bool) -> TcpState {
+        let has = |flag: u16| flags & flag != 0;
+
+        if has(TcpFlags::RST) {
+            return TcpState::Reset;
+        }
+        match state {
+            None if has(TcpFlags::SYN) && !has(TcpFlags::ACK) => TcpState::SynSent,
+            // The capture started in the middle of the connection.
+            None => TcpState::Established,
+            Some(TcpState::SynSent) if !forward && has(TcpFlags::SYN) && has(TcpFlags::ACK) => {
+                TcpState::SynReceived
+            }
+            Some(TcpState::SynReceived) if forward && has(TcpFlags::ACK) => TcpState::Established,
+            // The connection is closed once both sides sent a FIN, which is checked on the
+            // counters of the flow.
+            Some(TcpState::SynSent | TcpState::SynReceived | TcpState::Established)
+                if has(TcpFlags::FIN) =>
+            {
+                TcpState::Closing
+            }
+            Some(state) => state,
+        }
+    }
+}
+
+impl std::fmt::Display for TcpState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = match self {
+            TcpState::SynSent => "SYN_SENT",
+            TcpState::SynReceived => "SYN_RECEIVED",
+            TcpState::Established => "ESTABLISHED",
+            TcpState::Closing => "CLOSING",
+            TcpState::Closed => "CLOSED",
+            TcpState::Reset => "RESET",
+        };
+        write!(f, "{}", state)
+    }
+}
+
+impl std::fmt::Display for EndReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = match self {
+            EndReason::IdleTimeout => "idle timeout",
+            EndReason::ActiveTimeout => "active timeout",
+            EndReason::EndOfFlow => "end of flow",
+            EndReason::ForcedEnd => "forced end",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+impl std::fmt::Display for FlowRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[{}] {}{}{} {} {}{}{} {} pkts/{} bytes {} {} pkts/{} bytes ({:.3}s",
+            pnet::packet::ip::IpNextHeaderProtocol(self.protocol),
+            self.src.0,
+            ":".red(),
+            self.src.1,
+            "<->".blue(),
+            self.dst.0,
+            ":".red(),
+            self.dst.1,
+            self.forward.packets,
+            self.forward.bytes,
+            "/".blue(),
+            self.reverse.packets,
+            self.reverse.bytes,
+            self.last_seen.saturating_sub(self.first_seen).as_secs_f64(),
+        )?;
+        if let Some(state) = self.tcp_state {
+            write!(f, ", {}", state)?;
+        }
+        write!(f, ", {})", self.end_reason)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::Protocol;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use pnet::util::MacAddr;
+    use std::net::Ipv4Addr;
+
+    fn udp(src_port: u16) -> PacketInfo {
+        PacketInfo {
+            protocol: Protocol::Ip(IpNextHeaderProtocols::Udp),
+            src_mac: MacAddr::zero(),
+            dst_mac: MacAddr::zero(),
+            src_ip: Some(Ipv4Addr::new(10, 0, 0, 1).into()),
+            dst_ip: Some(Ipv4Addr::new(10, 0, 0, 2).into()),
+            src_port: Some(src_port),
+            dst_port: Some(53),
+            ip_protocol: Some(17),
+            tcp_flags: None,
+            len: 64,
+            arp: None,
+            app: None,
+        }
+    }
+
+    #[test]
+    fn both_directions_share_a_flow() {
+        let mut table = FlowTable::new(Duration::from_secs(15), Duration::from_secs(1800), 16);
+        let mut reply = udp(4000);
+        std::mem::swap(&mut reply.src_ip, &mut reply.dst_ip);
+        std::mem::swap(&mut reply.src_port, &mut reply.dst_port);
+
+        table.update(&udp(4000), Duration::ZERO, 100);
+        table.update(&reply, Duration::ZERO, 200);
+        let records = table.flush();
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].forward.bytes, 100);
+        assert_eq!(records[0].reverse.bytes, 200);
+    }
+
+    #[test]
+    fn idle_flows_expire() {
+        let mut table = FlowTable::new(Duration::from_secs(15), Duration::from_secs(1800), 16);
+
+        table.update(&udp(4000), Duration::from_secs(1), 100);
+        table.update(&udp(4001), Duration::from_secs(10), 100);
+        assert!(table.sweep(Duration::from_secs(10)).is_empty());
+        // No packet is needed for the flows to expire.
+        let records = table.sweep(Duration::from_secs(20));
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].src.1, 4000);
+        assert_eq!(records[0].end_reason, EndReason::IdleTimeout);
+    }
+
+    #[test]
+    fn full_table_ends_the_least_recently_seen_flows() {
+        let mut table = FlowTable::new(Duration::from_secs(15), Duration::from_secs(1800), 16);
+        let ts = Duration::from_millis(10);
+
+        for port in 0..16 {
+            assert!(table.update(&udp(port), ts * port as u32, 100).is_empty());
+        }
+        let records = table.update(&udp(100), ts * 16, 100);
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].src.1, 0);
+        assert_eq!(records[0].end_reason, EndReason::ForcedEnd);
+        assert_eq!(table.flows.len(), 16);
+    }
+}
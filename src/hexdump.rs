@@ -0,0 +1,169 @@
+use crate::packet;
+use std::fmt::Display;
+
+/// Number of bytes shown on each line of a dump.
+const LINE_LEN: usize = 16;
+
+/// `HexDump` formats the bytes of packets as offset, hex and ASCII columns, the way `hexdump -C`
+/// does.
+///
+/// Properties:
+///
+/// * `payload`: Whether only the transport layer payload is dumped, instead of the whole packet.
+/// * `limit`: The maximum number of bytes dumped.
+#[derive(Clone, Copy)]
+pub struct HexDump {
+    payload: bool,
+    limit: Option<usize>,
+}
+
+impl HexDump {
+    /// It creates a hex dump formatter.
+    ///
+    /// Arguments:
+    ///
+    /// * `payload`: Whether only the transport layer payload is dumped.
+    /// * `limit`: The maximum number of bytes dumped.
+    ///
+    /// Returns:
+    ///
+    /// A new instance of the HexDump struct.
+    pub fn new(payload: bool, limit: Option<usize>) -> Self {
+        HexDump { payload, limit }
+    }
+
+    /// It dumps a packet, or its payload. Packets without a transport layer, such as the ones
+    /// which could not be parsed, are dumped whole.
+    ///
+    /// Arguments:
+    ///
+    /// * `data`: The bytes of the packet.
+    ///
+    /// Returns:
+    ///
+    /// The indented lines of the dump, without a trailing line break.
+    pub fn dump(&self, data: &[u8]) -> String {
+        let (start, bytes) = match self.payload {
+            true => match packet::payload_offset(data) {
+                Some(offset) => (offset, &data[offset..]),
+                None => (0, data),
+            },
+            false => (0, data),
+        };
+        let len = self
+            .limit
+            .map_or(bytes.len(), |limit| limit.min(bytes.len()));
+        let shown = &bytes[..len];
+
+        let mut lines: Vec<String> = shown
+            .chunks(LINE_LEN)
+            .enumerate()
+            .map(|(index, chunk)| line(start + index * LINE_LEN, chunk))
+            .collect();
+        if shown.is_empty() {
+            lines.push("    (no payload)".to_string());
+        }
+        if shown.len() < bytes.len() {
+            lines.push(format!("    ... {} more bytes", bytes.len() - shown.len()));
+        }
+        lines.join("\n")
+    }
+}
+
+/// It formats a line of a dump, such as
+/// `    0010  00 28 00 00 40 00 40 06  00 00 0a 00 00 01 0a 00  |.(..@.@.........|`.
+///
+/// Arguments:
+///
+/// * `offset`: The offset of the first byte in the packet.
+/// * `chunk`: The bytes of the line.
+fn line(offset: usize, chunk: &[u8]) -> String {
+    let mut hex = String::with_capacity(3 * LINE_LEN + 1);
+    for index in 0..LINE_LEN {
+        if index == LINE_LEN / 2 {
+            hex.push(' ');
+        }
+        match chunk.get(index) {
+            Some(byte) => hex += &format!("{:02x} ", byte),
+            None => hex += "   ",
+        }
+    }
+    let ascii: String = chunk
+        .iter()
+        .map(|&byte| match byte {
+            0x20..=0x7e => byte as char,
+            _ => '.',
+        })
+        .collect();
+
+    format!("    {:04x}  {} |{}|", offset, hex, ascii)
+}
+
+/// `WithDump` displays a message followed by the dump of its packet, if any, on the next lines.
+pub struct WithDump<'a, T: Display>(pub T, pub Option<&'a str>);
+
+impl<T: Display> Display for WithDump<'_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)?;
+        if let Some(dump) = self.1 {
+            write!(f, "\n{}", dump)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// It builds an Ethernet frame carrying a UDP datagram with a payload.
+    fn udp(payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0x02, 0, 0, 0, 0, 2, 0x02, 0, 0, 0, 0, 1, 0x08, 0x00];
+        frame.extend_from_slice(&[0x45, 0]);
+        frame.extend_from_slice(&((20 + 8 + payload.len()) as u16).to_be_bytes());
+        frame.extend_from_slice(&[0, 1, 0, 0, 64, 17, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2]);
+        frame.extend_from_slice(&[0x13, 0x88, 0x27, 0x0f]);
+        frame.extend_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+        frame.extend_from_slice(&[0, 0]);
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn dumps_offsets_hex_and_ascii() {
+        let dump = HexDump::new(false, None).dump(b"GET / HTTP/1.1\r\nHost");
+
+        assert_eq!(
+            dump,
+            "    0000  47 45 54 20 2f 20 48 54  54 50 2f 31 2e 31 0d 0a  |GET / HTTP/1.1..|\n    \
+             0010  48 6f 73 74                                       |Host|"
+        );
+    }
+
+    #[test]
+    fn dumps_the_payload_from_its_offset_up_to_the_limit() {
+        let frame = udp(b"0123456789abcdefXYZ");
+
+        let dump = HexDump::new(true, Some(16)).dump(&frame);
+        assert_eq!(
+            dump,
+            "    002a  30 31 32 33 34 35 36 37  38 39 61 62 63 64 65 66  |0123456789abcdef|\n    \
+             ... 3 more bytes"
+        );
+
+        assert_eq!(HexDump::new(true, None).dump(&udp(b"")), "    (no payload)");
+        // Packets without a transport layer are dumped whole.
+        assert!(HexDump::new(true, None)
+            .dump(&[0xff; 4])
+            .starts_with("    0000  ff ff ff ff"));
+    }
+
+    #[test]
+    fn messages_are_followed_by_their_dump() {
+        assert_eq!(
+            WithDump("packet", Some("    dump")).to_string(),
+            "packet\n    dump"
+        );
+        assert_eq!(WithDump("packet", None).to_string(), "packet");
+    }
+}
@@ -0,0 +1,47 @@
+//! The sniffer of Megalotron: it captures packets, parses them, filters them with a blacklist,
+//! redacts and anonymizes them, then saves, describes and streams them to a grpc server.
+//!
+//! The `sniffer` binary adds its options, its logger, its metrics endpoint and its terminal UI on
+//! top of this library, which other services can embed to parse packets, filter them or stream
+//! them. The counters of a capture are handed to its sniffer, the packets it delivers are told to
+//! its observers, and it logs through the `log` facade.
+
+#[allow(clippy::all)]
+pub mod packet_streaming {
+    tonic::include_proto!("packet_streaming");
+}
+#[cfg(all(target_os = "linux", feature = "af-packet"))]
+mod afpacket;
+mod anonymizer;
+mod blacklist;
+mod checksum;
+pub mod client;
+mod counters;
+pub mod dissector;
+mod error;
+mod exporter;
+mod flow;
+pub mod hexdump;
+mod limits;
+mod metadata;
+pub mod output;
+pub mod packet;
+mod pipeline;
+mod privacy;
+mod sniffer;
+pub mod source;
+
+pub use anonymizer::Anonymizer;
+pub use blacklist::{Blacklist, Connections};
+pub use counters::Counters;
+pub use error::{Error, EXIT_CODES, EXIT_INTERNAL};
+pub use exporter::{ExportProtocol, Exporter};
+pub use flow::{FlowRecord, FlowTable};
+pub use hexdump::HexDump;
+pub use limits::{parse_time, Limits};
+pub use output::{Output, OutputFormat};
+pub use packet::PacketInfo;
+pub use pipeline::{Observer, Pipeline, StreamMode};
+pub use privacy::{RedactMode, Redactor};
+pub use sniffer::{Sniffer, SnifferBuilder};
+pub use source::{Backend, PacketSource};
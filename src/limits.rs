@@ -0,0 +1,185 @@
+use chrono::{DateTime, Local};
+use std::time::Duration;
+
+/// `Limits` contains the conditions which end a capture before its source does.
+///
+/// Properties:
+///
+/// * `count`: The number of packets after which the capture stops.
+/// * `bytes`: The number of bytes on the wire after which the capture stops.
+/// * `deadline`: The time at which the capture stops, the earliest of its duration and of its
+///   end time.
+pub struct Limits {
+    count: Option<u64>,
+    bytes: Option<u64>,
+    deadline: Option<DateTime<Local>>,
+}
+
+impl Limits {
+    /// It creates the stop conditions of a capture starting now.
+    ///
+    /// Arguments:
+    ///
+    /// * `count`: The number of packets after which the capture stops.
+    /// * `bytes`: The number of bytes on the wire after which the capture stops.
+    /// * `duration`: The number of seconds after which the capture stops.
+    /// * `until`: The time at which the capture stops.
+    ///
+    /// Returns:
+    ///
+    /// A new instance of the Limits struct.
+    pub fn new(
+        count: Option<u64>,
+        bytes: Option<u64>,
+        duration: Option<u64>,
+        until: Option<DateTime<Local>>,
+    ) -> Self {
+        let end = duration
+            .and_then(|duration| chrono::Duration::from_std(Duration::from_secs(duration)).ok())
+            .and_then(|duration| Local::now().checked_add_signed(duration));
+        let deadline = match (end, until) {
+            (Some(end), Some(until)) => Some(end.min(until)),
+            (end, until) => end.or(until),
+        };
+
+        Limits {
+            count,
+            bytes,
+            deadline,
+        }
+    }
+
+    /// It checks if the capture must stop.
+    ///
+    /// Arguments:
+    ///
+    /// * `packets`: The number of packets captured so far.
+    /// * `bytes`: The number of bytes on the wire captured so far.
+    ///
+    /// Returns:
+    ///
+    /// The condition which was reached, or `None` if the capture goes on.
+    pub fn reached(&self, packets: u64, bytes: u64) -> Option<String> {
+        if let Some(count) = self.count.filter(|count| packets >= *count) {
+            return Some(format!("{} packets captured", count));
+        }
+        if let Some(limit) = self.bytes.filter(|limit| bytes >= *limit) {
+            return Some(format!("{} bytes captured", limit));
+        }
+        match self.deadline {
+            Some(deadline) if Local::now() >= deadline => Some(format!(
+                "deadline {} reached",
+                deadline.format("%Y-%m-%d %H:%M:%S")
+            )),
+            _ => None,
+        }
+    }
+
+    /// It checks if a packet would make the capture go over its number of bytes, in which case
+    /// the packet is left out.
+    ///
+    /// Arguments:
+    ///
+    /// * `bytes`: The number of bytes on the wire captured, the packet included.
+    ///
+    /// Returns:
+    ///
+    /// The condition which the packet would exceed, or `None` if the packet is captured.
+    pub fn exceeded(&self, bytes: u64) -> Option<String> {
+        self.bytes
+            .filter(|limit| bytes > *limit)
+            .map(|limit| format!("{} bytes limit reached", limit))
+    }
+}
+
+/// It parses the end time of a capture, either as an RFC 3339 date, as a local date and time, or
+/// as a local time of the next 24 hours.
+///
+/// Arguments:
+///
+/// * `time`: The time given on the command line, such as `2023-06-01 18:30:00` or `18:30`.
+///
+/// Returns:
+///
+/// The end time of the capture.
+pub fn parse_time(time: &str) -> Result<DateTime<Local>, String> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(time) {
+        return Ok(date.with_timezone(&Local));
+    }
+    let invalid = || format!("Invalid time: {}", time);
+
+    for format in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M"] {
+        if let Ok(date) = chrono::NaiveDateTime::parse_from_str(time, format) {
+            return date
+                .and_local_timezone(Local)
+                .earliest()
+                .ok_or_else(invalid);
+        }
+    }
+    for format in ["%H:%M:%S", "%H:%M"] {
+        if let Ok(clock) = chrono::NaiveTime::parse_from_str(time, format) {
+            let now = Local::now();
+            let mut date = now.date_naive().and_time(clock);
+            if date <= now.naive_local() {
+                date += chrono::Duration::days(1);
+            }
+            return date
+                .and_local_timezone(Local)
+                .earliest()
+                .ok_or_else(invalid);
+        }
+    }
+    Err(invalid())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_stop_the_capture_once_reached() {
+        let limits = Limits::new(Some(10), Some(1000), None, None);
+
+        assert_eq!(limits.reached(9, 999), None);
+        assert!(limits.reached(10, 0).unwrap().contains("10 packets"));
+        assert!(limits.reached(0, 1000).unwrap().contains("1000 bytes"));
+    }
+
+    #[test]
+    fn packets_going_over_the_byte_limit_are_left_out() {
+        let limits = Limits::new(None, Some(1000), None, None);
+
+        assert_eq!(limits.exceeded(1000), None);
+        assert!(limits.exceeded(1001).is_some());
+        assert_eq!(Limits::new(None, None, None, None).exceeded(u64::MAX), None);
+    }
+
+    #[test]
+    fn the_earliest_end_time_stops_the_capture() {
+        let past = Local::now() - chrono::Duration::seconds(1);
+
+        assert!(Limits::new(None, None, Some(3600), Some(past))
+            .reached(0, 0)
+            .is_some());
+        assert_eq!(
+            Limits::new(None, None, Some(3600), None).reached(0, 0),
+            None
+        );
+        assert!(Limits::new(None, None, Some(0), None)
+            .reached(0, 0)
+            .is_some());
+    }
+
+    #[test]
+    fn parses_end_times() {
+        let date = parse_time("2023-06-01 18:30:00").unwrap();
+        assert_eq!(
+            date.format("%Y-%m-%d %H:%M").to_string(),
+            "2023-06-01 18:30"
+        );
+
+        let next = parse_time("18:30").unwrap();
+        assert!(next > Local::now() && next <= Local::now() + chrono::Duration::days(1));
+        assert!(parse_time("tomorrow").is_err());
+    }
+}
@@ -2,29 +2,124 @@ use std::fmt::Display;
 use std::fs::File;
 use std::fs::OpenOptions;
 use std::io::Write;
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
 
-use super::args::LogLevel;
+use crate::args::{LogFormat, LogLevel};
+use chrono::{DateTime, Local, SecondsFormat};
 use env_logger::{Builder, Target};
 use lazy_static::lazy_static;
-use log::LevelFilter;
+use log::{LevelFilter, Log, Metadata};
+use serde_json::{json, Value};
+use sniffer::output::strip_ansi;
+
+/// Maximum number of records waiting to be written. Debug and info records logged while the queue
+/// is full are dropped, so that logging never slows the capture down, while warnings and errors
+/// wait for room, since they may be the only explanation of an exit code.
+const QUEUE_CAPACITY: usize = 8192;
+
+/// Maximum time waited for the pending records to be written when flushing the logger.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Target of the records logged by the sniffer library through the `log` facade, followed by the
+/// path of their module.
+const LIBRARY: &str = "sniffer";
 
 lazy_static! {
-    pub static ref LOGGER: Mutex<Logger> = Mutex::new(Logger::default());
+    /// The logger being configured, until `init` hands it to the writer thread.
+    static ref LOGGER: Mutex<Logger> = Mutex::new(Logger::default());
+}
+
+/// The handle used to log once the logger is initialized.
+static HANDLE: OnceLock<Handle> = OnceLock::new();
+
+/// The number of records dropped because the queue was full.
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Whether the records are written on the console, on top of the log file.
+static CONSOLE: AtomicBool = AtomicBool::new(true);
+
+/// `Handle` is what logging a record needs, shared by all the threads.
+///
+/// Properties:
+///
+/// * `log_level`: The minimum level of the records written.
+/// * `log_format`: The format of the records, telling if their fields must be computed.
+/// * `sender`: The queue of the writer thread.
+struct Handle {
+    log_level: LogLevel,
+    log_format: LogFormat,
+    sender: SyncSender<Message>,
+}
+
+/// `Message` is what the writer thread receives.
+enum Message {
+    Record(Record),
+    Reopen,
+    Flush(mpsc::Sender<()>),
+}
+
+/// `Library` hands the records logged by the sniffer library through the `log` facade to the
+/// writer thread. The records of the other crates, such as tonic, are left out.
+struct Library;
+
+/// `Record` is a log message waiting to be written.
+///
+/// Properties:
+///
+/// * `level`: The log level of the message.
+/// * `time`: The time the message was logged.
+/// * `message`: The message.
+/// * `fields`: The structured fields of the message, only computed for JSON.
+struct Record {
+    level: LogLevel,
+    time: DateTime<Local>,
+    message: String,
+    fields: Option<Value>,
 }
 
 /// `Logger` is a struct that contains a reference to a string, a `LogLevel` enum, and an optional
-/// `File`.
+/// `File`. Once initialized, it is owned by the writer thread.
 ///
 /// Properties:
 ///
 /// * `stack`: The name of the stack that the logger is associated with.
 /// * `log_level`: This is the log level that the logger will use.
+/// * `log_format`: This is the format of the log lines, on the standard output and in the file.
 /// * `log_file`: This is the file that the logger will write to.
+/// * `log_path`: This is the path of the file that the logger will write to.
+/// * `log_size`: This is the size of the file that the logger will write to.
+/// * `log_opened`: This is the time the file that the logger will write to was created.
+/// * `rotation`: This is when the file that the logger will write to is rotated.
+/// * `stderr`: Whether the logs are written on the standard error instead of the standard output.
+/// * `console`: This is the logger writing the records on the console, once initialized.
 pub struct Logger {
     stack: &'static str,
     log_level: LogLevel,
+    log_format: LogFormat,
     log_file: Option<File>,
+    log_path: Option<String>,
+    log_size: u64,
+    log_opened: Instant,
+    rotation: Rotation,
+    stderr: bool,
+    console: Option<env_logger::Logger>,
+}
+
+/// `Rotation` tells when the log file is rotated. The current file is renamed with a `.1` suffix,
+/// the previous ones being shifted up to the retention limit, and a new file is created.
+///
+/// Properties:
+///
+/// * `max_size`: The size in bytes above which the log file is rotated.
+/// * `interval`: The time after which the log file is rotated.
+/// * `keep`: The number of rotated files kept.
+pub struct Rotation {
+    pub max_size: Option<u64>,
+    pub interval: Option<Duration>,
+    pub keep: usize,
 }
 
 pub fn set_stack(stack: &'static str) {
@@ -37,75 +132,373 @@ pub fn set_verbosity(level: LogLevel) {
     logger.log_level = level;
 }
 
+pub fn set_format(format: LogFormat) {
+    let mut logger = LOGGER.lock().unwrap();
+    logger.log_format = format;
+}
+
 pub fn set_logfile(log_file: &Option<String>) -> Result<(), std::io::Error> {
     let mut logger = LOGGER.lock().unwrap();
-    logger.log_file = match log_file {
-        Some(path) => Some(
-            OpenOptions::new()
-                .write(true)
-                .append(true)
-                .create(true)
-                .open(path)?,
-        ),
-        None => None,
+    logger.log_path = log_file.clone();
+    logger.open()
+}
+
+pub fn set_rotation(rotation: Rotation) {
+    let mut logger = LOGGER.lock().unwrap();
+    logger.rotation = rotation;
+}
+
+/// It writes the logs on the standard error, leaving the standard output to the packets.
+pub fn set_stderr(stderr: bool) {
+    let mut logger = LOGGER.lock().unwrap();
+    logger.stderr = stderr;
+}
+
+/// It stops or resumes writing the records on the console, for instance while it shows the
+/// terminal UI. The log file is still written.
+pub fn set_console(enabled: bool) {
+    CONSOLE.store(enabled, Ordering::Relaxed);
+}
+
+/// It closes and opens again the log file, for instance once it was moved by logrotate.
+pub fn reopen() {
+    if let Some(handle) = HANDLE.get() {
+        handle.sender.send(Message::Reopen).ok();
+    }
+}
+
+/// It waits for the records already logged to be written, for instance before exiting.
+pub fn flush() {
+    let Some(handle) = HANDLE.get() else {
+        return;
     };
-    Ok(())
+    let (done, wait) = mpsc::channel();
+
+    if handle.sender.send(Message::Flush(done)).is_ok() {
+        wait.recv_timeout(FLUSH_TIMEOUT).ok();
+    }
+}
+
+/// It checks if the records of a level are written, to skip building the costly ones otherwise.
+pub fn enabled(level: LogLevel) -> bool {
+    HANDLE.get().is_some_and(|handle| level >= handle.log_level)
+}
+
+/// It returns the number of records dropped because the writer thread could not keep up.
+pub fn dropped() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+impl Logger {
+    /// It opens the log file in append mode, creating it if needed.
+    fn open(&mut self) -> Result<(), std::io::Error> {
+        self.log_file = None;
+        if let Some(ref path) = self.log_path {
+            let file = OpenOptions::new().append(true).create(true).open(path)?;
+            self.log_size = file.metadata()?.len();
+            self.log_opened = Instant::now();
+            self.log_file = Some(file);
+        }
+        Ok(())
+    }
+
+    /// It rotates the log file if writing a line would make it too big, or if it is too old.
+    ///
+    /// Arguments:
+    ///
+    /// * `len`: The length of the line about to be written.
+    fn rotate(&mut self, len: usize) -> Result<(), std::io::Error> {
+        let Some(ref path) = self.log_path else {
+            return Ok(());
+        };
+        let too_big = self
+            .rotation
+            .max_size
+            .is_some_and(|max_size| self.log_size > 0 && self.log_size + len as u64 > max_size);
+        let too_old = self
+            .rotation
+            .interval
+            .is_some_and(|interval| self.log_opened.elapsed() >= interval);
+        if !too_big && !too_old {
+            return Ok(());
+        }
+
+        self.log_file = None;
+        let rotated = |index: usize| format!("{}.{}", path, index);
+        if self.rotation.keep == 0 {
+            std::fs::remove_file(path)?;
+        } else {
+            for index in (1..self.rotation.keep).rev() {
+                if std::path::Path::new(&rotated(index)).exists() {
+                    std::fs::rename(rotated(index), rotated(index + 1))?;
+                }
+            }
+            std::fs::rename(path, rotated(1))?;
+        }
+        self.open()
+    }
 }
 
-/// This function sets up the logger
+/// This function sets up the logger, starting the thread which writes the records, and installs it
+/// as the logger of the `log` facade for the records of the sniffer library. The logger can no
+/// longer be configured afterwards.
 pub fn init() {
-    let logger = LOGGER.lock().unwrap();
+    let mut logger = std::mem::take(&mut *LOGGER.lock().unwrap());
     let verbosity = match logger.log_level {
         LogLevel::Debug => LevelFilter::Debug,
         LogLevel::Info => LevelFilter::Info,
         LogLevel::Warn => LevelFilter::Warn,
         LogLevel::Error => LevelFilter::Error,
     };
-    Builder::new()
+    let mut builder = Builder::new();
+    builder
         .filter(Some(logger.stack), verbosity)
-        .target(Target::Stdout)
-        .init();
+        .target(match logger.stderr {
+            true => Target::Stderr,
+            false => Target::Stdout,
+        });
+    // JSON records are already complete lines.
+    if logger.log_format == LogFormat::Json {
+        builder.format(|buf, record| writeln!(buf, "{}", record.args()));
+    }
+    logger.console = Some(builder.build());
+
+    let (sender, receiver) = mpsc::sync_channel(QUEUE_CAPACITY);
+    let handle = Handle {
+        log_level: logger.log_level,
+        log_format: logger.log_format,
+        sender,
+    };
+    if HANDLE.set(handle).is_ok() {
+        std::thread::spawn(move || write_records(logger, receiver));
+        if log::set_boxed_logger(Box::new(Library)).is_ok() {
+            log::set_max_level(verbosity);
+        }
+    }
+}
+
+impl Log for Library {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let target = metadata.target();
+        let library = target == LIBRARY
+            || target
+                .strip_prefix(LIBRARY)
+                .is_some_and(|path| path.starts_with("::"));
+
+        library && enabled(level(metadata.level()))
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            log(level(record.level()), record.args(), &|| None);
+        }
+    }
+
+    fn flush(&self) {
+        flush();
+    }
+}
+
+/// It returns the log level of a record of the `log` facade, trace records being debug ones.
+fn level(level: log::Level) -> LogLevel {
+    match level {
+        log::Level::Trace | log::Level::Debug => LogLevel::Debug,
+        log::Level::Info => LogLevel::Info,
+        log::Level::Warn => LogLevel::Warn,
+        log::Level::Error => LogLevel::Error,
+    }
 }
 
-/// Writes a log message to a file
+/// It hands a log message to the writer thread.
 ///
 /// Arguments:
 ///
 /// * `level`: The log level of the message.
 /// * `message`: The message to log.
-fn log_to_file(logger: &mut Logger, level: LogLevel, message: &impl Display) {
-    if let Some(ref mut file) = logger.log_file {
-        let level = match level {
-            LogLevel::Debug => "DEBUG",
-            LogLevel::Info => "INFO ",
-            LogLevel::Warn => "WARN ",
-            LogLevel::Error => "ERROR",
+/// * `fields`: The structured fields of the message, only computed for JSON.
+fn log(level: LogLevel, message: &dyn Display, fields: &dyn Fn() -> Option<Value>) {
+    let Some(handle) = HANDLE.get() else {
+        // Nothing can be written before the logger is initialized, or when an application
+        // embedding the sniffer leaves it alone, so only the warnings and errors are shown.
+        if level >= LogLevel::Warn {
+            eprintln!("{}", message);
+        }
+        return;
+    };
+    if level < handle.log_level {
+        return;
+    }
+
+    let record = Record {
+        level,
+        time: Local::now(),
+        message: message.to_string(),
+        fields: match handle.log_format {
+            LogFormat::Text => None,
+            LogFormat::Json => fields(),
+        },
+    };
+    enqueue(&handle.sender, record);
+}
+
+/// It puts a record in the queue of the writer thread. Debug and info records are dropped if the
+/// queue is full, the other ones wait for room, and are written on the standard error if the
+/// writer thread is gone.
+///
+/// Arguments:
+///
+/// * `sender`: The queue of the writer thread.
+/// * `record`: The record to write.
+fn enqueue(sender: &SyncSender<Message>, record: Record) {
+    if record.level < LogLevel::Warn {
+        if sender.try_send(Message::Record(record)).is_err() {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+        }
+        return;
+    }
+    if let Err(mpsc::SendError(Message::Record(record))) = sender.send(Message::Record(record)) {
+        eprintln!("{}", record.message);
+    }
+}
+
+/// It writes the records received from the queue until all its senders are gone, reporting the
+/// records dropped in the meantime.
+///
+/// Arguments:
+///
+/// * `logger`: The logger writing the records.
+/// * `receiver`: The queue of the records.
+fn write_records(mut logger: Logger, receiver: Receiver<Message>) {
+    let mut reported = 0;
+
+    for message in receiver {
+        match message {
+            Message::Record(record) => {
+                let dropped = DROPPED.load(Ordering::Relaxed);
+                if dropped > reported {
+                    logger.write(Record {
+                        level: LogLevel::Warn,
+                        time: Local::now(),
+                        message: format!("{} log records dropped", dropped - reported),
+                        fields: None,
+                    });
+                    reported = dropped;
+                }
+                logger.write(record);
+            }
+            Message::Reopen => {
+                if let Err(err) = logger.open() {
+                    eprintln!("Could not reopen the log file: {}", err);
+                }
+            }
+            Message::Flush(done) => {
+                if let Some(ref mut file) = logger.log_file {
+                    file.flush().ok();
+                }
+                done.send(()).ok();
+            }
+        }
+    }
+}
+
+impl Logger {
+    /// It writes a record to the log file and to the standard output, as text or as JSON.
+    fn write(&mut self, record: Record) {
+        let json = match self.log_format {
+            LogFormat::Text => None,
+            LogFormat::Json => Some(to_json(self.stack, &record)),
+        };
+
+        if self.log_file.is_some() {
+            let line = match json {
+                Some(ref json) => format!("{}\n", json),
+                None => format!(
+                    "[{:?} {} {}] {}\n",
+                    record.time,
+                    level_name(record.level),
+                    self.stack,
+                    strip_ansi(&record.message)
+                ),
+            };
+            if let Err(err) = self.rotate(line.len()) {
+                eprintln!("Could not rotate the log file: {}", err);
+                // The file is still written to, rather than losing the following lines.
+                if self.log_file.is_none() {
+                    self.open().ok();
+                }
+            }
+            if let Some(ref mut file) = self.log_file {
+                if let Err(err) = file.write_all(line.as_bytes()) {
+                    eprintln!("Could not write to the log file: {}", err);
+                }
+            }
+            self.log_size += line.len() as u64;
+        }
+
+        let level = match record.level {
+            LogLevel::Debug => log::Level::Debug,
+            LogLevel::Info => log::Level::Info,
+            LogLevel::Warn => log::Level::Warn,
+            LogLevel::Error => log::Level::Error,
         };
-        file.write_all(
-            format!(
-                "[{:?} {} {}] {}\n",
-                chrono::offset::Local::now(),
-                level,
-                logger.stack,
-                message
-            )
-            .as_bytes(),
-        )
-        .unwrap();
+        if let Some(ref console) = self.console {
+            if CONSOLE.load(Ordering::Relaxed) {
+                console.log(
+                    &log::Record::builder()
+                        .args(format_args!(
+                            "{}",
+                            json.as_deref().unwrap_or(&record.message)
+                        ))
+                        .level(level)
+                        .target(self.stack)
+                        .build(),
+                );
+            }
+        }
+    }
+}
+
+/// It returns the name of a log level, padded to the same width.
+fn level_name(level: LogLevel) -> &'static str {
+    match level {
+        LogLevel::Debug => "DEBUG",
+        LogLevel::Info => "INFO ",
+        LogLevel::Warn => "WARN ",
+        LogLevel::Error => "ERROR",
     }
 }
 
+/// It formats a record as a JSON object, on a single line.
+fn to_json(stack: &str, record: &Record) -> String {
+    let mut json = json!({
+        "timestamp": record.time.to_rfc3339_opts(SecondsFormat::Millis, false),
+        "level": level_name(record.level).trim_end(),
+        "stack": stack,
+        "message": strip_ansi(&record.message),
+    });
+    if let (Some(json), Some(Value::Object(fields))) = (json.as_object_mut(), &record.fields) {
+        json.extend(fields.clone());
+    }
+    json.to_string()
+}
+
 /// Logs a message if the log level is set to debug.
 ///
 /// Arguments:
 ///
 /// * `message`: message to be logged
 pub fn debug(message: impl Display) {
-    let mut logger = LOGGER.lock().unwrap();
-    if logger.log_level == LogLevel::Debug {
-        log_to_file(&mut logger, LogLevel::Debug, &message);
-    }
-    log::debug!(target: logger.stack, "{}", message);
+    log(LogLevel::Debug, &message, &|| None);
+}
+/// Logs a message with structured fields if the log level is set to debug. The fields are only
+/// written by the JSON format, the text format relying on the message alone.
+///
+/// Arguments:
+///
+/// * `message`: message to be logged
+/// * `fields`: function returning a JSON object whose fields are added to the log record
+pub fn debug_fields(message: impl Display, fields: impl Fn() -> Value) {
+    log(LogLevel::Debug, &message, &|| Some(fields()));
 }
 /// Logs a message if the log level is set to info.
 ///
@@ -113,11 +506,7 @@ pub fn debug(message: impl Display) {
 ///
 /// * `message`: message to be logged
 pub fn info(message: impl Display) {
-    let mut logger = LOGGER.lock().unwrap();
-    if logger.log_level <= LogLevel::Info {
-        log_to_file(&mut logger, LogLevel::Info, &message);
-    }
-    log::info!(target: logger.stack, "{}", message);
+    log(LogLevel::Info, &message, &|| None);
 }
 /// Logs a message if the log level is set to warning.
 ///
@@ -125,11 +514,7 @@ pub fn info(message: impl Display) {
 ///
 /// * `message`: message to be logged
 pub fn warn(message: impl Display) {
-    let mut logger = LOGGER.lock().unwrap();
-    if logger.log_level <= LogLevel::Warn {
-        log_to_file(&mut logger, LogLevel::Warn, &message);
-    }
-    log::warn!(target: logger.stack, "{}", message);
+    log(LogLevel::Warn, &message, &|| None);
 }
 /// Logs a message if the log level is set to error.
 ///
@@ -137,11 +522,7 @@ pub fn warn(message: impl Display) {
 ///
 /// * `message`: message to be logged
 pub fn error(message: impl Display) {
-    let mut logger = LOGGER.lock().unwrap();
-    if logger.log_level <= LogLevel::Error {
-        log_to_file(&mut logger, LogLevel::Error, &message);
-    }
-    log::error!(target: logger.stack, "{}", message);
+    log(LogLevel::Error, &message, &|| None);
 }
 
 impl Default for Logger {
@@ -149,7 +530,136 @@ impl Default for Logger {
         Logger {
             stack: "unknown",
             log_level: LogLevel::Info,
+            log_format: LogFormat::Text,
             log_file: None,
+            log_path: None,
+            log_size: 0,
+            log_opened: Instant::now(),
+            rotation: Rotation {
+                max_size: None,
+                interval: None,
+                keep: 0,
+            },
+            stderr: false,
+            console: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(level: LogLevel, message: &str) -> Record {
+        Record {
+            level,
+            time: Local::now(),
+            message: message.to_string(),
+            fields: None,
+        }
+    }
+
+    fn message(received: Message) -> String {
+        match received {
+            Message::Record(record) => record.message,
+            _ => panic!("Not a record"),
+        }
+    }
+
+    #[test]
+    fn only_debug_and_info_records_are_dropped_when_the_queue_is_full() {
+        let (sender, receiver) = mpsc::sync_channel(1);
+        let before = dropped();
+
+        enqueue(&sender, record(LogLevel::Info, "first"));
+        enqueue(&sender, record(LogLevel::Debug, "dropped"));
+        assert_eq!(dropped(), before + 1);
+
+        let writer = std::thread::spawn(move || enqueue(&sender, record(LogLevel::Error, "fatal")));
+        assert_eq!(message(receiver.recv().unwrap()), "first");
+        assert_eq!(message(receiver.recv().unwrap()), "fatal");
+        writer.join().unwrap();
+    }
+
+    /// It returns a logger writing in a file of the temporary directory, removed beforehand.
+    fn file_logger(name: &str, log_format: LogFormat, rotation: Rotation) -> (Logger, String) {
+        let path = std::env::temp_dir()
+            .join(format!("sniffer-{}-{}.log", name, std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        for index in 0..4 {
+            let file = match index {
+                0 => path.clone(),
+                _ => format!("{}.{}", path, index),
+            };
+            std::fs::remove_file(file).ok();
+        }
+        let mut logger = Logger {
+            stack: "test",
+            log_format,
+            log_path: Some(path.clone()),
+            rotation,
+            ..Logger::default()
+        };
+        logger.open().unwrap();
+        (logger, path)
+    }
+
+    #[test]
+    fn json_records_hold_their_fields_without_colors() {
+        let (mut logger, path) = file_logger(
+            "json",
+            LogFormat::Json,
+            Rotation {
+                max_size: None,
+                interval: None,
+                keep: 0,
+            },
+        );
+        logger.write(Record {
+            fields: Some(json!({ "src_port": 443 })),
+            ..record(LogLevel::Warn, "\x1b[31mTcp\x1b[0m packet")
+        });
+
+        let line = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(path).ok();
+        let json: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(json["level"], "WARN");
+        assert_eq!(json["stack"], "test");
+        assert_eq!(json["message"], "Tcp packet");
+        assert_eq!(json["src_port"], 443);
+        assert!(json["timestamp"].is_string());
+    }
+
+    #[test]
+    fn rotated_files_are_shifted_up_to_the_retention_limit() {
+        let (mut logger, path) = file_logger(
+            "rotation",
+            LogFormat::Text,
+            Rotation {
+                max_size: Some(1),
+                interval: None,
+                keep: 2,
+            },
+        );
+        for message in ["one", "two", "three", "four"] {
+            logger.write(record(LogLevel::Info, message));
+        }
+
+        let read = |file: &str| std::fs::read_to_string(file).ok();
+        let files = [
+            read(&path),
+            read(&format!("{}.1", path)),
+            read(&format!("{}.2", path)),
+            read(&format!("{}.3", path)),
+        ];
+        std::fs::remove_file(&path).ok();
+        for index in 1..4 {
+            std::fs::remove_file(format!("{}.{}", path, index)).ok();
         }
+        assert!(files[0].as_ref().unwrap().ends_with("test] four\n"));
+        assert!(files[1].as_ref().unwrap().ends_with("test] three\n"));
+        assert!(files[2].as_ref().unwrap().ends_with("test] two\n"));
+        assert!(files[3].is_none());
     }
 }
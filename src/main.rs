@@ -1,103 +1,303 @@
-#[allow(clippy::all)]
-mod packet_streaming {
-    tonic::include_proto!("packet_streaming");
-}
 mod args;
+mod config;
 mod logger;
-mod packet;
-mod sniffer;
-
-use packet_streaming::packet_streaming_client::PacketStreamingClient;
-use packet_streaming::{Packet, PacketData, PacketHeader};
+mod metrics;
+mod stats;
+mod tui;
 
-use args::Args;
-use async_stream::stream;
-use clap::Parser;
+use crate::args::{Args, LogLevel};
+use crate::logger::Rotation;
+use crate::metrics::{GrpcState, METRICS};
+use bytes::Bytes;
 use colored::Colorize;
-use futures_util::{pin_mut, StreamExt};
-use packet::PacketInfo;
-use sniffer::Sniffer;
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use sniffer::client::{self, TlsOptions};
+use sniffer::hexdump::WithDump;
+use sniffer::{
+    source, Anonymizer, Backend, Blacklist, Error, Exporter, FlowTable, HexDump, Limits, Observer,
+    Output, PacketInfo, Pipeline, Redactor, Sniffer, EXIT_INTERNAL,
+};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// `Monitor` accounts the packets delivered by the pipeline in the metrics and the statistics,
+/// logs them in debug, and logs the summary of the capture once it ends.
+struct Monitor;
 
 #[tokio::main]
 async fn main() {
-    let args = Args::parse();
-    let mut core = Sniffer::new(&args).unwrap();
+    if let Err(err) = run().await {
+        tui::restore();
+        logger::error(&err);
+        logger::flush();
+        std::process::exit(err.exit_code());
+    }
+}
+
+/// It runs the sniffer until its capture ends or it is stopped.
+///
+/// Returns:
+///
+/// The error which made the sniffer fail, giving its exit code.
+async fn run() -> Result<(), Error> {
+    let args = config::parse()?;
+    init_logger(&args)?;
+    let mut core = open(&args)?;
+    install_hooks(core.stopper())?;
+    if let Some(ref address) = args.metrics {
+        metrics::listen(address).await?;
+    }
     let mut client = match args.url {
-        Some(ref url) => Some(
-            PacketStreamingClient::connect(url.to_owned())
-                .await
-                .unwrap(),
-        ),
+        Some(ref url) => {
+            METRICS.set_grpc_state(GrpcState::Connecting);
+            let tls = TlsOptions {
+                ca: args.tls_ca.clone(),
+                cert: args.tls_cert.clone(),
+                key: args.tls_key.clone(),
+                domain: args.tls_domain.clone(),
+            };
+            let client = client::connect(url, &tls).await;
+            METRICS.set_grpc_state(match client {
+                Ok(_) => GrpcState::Connected,
+                Err(_) => GrpcState::Disconnected,
+            });
+            Some(client?)
+        }
         None => None,
     };
+    let streaming = client.is_some();
 
     logger::info("Sniffer started");
+    stats::init();
+    if let Some(interval) = args.stats_interval {
+        tokio::spawn(stats::run(Duration::from_secs(interval.max(1))));
+    }
+    let mut observers: Vec<Box<dyn Observer>> = vec![Box::new(Monitor)];
+    // The blacklist edited from the terminal UI is shared with the capture loop.
+    let mut tui_thread = None;
+    if args.tui {
+        let blacklist = core.blacklist.get_or_insert_with(Default::default).clone();
+        let (tui, thread) = tui::start(blacklist, core.stopper())?;
+        observers.push(Box::new(tui));
+        tui_thread = Some(thread);
+    }
 
-    let packet_stream = stream! {
-        while let Ok(packet) = core.capture.next_packet() {
-            match PacketInfo::from(&packet) {
-                Some(info) => {
-                    if let Some(ref blacklist) = core.blacklist {
-                        if blacklist.from.contains(&info.src_mac) {
-                            logger::debug(format!("IGNORED: {}", info));
-                            continue;
-                        }
-                        if blacklist.into.contains(&info.dst_mac) {
-                            logger::debug(format!("IGNORED: {}", info));
-                            continue;
-                        }
-                        if let Some(ip) = info.src_ip {
-                            if blacklist.from.contains(&ip.to_string()) {
-                                logger::debug(format!("IGNORED: {}", info));
-                                continue;
-                            }
-                            if let Some(port) = info.src_port {
-                                if blacklist.from.contains(&format!("{}:{}", ip, port)) {
-                                    logger::debug(format!("IGNORED: {}", info));
-                                    continue;
-                                }
-                            }
-                        }
-                        if let Some(ip) = info.dst_ip {
-                            if blacklist.into.contains(&ip.to_string()) {
-                                logger::debug(format!("IGNORED: {}", info));
-                                continue;
-                            }
-                            if let Some(port) = info.dst_port {
-                                if blacklist.into.contains(&format!("{}:{}", ip, port)) {
-                                    logger::debug(format!("IGNORED: {}", info));
-                                    continue;
-                                }
-                            }
-                        }
-                    }
-                    logger::debug(format!("{}", info))
-                }
-                None => logger::debug(format!("[{}] Could not parse the packet", "???".red())),
-            }
+    let stream_mode = streaming.then_some(args.stream_mode);
+    let mut pipeline = Pipeline::start(core, args.workers, stream_mode, observers);
+    let mut streamed = Ok(());
+    if let Some(ref mut cli) = client {
+        streamed = cli.run(pipeline.packets()).await.map(|_| ());
+        METRICS.set_grpc_state(GrpcState::Disconnected);
+        // The capture ends with the gRPC stream, its packets being saved before the error of the
+        // stream is reported.
+        if streamed.is_err() {
+            pipeline.stopper().store(true, Ordering::SeqCst);
+        }
+    }
+    let result = tokio::task::spawn_blocking(move || pipeline.join())
+        .await
+        .expect("The pipeline panicked");
 
-            if core.savefile.is_some() {
-                core.savefile.as_mut().unwrap().write(&packet);
-            }
+    if let Some(thread) = tui_thread {
+        thread.join().ok();
+    }
+    // A panicking thread is reported first, the stream it may have broken being a consequence.
+    if let Err(err @ Error::Internal(_)) = result {
+        return Err(err);
+    }
+    streamed?;
+    result?;
+    logger::info("Sniffer stopped");
+    logger::flush();
+    Ok(())
+}
 
-            yield Packet {
-                header: Some(PacketHeader {
-                    ts_sec: packet.header.ts.tv_sec as u32,
-                    ts_usec: packet.header.ts.tv_usec as u32,
-                    caplen: packet.header.caplen,
-                    len: packet.header.len,
-                }),
-                data: Some(PacketData {
-                    data: packet.data.to_vec(),
-                }),
-            };
+/// It checks the options, opens the sources of the packets with the chosen backend, then
+/// everything the packets go through, the counters of the capture being the ones of the metrics.
+///
+/// Arguments:
+///
+/// * `args`: The options of the sniffer.
+///
+/// Returns:
+///
+/// The sniffer, or the error which prevented it from starting.
+fn open(args: &Args) -> Result<Sniffer, Error> {
+    let conflict = if args.read.is_some() && args.interface.is_some() {
+        Some("You can't both read packets from an input file and a network interface")
+    } else if args.tui && args.output.as_deref() == Some("-") {
+        Some("You can't both show the terminal UI and write packets on the standard output")
+    } else if args.read.is_some() && args.backend == Backend::AfPacket {
+        Some("You can't read packets from an input file with the af-packet backend")
+    } else if args.backend == Backend::Pcap && (args.fanout > 1 || args.fanout_group.is_some()) {
+        Some("You can't share the network interface between sockets with the pcap backend")
+    } else {
+        None
+    };
+    if let Some(conflict) = conflict {
+        return Err(Error::Config(conflict.to_string()));
+    }
+
+    let sources = match args.read {
+        Some(ref input) => source::open_file(input)?,
+        None => source::open_interface(
+            args.interface.as_deref(),
+            args.backend,
+            args.fanout,
+            args.fanout_group,
+        )?,
+    };
+    let mut builder = Sniffer::builder()
+        .sources(sources)
+        .counters(METRICS.counters.clone());
+    if let Some(ref file) = args.write {
+        builder = builder.write(file);
+    }
+    if let Some(ref path) = args.output {
+        builder = builder.output(Output::new(path, args.output_format)?);
+    }
+    if args.hexdump {
+        builder = builder.hexdump(HexDump::new(args.hexdump_payload, args.hexdump_limit));
+    }
+
+    let blacklist: Option<Blacklist> = match args.blacklist {
+        None => args.inline_blacklist.clone(),
+        Some(ref file) => {
+            let data = std::fs::read_to_string(file).map_err(|err| {
+                Error::Blacklist(format!("Could not read the blacklist file: {}", err))
+            })?;
+            let conf: Blacklist = toml::from_str(&data).map_err(|err| {
+                Error::Blacklist(format!("Could not parse the blacklist file: {}", err))
+            })?;
+
+            Some(conf)
         }
     };
+    if let Some(blacklist) = blacklist {
+        builder = builder.blacklist(blacklist);
+    }
 
-    if let Some(ref mut cli) = client {
-        cli.run(packet_stream).await.unwrap();
-    } else {
-        pin_mut!(packet_stream);
-        while (packet_stream.next().await).is_some() {}
+    if let Some(ref collector) = args.export {
+        builder = builder.exporter(Exporter::new(collector, args.export_protocol)?);
+    }
+    if args.flows || args.export.is_some() {
+        builder = builder.flows(FlowTable::new(
+            Duration::from_secs(args.flow_idle_timeout),
+            Duration::from_secs(args.flow_active_timeout),
+            args.flow_max,
+        ));
+    }
+
+    if let Some(redactor) = Redactor::new(args.redact, args.snaplen) {
+        builder = builder.redactor(redactor);
+    }
+    if let Some(ref file) = args.anonymize_key {
+        builder = builder.anonymizer(Anonymizer::from_file(file)?);
+    }
+
+    builder
+        .limits(Limits::new(
+            args.count,
+            args.max_bytes,
+            args.duration,
+            args.until,
+        ))
+        .build()
+}
+
+/// It configures the logger of the process from the options, then starts it.
+///
+/// Arguments:
+///
+/// * `args`: The options giving the verbosity, the format and the file of the logs.
+fn init_logger(args: &Args) -> Result<(), Error> {
+    logger::set_stack("sniffer");
+    logger::set_verbosity(args.verbosity);
+    logger::set_format(args.log_format);
+    logger::set_logfile(&args.logfile)?;
+    logger::set_rotation(Rotation {
+        max_size: args.log_max_size,
+        interval: args.log_rotate_interval.map(Duration::from_secs),
+        keep: args.log_keep,
+    });
+    logger::set_stderr(args.output.as_deref() == Some("-"));
+    logger::init();
+    Ok(())
+}
+
+/// It sets the panic hook, logging the panic and exiting with `EXIT_INTERNAL`, and handles the
+/// signals, SIGINT and SIGTERM asking the capture loop to stop the first time and exiting right
+/// away the second with 128 plus the signal, and SIGHUP reopening the log file.
+///
+/// Arguments:
+///
+/// * `stop`: The handle asking the capture of the sniffer to stop.
+fn install_hooks(stop: Arc<AtomicBool>) -> Result<(), Error> {
+    std::panic::set_hook(Box::new(|err| {
+        if let Some(msg) = err.payload().downcast_ref::<&str>() {
+            logger::error(msg);
+        } else if let Some(msg) = err.payload().downcast_ref::<String>() {
+            logger::error(msg);
+        } else {
+            logger::error(err);
+        }
+        logger::flush();
+        std::process::exit(EXIT_INTERNAL);
+    }));
+
+    let mut signals = Signals::new([SIGINT, SIGTERM, SIGHUP])?;
+    std::thread::spawn(move || {
+        for signal in signals.forever() {
+            if signal == SIGHUP {
+                logger::reopen();
+                continue;
+            }
+            print!("\r");
+            if stop.swap(true, Ordering::SeqCst) {
+                tui::restore();
+                logger::warn("Sniffer killed");
+                logger::flush();
+                std::process::exit(128 + signal);
+            }
+            logger::warn("Sniffer stopping, send the signal again to force it");
+        }
+    });
+    Ok(())
+}
+
+impl Observer for Monitor {
+    fn dumps(&self) -> bool {
+        logger::enabled(LogLevel::Debug)
+    }
+
+    fn packet(
+        &mut self,
+        header: &pcap::PacketHeader,
+        info: Option<&PacketInfo>,
+        _data: &Bytes,
+        dump: Option<&str>,
+    ) {
+        match info {
+            Some(info) => {
+                METRICS.add_bytes(info.protocol, header.len);
+                stats::add_talker(info, header.len);
+                logger::debug_fields(WithDump(info, dump), || info.fields());
+            }
+            None => logger::debug(WithDump(
+                format!("[{}] Could not parse the packet", "???".red()),
+                dump,
+            )),
+        }
+    }
+
+    fn ignored(&mut self, info: &PacketInfo, rule: &str) {
+        METRICS.add_blacklist_hit(rule);
+        logger::debug_fields(format!("IGNORED: {}", info), || info.fields());
+    }
+
+    fn end(&mut self) {
+        stats::summary();
     }
 }
@@ -0,0 +1,115 @@
+use crate::dissector::arp::ArpInfo;
+use crate::dissector::dhcp::{DhcpInfo, DhcpVersion};
+use crate::dissector::dns::DnsInfo;
+use crate::dissector::http::HttpInfo;
+use crate::dissector::tls::TlsInfo;
+use crate::dissector::Application;
+use crate::packet::PacketInfo;
+use crate::packet_streaming::{
+    ArpMetadata, DhcpMetadata, DnsAnswer, DnsMetadata, DnsQuestion, HttpMetadata, PacketMetadata,
+    TlsMetadata,
+};
+
+impl From<&PacketInfo> for PacketMetadata {
+    fn from(info: &PacketInfo) -> Self {
+        let mut metadata = PacketMetadata {
+            protocol: info.protocol.to_string(),
+            src_mac: info.src_mac.to_string(),
+            dst_mac: info.dst_mac.to_string(),
+            src_ip: info.src_ip.map(|ip| ip.to_string()),
+            dst_ip: info.dst_ip.map(|ip| ip.to_string()),
+            src_port: info.src_port.map(u32::from),
+            dst_port: info.dst_port.map(u32::from),
+            ip_protocol: info.ip_protocol.map(u32::from),
+            tcp_flags: info.tcp_flags.map(u32::from),
+            len: info.len,
+            arp: info.arp.as_ref().map(ArpMetadata::from),
+            ..Default::default()
+        };
+
+        match info.app {
+            Some(Application::Dhcp(ref dhcp)) => metadata.dhcp = Some(dhcp.into()),
+            Some(Application::Dns(ref dns)) => metadata.dns = Some(dns.into()),
+            Some(Application::Http(ref http)) => metadata.http = Some(http.into()),
+            Some(Application::Tls(ref tls)) => metadata.tls = Some(tls.into()),
+            None => {}
+        }
+        metadata
+    }
+}
+
+impl From<&ArpInfo> for ArpMetadata {
+    fn from(arp: &ArpInfo) -> Self {
+        ArpMetadata {
+            operation: arp.operation as u32,
+            sender_hw: arp.sender_hw.to_string(),
+            sender_ip: arp.sender_ip.to_string(),
+            target_hw: arp.target_hw.to_string(),
+            target_ip: arp.target_ip.to_string(),
+            gratuitous: arp.gratuitous,
+        }
+    }
+}
+
+impl From<&DnsInfo> for DnsMetadata {
+    fn from(dns: &DnsInfo) -> Self {
+        DnsMetadata {
+            id: dns.id as u32,
+            response: dns.response,
+            rcode: dns.rcode.0 as u32,
+            questions: dns
+                .questions
+                .iter()
+                .map(|question| DnsQuestion {
+                    name: question.name.clone(),
+                    qtype: question.qtype.0 as u32,
+                })
+                .collect(),
+            answers: dns
+                .answers
+                .iter()
+                .map(|answer| DnsAnswer {
+                    name: answer.name.clone(),
+                    rtype: answer.rtype.0 as u32,
+                    ttl: answer.ttl,
+                    data: answer.data.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<&HttpInfo> for HttpMetadata {
+    fn from(http: &HttpInfo) -> Self {
+        HttpMetadata {
+            method: http.method.clone(),
+            host: http.host.clone(),
+            path: http.path.clone(),
+        }
+    }
+}
+
+impl From<&TlsInfo> for TlsMetadata {
+    fn from(tls: &TlsInfo) -> Self {
+        TlsMetadata {
+            sni: tls.sni.clone(),
+            alpn: tls.alpn.clone(),
+        }
+    }
+}
+
+impl From<&DhcpInfo> for DhcpMetadata {
+    fn from(dhcp: &DhcpInfo) -> Self {
+        DhcpMetadata {
+            version: match dhcp.version {
+                DhcpVersion::V4 => 4,
+                DhcpVersion::V6 => 6,
+            },
+            message_type: dhcp.message_type.to_string(),
+            client_mac: dhcp.client_mac.map(|mac| mac.to_string()),
+            requested_ip: dhcp.requested_ip.map(|ip| ip.to_string()),
+            assigned_ip: dhcp.assigned_ip.map(|ip| ip.to_string()),
+            hostname: dhcp.hostname.clone(),
+        }
+    }
+}
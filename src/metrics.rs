@@ -0,0 +1,381 @@
+use crate::logger;
+use lazy_static::lazy_static;
+use sniffer::packet::Protocol;
+use sniffer::Counters;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::io;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Maximum size of a request, headers included, read before answering it.
+const MAX_REQUEST: usize = 8192;
+
+/// Maximum time waited for a client to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+lazy_static! {
+    pub static ref METRICS: Metrics = Metrics::default();
+}
+
+/// `GrpcState` is the state of the connection to the gRPC server.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum GrpcState {
+    Disabled,
+    Connecting,
+    Connected,
+    Disconnected,
+}
+
+/// Names of the connection states, as exposed in the `state` label.
+const GRPC_STATES: [(GrpcState, &str); 4] = [
+    (GrpcState::Disabled, "disabled"),
+    (GrpcState::Connecting, "connecting"),
+    (GrpcState::Connected, "connected"),
+    (GrpcState::Disconnected, "disconnected"),
+];
+
+/// `Metrics` contains what is exposed on the `/metrics` endpoint.
+///
+/// Properties:
+///
+/// * `counters`: The counters of the capture, handed to the sniffer which updates them.
+/// * `protocol_bytes`: The number of bytes on the wire of the parsed packets, by protocol.
+/// * `blacklist_hits`: The number of packets ignored because of the blacklist, by rule.
+/// * `grpc_state`: The state of the connection to the gRPC server.
+#[derive(Default)]
+pub struct Metrics {
+    pub counters: Arc<Counters>,
+    protocol_bytes: Mutex<HashMap<Protocol, u64>>,
+    blacklist_hits: Mutex<HashMap<String, u64>>,
+    grpc_state: AtomicU8,
+}
+
+impl Metrics {
+    /// It accounts the bytes of a parsed packet to its protocol.
+    ///
+    /// Arguments:
+    ///
+    /// * `protocol`: The protocol of the packet.
+    /// * `len`: The length of the packet on the wire.
+    pub fn add_bytes(&self, protocol: Protocol, len: u32) {
+        *self
+            .protocol_bytes
+            .lock()
+            .unwrap()
+            .entry(protocol)
+            .or_insert(0) += len as u64;
+    }
+
+    /// It accounts a packet ignored because of a rule of the blacklist.
+    ///
+    /// Arguments:
+    ///
+    /// * `rule`: The rule matched.
+    pub fn add_blacklist_hit(&self, rule: &str) {
+        add(&self.blacklist_hits, rule, 1);
+    }
+
+    /// It returns the number of packets ignored by each rule of the blacklist, the most frequent
+    /// first.
+    pub fn blacklist_hits(&self) -> Vec<(String, u64)> {
+        let mut hits = sorted(&self.blacklist_hits);
+        hits.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        hits
+    }
+
+    /// It stores the state of the connection to the gRPC server.
+    pub fn set_grpc_state(&self, state: GrpcState) {
+        self.grpc_state.store(state as u8, Ordering::Relaxed);
+    }
+
+    /// It renders the metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let capture = &self.counters;
+        let counters = [
+            (
+                "packets_captured",
+                "Packets read from the capture",
+                &capture.captured,
+            ),
+            (
+                "bytes_captured",
+                "Bytes read from the capture",
+                &capture.captured_bytes,
+            ),
+            ("packets_parsed", "Packets parsed", &capture.parsed),
+            (
+                "packets_unparseable",
+                "Packets which could not be parsed",
+                &capture.unparseable,
+            ),
+            (
+                "packets_blacklisted",
+                "Packets ignored by the blacklist",
+                &capture.blacklisted,
+            ),
+            (
+                "packets_saved",
+                "Packets written to the savefile",
+                &capture.saved,
+            ),
+            (
+                "packets_sent",
+                "Packets sent to the gRPC server",
+                &capture.sent,
+            ),
+            (
+                "pcap_received",
+                "Packets received by libpcap",
+                &capture.pcap_received,
+            ),
+            (
+                "pcap_dropped",
+                "Packets dropped by libpcap",
+                &capture.pcap_dropped,
+            ),
+            (
+                "pcap_if_dropped",
+                "Packets dropped by the interface",
+                &capture.pcap_if_dropped,
+            ),
+            (
+                "pipeline_stalls",
+                "Times the capture waited for the processing pipeline",
+                &capture.pipeline_stalls,
+            ),
+        ];
+
+        for (name, help, counter) in counters {
+            let _ = writeln!(out, "# HELP sniffer_{}_total {}", name, help);
+            let _ = writeln!(out, "# TYPE sniffer_{}_total counter", name);
+            let _ = writeln!(
+                out,
+                "sniffer_{}_total {}",
+                name,
+                counter.load(Ordering::Relaxed)
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP sniffer_pipeline_stall_seconds_total Time the capture waited for the processing pipeline"
+        );
+        let _ = writeln!(out, "# TYPE sniffer_pipeline_stall_seconds_total counter");
+        let _ = writeln!(
+            out,
+            "sniffer_pipeline_stall_seconds_total {}",
+            capture.pipeline_stall_micros.load(Ordering::Relaxed) as f64 / 1e6
+        );
+        let _ = writeln!(
+            out,
+            "# HELP sniffer_pipeline_queued_packets Packets captured and not yet processed"
+        );
+        let _ = writeln!(out, "# TYPE sniffer_pipeline_queued_packets gauge");
+        let _ = writeln!(
+            out,
+            "sniffer_pipeline_queued_packets {}",
+            capture.pipeline_queued.load(Ordering::Relaxed)
+        );
+
+        let _ = writeln!(
+            out,
+            "# HELP sniffer_log_records_dropped_total Log records dropped because the logger could not keep up"
+        );
+        let _ = writeln!(out, "# TYPE sniffer_log_records_dropped_total counter");
+        let _ = writeln!(
+            out,
+            "sniffer_log_records_dropped_total {}",
+            logger::dropped()
+        );
+
+        let _ = writeln!(
+            out,
+            "# HELP sniffer_bytes_total Bytes of the parsed packets"
+        );
+        let _ = writeln!(out, "# TYPE sniffer_bytes_total counter");
+        for (protocol, bytes) in sorted(&self.protocol_bytes) {
+            let _ = writeln!(
+                out,
+                "sniffer_bytes_total{{protocol=\"{}\"}} {}",
+                escape(&protocol),
+                bytes
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP sniffer_blacklist_hits_total Packets ignored by each blacklist rule"
+        );
+        let _ = writeln!(out, "# TYPE sniffer_blacklist_hits_total counter");
+        for (rule, hits) in sorted(&self.blacklist_hits) {
+            let _ = writeln!(
+                out,
+                "sniffer_blacklist_hits_total{{rule=\"{}\"}} {}",
+                escape(&rule),
+                hits
+            );
+        }
+
+        let state = self.grpc_state.load(Ordering::Relaxed);
+        let _ = writeln!(
+            out,
+            "# HELP sniffer_grpc_connection_state State of the connection to the gRPC server"
+        );
+        let _ = writeln!(out, "# TYPE sniffer_grpc_connection_state gauge");
+        for (value, name) in GRPC_STATES {
+            let _ = writeln!(
+                out,
+                "sniffer_grpc_connection_state{{state=\"{}\"}} {}",
+                name,
+                (value as u8 == state) as u8
+            );
+        }
+        out
+    }
+}
+
+/// It adds a value to the counter of a key, creating it if needed.
+fn add(counters: &Mutex<HashMap<String, u64>>, key: &str, value: u64) {
+    let mut counters = counters.lock().unwrap();
+    match counters.get_mut(key) {
+        Some(counter) => *counter += value,
+        None => {
+            counters.insert(key.to_string(), value);
+        }
+    }
+}
+
+/// It returns the counters of a map sorted by key, once formatted.
+fn sorted<K: ToString>(counters: &Mutex<HashMap<K, u64>>) -> Vec<(String, u64)> {
+    let mut counters: Vec<(String, u64)> = counters
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(key, value)| (key.to_string(), *value))
+        .collect();
+    counters.sort();
+    counters
+}
+
+/// It escapes a label value of the exposition format.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// It starts serving the metrics over HTTP, answering `GET /metrics` requests.
+///
+/// Arguments:
+///
+/// * `address`: The `host:port` address to listen on.
+pub async fn listen(address: &str) -> io::Result<()> {
+    let listener = TcpListener::bind(address).await?;
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(async move {
+                        if let Err(err) = respond(stream).await {
+                            logger::debug(format!("Could not serve the metrics: {}", err));
+                        }
+                    });
+                }
+                Err(err) => logger::warn(format!("Could not accept a metrics client: {}", err)),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// It answers a single HTTP request, then closes the connection.
+async fn respond(mut stream: TcpStream) -> io::Result<()> {
+    let request = tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut stream))
+        .await
+        .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+    let request = String::from_utf8_lossy(&request);
+
+    let response = match request.split_whitespace().take(2).collect::<Vec<_>>()[..] {
+        ["GET", "/metrics"] => {
+            let body = METRICS.render();
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+        }
+        _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+    };
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// It reads a request until the end of its headers, which may come in several segments, or until
+/// it is too big to be a metrics request.
+async fn read_request(stream: &mut TcpStream) -> io::Result<Vec<u8>> {
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 1024];
+
+    while !request.windows(4).any(|end| end == b"\r\n\r\n") && request.len() < MAX_REQUEST {
+        let len = stream.read(&mut buffer).await?;
+        if len == 0 {
+            break;
+        }
+        request.extend_from_slice(&buffer[..len]);
+    }
+    Ok(request)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_counters_and_breakdowns() {
+        let metrics = Metrics::default();
+        metrics.counters.captured.store(3, Ordering::Relaxed);
+        metrics.add_bytes(Protocol::Ether(pnet::packet::ethernet::EtherTypes::Arp), 60);
+        metrics.add_blacklist_hit("host \"quoted\"");
+        metrics.set_grpc_state(GrpcState::Connected);
+
+        let text = metrics.render();
+        assert!(text.contains("# TYPE sniffer_packets_captured_total counter\n"));
+        assert!(text.contains("\nsniffer_packets_captured_total 3\n"));
+        assert!(text.contains("\nsniffer_bytes_total{protocol=\"Arp\"} 60\n"));
+        assert!(text.contains("\nsniffer_blacklist_hits_total{rule=\"host \\\"quoted\\\"\"} 1\n"));
+        assert!(text.contains("\nsniffer_grpc_connection_state{state=\"connected\"} 1\n"));
+        assert!(text.contains("\nsniffer_grpc_connection_state{state=\"disabled\"} 0\n"));
+    }
+
+    #[tokio::test]
+    async fn answers_requests_split_across_segments() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            respond(stream).await.unwrap();
+        });
+
+        let mut client = TcpStream::connect(address).await.unwrap();
+        client.write_all(b"GET /met").await.unwrap();
+        client.flush().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        client
+            .write_all(b"rics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        server.await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("sniffer_packets_captured_total"));
+    }
+}
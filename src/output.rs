@@ -0,0 +1,439 @@
+use crate::hexdump::WithDump;
+use crate::packet::PacketInfo;
+use chrono::{DateTime, Local, SecondsFormat};
+use clap::ValueEnum;
+use pnet::packet::tcp::TcpFlags;
+use pnet::util::MacAddr;
+use serde_json::{json, Value};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// `OutputFormat` is the format of the packet descriptions.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum OutputFormat {
+    Text,
+    Verbose,
+    Jsonl,
+    Csv,
+}
+
+/// Fields of a packet, in the order of the CSV columns. JSON lines hold the same fields, missing
+/// values being `null`.
+///
+/// * `timestamp`: The capture time, as an RFC 3339 date with microseconds.
+/// * `caplen`: The number of bytes captured.
+/// * `len`: The length of the packet on the wire.
+/// * `protocol`: The protocol of the packet, missing when it could not be parsed.
+/// * `src_mac`, `dst_mac`: The MAC addresses.
+/// * `src_ip`, `dst_ip`: The IP addresses, or the protocol addresses of an ARP packet.
+/// * `src_port`, `dst_port`: The TCP or UDP ports.
+/// * `ip_protocol`: The protocol number of the IP payload.
+/// * `tcp_flags`: The TCP flags, as a number.
+/// * `payload_len`: The length of the payload of the network layer.
+/// * `info`: The description of the ARP or application layer.
+pub const COLUMNS: [&str; 14] = [
+    "timestamp",
+    "caplen",
+    "len",
+    "protocol",
+    "src_mac",
+    "dst_mac",
+    "src_ip",
+    "dst_ip",
+    "src_port",
+    "dst_port",
+    "ip_protocol",
+    "tcp_flags",
+    "payload_len",
+    "info",
+];
+
+/// TCP flags and their letter, in the order tcpdump prints them.
+const TCP_FLAGS: [(u16, char); 8] = [
+    (TcpFlags::FIN, 'F'),
+    (TcpFlags::SYN, 'S'),
+    (TcpFlags::RST, 'R'),
+    (TcpFlags::PSH, 'P'),
+    (TcpFlags::ACK, '.'),
+    (TcpFlags::URG, 'U'),
+    (TcpFlags::ECE, 'E'),
+    (TcpFlags::CWR, 'W'),
+];
+
+/// `Output` writes a line per packet, or a few for the verbose format, on the standard output or
+/// in a file, for other programs or for people to read.
+///
+/// Properties:
+///
+/// * `writer`: The buffered standard output or file.
+/// * `format`: The format of the packets.
+/// * `header`: Whether the CSV header is still to be written.
+pub struct Output {
+    writer: BufWriter<Box<dyn Write + Send>>,
+    format: OutputFormat,
+    header: bool,
+}
+
+impl Output {
+    /// It opens the output of the packets.
+    ///
+    /// Arguments:
+    ///
+    /// * `path`: The file the packets are written to, or `-` for the standard output.
+    /// * `format`: The format of the packets.
+    ///
+    /// Returns:
+    ///
+    /// A new instance of the Output struct.
+    pub fn new(path: &str, format: OutputFormat) -> io::Result<Self> {
+        let writer: Box<dyn Write + Send> = match path {
+            "-" => Box::new(io::stdout()),
+            path => Box::new(File::create(path)?),
+        };
+
+        Ok(Output {
+            writer: BufWriter::new(writer),
+            format,
+            header: format == OutputFormat::Csv,
+        })
+    }
+
+    /// It writes a packet in the chosen format.
+    ///
+    /// Arguments:
+    ///
+    /// * `header`: The pcap header of the packet.
+    /// * `info`: The parsed packet, or `None` if it could not be parsed.
+    /// * `dump`: The hex dump of the packet, written under its description by the text formats.
+    pub fn write(
+        &mut self,
+        header: &pcap::PacketHeader,
+        info: Option<&PacketInfo>,
+        dump: Option<&str>,
+    ) -> io::Result<()> {
+        let time = timestamp(header);
+
+        match self.format {
+            OutputFormat::Text => {
+                let text = text(time, header, info);
+                writeln!(self.writer, "{}", WithDump(text, dump))
+            }
+            OutputFormat::Verbose => {
+                let verbose = verbose(time, header, info);
+                writeln!(self.writer, "{}", WithDump(verbose, dump))
+            }
+            OutputFormat::Jsonl => writeln!(self.writer, "{}", fields(time, header, info)),
+            OutputFormat::Csv => {
+                if self.header {
+                    writeln!(self.writer, "{}", COLUMNS.join(","))?;
+                    self.header = false;
+                }
+                let fields = fields(time, header, info);
+                let row: Vec<String> = COLUMNS
+                    .iter()
+                    .map(|column| match fields[*column] {
+                        Value::Null => String::new(),
+                        Value::String(ref value) => csv_escape(value),
+                        ref value => value.to_string(),
+                    })
+                    .collect();
+                writeln!(self.writer, "{}", row.join(","))
+            }
+        }
+    }
+
+    /// It writes the packets still buffered.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// It returns the capture time of a packet.
+pub fn timestamp(header: &pcap::PacketHeader) -> DateTime<Local> {
+    (UNIX_EPOCH + since_epoch(header)).into()
+}
+
+/// It returns the capture time of a packet since the UNIX epoch. Malformed headers, with a
+/// negative time or more than a second of microseconds, are clamped instead of overflowing.
+pub fn since_epoch(header: &pcap::PacketHeader) -> Duration {
+    let secs = header.ts.tv_sec.max(0) as u64;
+    let micros = header.ts.tv_usec.clamp(0, 999_999) as u64;
+
+    Duration::from_secs(secs) + Duration::from_micros(micros)
+}
+
+/// It gathers the fields of a packet, following the schema of `COLUMNS`.
+fn fields(time: DateTime<Local>, header: &pcap::PacketHeader, info: Option<&PacketInfo>) -> Value {
+    let mut fields = json!({
+        "timestamp": time.to_rfc3339_opts(SecondsFormat::Micros, false),
+        "caplen": header.caplen,
+        "len": header.len,
+    });
+    let Some(info) = info else {
+        for column in &COLUMNS[3..] {
+            fields[*column] = Value::Null;
+        }
+        return fields;
+    };
+
+    fields["protocol"] = json!(info.protocol.to_string());
+    fields["src_mac"] = json!(info.src_mac.to_string());
+    fields["dst_mac"] = json!(info.dst_mac.to_string());
+    fields["src_ip"] = json!(info.src_ip);
+    fields["dst_ip"] = json!(info.dst_ip);
+    fields["src_port"] = json!(info.src_port);
+    fields["dst_port"] = json!(info.dst_port);
+    fields["ip_protocol"] = json!(info.ip_protocol);
+    fields["tcp_flags"] = json!(info.tcp_flags);
+    fields["payload_len"] = json!(info.len);
+    fields["info"] = json!(description(info));
+    fields
+}
+
+/// It formats a packet on a single line, such as
+/// `18:30:00.123456 Tcp 10.0.0.1:443 > 10.0.0.2:51000 len 74 [S.]`.
+pub fn text(
+    time: DateTime<Local>,
+    header: &pcap::PacketHeader,
+    info: Option<&PacketInfo>,
+) -> String {
+    let time = time.format("%H:%M:%S%.6f");
+    let Some(info) = info else {
+        return format!("{} ??? len {}", time, header.len);
+    };
+
+    let endpoint = |ip: Option<_>, port: Option<u16>, mac: MacAddr| match (ip, port) {
+        (Some(ip), Some(port)) => format!("{}:{}", ip, port),
+        (Some(ip), None) => format!("{}", ip),
+        _ => mac.to_string(),
+    };
+    let mut line = format!(
+        "{} {} {} > {} len {}",
+        time,
+        info.protocol,
+        endpoint(info.src_ip, info.src_port, info.src_mac),
+        endpoint(info.dst_ip, info.dst_port, info.dst_mac),
+        header.len,
+    );
+    if let Some(flags) = info.tcp_flags {
+        line += &format!(" [{}]", tcp_flags(flags));
+    }
+    if let Some(description) = description(info) {
+        line += &format!(" {}", description);
+    }
+    line
+}
+
+/// It formats a packet the way tcpdump does with `-e -v`, its link layer and its application
+/// layer on their own indented lines.
+pub fn verbose(
+    time: DateTime<Local>,
+    header: &pcap::PacketHeader,
+    info: Option<&PacketInfo>,
+) -> String {
+    let time = time.format("%Y-%m-%d %H:%M:%S%.6f");
+    let Some(info) = info else {
+        return format!(
+            "{} unparseable packet, caplen {}, len {}",
+            time, header.caplen, header.len
+        );
+    };
+
+    let endpoint = |ip: Option<_>, port: Option<u16>, mac: MacAddr| match (ip, port) {
+        (Some(ip), Some(port)) => format!("{}.{}", ip, port),
+        (Some(ip), None) => format!("{}", ip),
+        _ => mac.to_string(),
+    };
+    let mut lines = format!(
+        "{} {} {} > {}:",
+        time,
+        info.protocol,
+        endpoint(info.src_ip, info.src_port, info.src_mac),
+        endpoint(info.dst_ip, info.dst_port, info.dst_mac),
+    );
+    if let Some(flags) = info.tcp_flags {
+        lines += &format!(" Flags [{}],", tcp_flags(flags));
+    }
+    lines += &format!(" length {}", info.len);
+
+    lines += &format!(
+        "\n    ether {} > {}, caplen {}, len {}",
+        info.src_mac, info.dst_mac, header.caplen, header.len
+    );
+    if let Some(protocol) = info.ip_protocol {
+        lines += &format!(", ip proto {}", protocol);
+    }
+    if let Some(description) = description(info) {
+        lines += &format!("\n    {}", description);
+    }
+    lines
+}
+
+/// It describes the ARP or application layer of a packet, without colors.
+fn description(info: &PacketInfo) -> Option<String> {
+    let description = match (&info.arp, &info.app) {
+        (Some(arp), _) => arp.to_string(),
+        (None, Some(app)) => app.to_string(),
+        (None, None) => return None,
+    };
+    Some(strip_ansi(description.trim()))
+}
+
+/// It formats TCP flags with the letters used by tcpdump, such as `S.` for a SYN-ACK.
+fn tcp_flags(flags: u16) -> String {
+    TCP_FLAGS
+        .iter()
+        .filter(|(flag, _)| flags & flag != 0)
+        .map(|(_, letter)| letter)
+        .collect()
+}
+
+/// It quotes a CSV field when it holds a separator, a quote or a line break.
+fn csv_escape(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// It removes the ANSI escape sequences, such as colors, from a message.
+pub fn strip_ansi(message: &str) -> String {
+    let mut stripped = String::with_capacity(message.len());
+    let mut chars = message.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            stripped.push(c);
+            continue;
+        }
+        // A control sequence ends with a character between '@' and '~'.
+        if chars.next() == Some('[') {
+            for c in chars.by_ref() {
+                if ('@'..='~').contains(&c) {
+                    break;
+                }
+            }
+        }
+    }
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// It builds an Ethernet frame carrying a TCP SYN-ACK from 10.0.0.1:443 to 10.0.0.2:51000.
+    fn syn_ack() -> (pcap::PacketHeader, Vec<u8>) {
+        let mut frame = vec![0x02, 0, 0, 0, 0, 2, 0x02, 0, 0, 0, 0, 1, 0x08, 0x00];
+        frame.extend_from_slice(&[0x45, 0, 0, 40, 0, 1, 0, 0, 64, 6, 0, 0]);
+        frame.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2]);
+        frame.extend_from_slice(&[0x01, 0xbb, 0xc7, 0x38, 0, 0, 0, 1, 0, 0, 0, 1]);
+        frame.extend_from_slice(&[0x50, 0x12, 0xff, 0xff, 0, 0, 0, 0]);
+        let header = pcap::PacketHeader {
+            ts: libc::timeval {
+                tv_sec: 1_700_000_000,
+                tv_usec: 123_456,
+            },
+            caplen: frame.len() as u32,
+            len: 60,
+        };
+        (header, frame)
+    }
+
+    /// It writes packets in a format, then reads the lines written.
+    fn written(
+        format: OutputFormat,
+        packets: &[(pcap::PacketHeader, Option<&PacketInfo>)],
+    ) -> String {
+        let path = std::env::temp_dir().join(format!(
+            "sniffer-output-{:?}-{}",
+            format,
+            std::process::id()
+        ));
+        let path = path.to_str().unwrap();
+        let mut output = Output::new(path, format).unwrap();
+        for (header, info) in packets {
+            output.write(header, *info, None).unwrap();
+        }
+        output.flush().unwrap();
+
+        let lines = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).ok();
+        lines
+    }
+
+    #[test]
+    fn text_formats_describe_the_packet_the_way_tcpdump_does() {
+        let (header, frame) = syn_ack();
+        let info = PacketInfo::from(&pcap::Packet::new(&header, &frame)).unwrap();
+        let time = timestamp(&header);
+
+        assert_eq!(
+            text(time, &header, Some(&info)),
+            format!(
+                "{} Tcp 10.0.0.1:443 > 10.0.0.2:51000 len 60 [S.]",
+                time.format("%H:%M:%S%.6f")
+            )
+        );
+        assert_eq!(
+            verbose(time, &header, Some(&info)),
+            format!(
+                "{} Tcp 10.0.0.1.443 > 10.0.0.2.51000: Flags [S.], length {}\n    ether 02:00:00:00:00:01 > 02:00:00:00:00:02, caplen 54, len 60, ip proto 6",
+                time.format("%Y-%m-%d %H:%M:%S%.6f"),
+                info.len
+            )
+        );
+        assert!(text(time, &header, None).ends_with(" ??? len 60"));
+    }
+
+    #[test]
+    fn json_lines_and_csv_rows_hold_the_same_fields() {
+        let (header, frame) = syn_ack();
+        let info = PacketInfo::from(&pcap::Packet::new(&header, &frame)).unwrap();
+        let packets = [(header, Some(&info)), (header, None)];
+
+        let jsonl = written(OutputFormat::Jsonl, &packets);
+        let lines: Vec<Value> = jsonl
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines[0]["protocol"], "Tcp");
+        assert_eq!(lines[0]["src_ip"], "10.0.0.1");
+        assert_eq!(lines[0]["dst_port"], 51000);
+        assert_eq!(lines[0]["tcp_flags"], 0x12);
+        assert_eq!(lines[1]["len"], 60);
+        assert!(lines[1]["protocol"].is_null());
+
+        let csv = written(OutputFormat::Csv, &packets);
+        let rows: Vec<&str> = csv.lines().collect();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0], COLUMNS.join(","));
+        let row: Vec<&str> = rows[1].split(',').collect();
+        assert_eq!(row.len(), COLUMNS.len());
+        assert_eq!(
+            row[3..12],
+            [
+                "Tcp",
+                "02:00:00:00:00:01",
+                "02:00:00:00:00:02",
+                "10.0.0.1",
+                "10.0.0.2",
+                "443",
+                "51000",
+                "6",
+                "18"
+            ]
+        );
+        assert!(rows[2].ends_with(",54,60,,,,,,,,,,,"));
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_and_colors_removed() {
+        assert_eq!(csv_escape("plain"), "plain");
+        assert_eq!(csv_escape("a,b"), "\"a,b\"");
+        assert_eq!(csv_escape("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(strip_ansi("\x1b[1;31mDNS\x1b[0m query"), "DNS query");
+    }
+}
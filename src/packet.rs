@@ -1,17 +1,23 @@
+use crate::dissector::arp::ArpInfo;
+use crate::dissector::{self, Application, Transport};
+use crate::packet_streaming::PacketMetadata;
 use colored::Colorize;
 use pnet::packet::arp::ArpPacket;
-use pnet::packet::ip::IpNextHeaderProtocols;
+use pnet::packet::ip::{IpNextHeaderProtocol, IpNextHeaderProtocols};
 use pnet::packet::udp::UdpPacket;
+use pnet::util::MacAddr;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::net::IpAddr;
 
-use pnet::packet::ethernet::{EtherTypes, EthernetPacket};
-use pnet::packet::ipv4::Ipv4Packet;
+use pnet::packet::ethernet::{EtherType, EtherTypes, EthernetPacket};
+use pnet::packet::ipv4::{Ipv4Flags, Ipv4Packet};
 use pnet::packet::ipv6::Ipv6Packet;
 use pnet::packet::tcp::TcpPacket;
 use pnet::packet::Packet;
 
-/// The first field is a string that contains the name of the protocol. The next two fields are strings
-/// that contain the source and destination MAC addresses. The next two fields are options of IP
+/// The first field is the protocol of the packet. The next two fields are the source and
+/// destination MAC addresses. The next two fields are options of IP
 /// addresses that contain the source and destination IP addresses. The next two fields are options of
 /// numbers that contain the source and destination ports. The last field is a number that contains the
 /// length of the packet.
@@ -25,20 +31,28 @@ use pnet::packet::Packet;
 /// * `dst_ip`: The destination IP address
 /// * `src_port`: The source port of the packet.
 /// * `dst_port`: The destination port of the packet.
+/// * `ip_protocol`: The protocol number of the IP payload.
+/// * `tcp_flags`: The flags of the packet if it is a TCP segment.
 /// * `len`: The length of the packet in bytes.
+/// * `arp`: The decoded fields of the packet if it is an ARP packet.
+/// * `app`: The decoded application layer of the packet, if its protocol is known.
 pub struct PacketInfo {
-    pub protocol: String,
-    pub src_mac: String,
-    pub dst_mac: String,
+    pub protocol: Protocol,
+    pub src_mac: MacAddr,
+    pub dst_mac: MacAddr,
     pub src_ip: Option<IpAddr>,
     pub dst_ip: Option<IpAddr>,
     pub src_port: Option<u16>,
     pub dst_port: Option<u16>,
+    pub ip_protocol: Option<u8>,
+    pub tcp_flags: Option<u16>,
     pub len: u32,
+    pub arp: Option<ArpInfo>,
+    pub app: Option<Application>,
 }
 
 impl PacketInfo {
-    /// > If the packet is an IPv4 packet, parse it as an IPv4 packet, otherwise if it's an IPv6 packet,
+    /// If the packet is an IPv4 packet, parse it as an IPv4 packet, otherwise if it's an IPv6 packet,
     /// parse it as an IPv6 packet, otherwise if it's an ARP packet, parse it as an ARP packet,
     /// otherwise parse it as an Ethernet packet.
     ///
@@ -56,84 +70,318 @@ impl PacketInfo {
             EtherTypes::Ipv4 => {
                 let ipv4 = Ipv4Packet::new(ethernet.payload())?;
 
-                let (src_port, dst_port) = match ipv4.get_next_level_protocol() {
-                    IpNextHeaderProtocols::Udp => {
-                        let udp = UdpPacket::new(ipv4.payload())?;
-
-                        (Some(udp.get_source()), Some(udp.get_destination()))
-                    }
-                    IpNextHeaderProtocols::Tcp => {
-                        let tcp = TcpPacket::new(ipv4.payload())?;
-
-                        (Some(tcp.get_source()), Some(tcp.get_destination()))
-                    }
-                    _ => (None, None),
-                };
+                let transport = Self::transport(ipv4.get_next_level_protocol(), ipv4.payload())?;
 
                 Some(PacketInfo {
-                    protocol: ipv4.get_next_level_protocol().to_string(),
-                    src_mac: ethernet.get_source().to_string(),
-                    dst_mac: ethernet.get_destination().to_string(),
+                    protocol: Protocol::Ip(ipv4.get_next_level_protocol()),
+                    src_mac: ethernet.get_source(),
+                    dst_mac: ethernet.get_destination(),
                     src_ip: Some(ipv4.get_source().into()),
                     dst_ip: Some(ipv4.get_destination().into()),
-                    src_port,
-                    dst_port,
+                    src_port: transport.src_port,
+                    dst_port: transport.dst_port,
+                    ip_protocol: Some(ipv4.get_next_level_protocol().0),
+                    tcp_flags: transport.tcp_flags,
                     len: ipv4.payload().len() as u32,
+                    arp: None,
+                    app: transport.app,
                 })
             }
             EtherTypes::Ipv6 => {
                 let ipv6 = Ipv6Packet::new(ethernet.payload())?;
 
-                let (src_port, dst_port) = match ipv6.get_next_header() {
-                    IpNextHeaderProtocols::Udp => {
-                        let udp = UdpPacket::new(ipv6.payload())?;
-
-                        (Some(udp.get_source()), Some(udp.get_destination()))
-                    }
-                    IpNextHeaderProtocols::Tcp => {
-                        let tcp = TcpPacket::new(ipv6.payload())?;
-
-                        (Some(tcp.get_source()), Some(tcp.get_destination()))
-                    }
-                    _ => (None, None),
-                };
+                let transport = Self::transport(ipv6.get_next_header(), ipv6.payload())?;
 
                 Some(PacketInfo {
-                    protocol: ipv6.get_next_header().to_string(),
-                    src_mac: ethernet.get_source().to_string(),
-                    dst_mac: ethernet.get_destination().to_string(),
+                    protocol: Protocol::Ip(ipv6.get_next_header()),
+                    src_mac: ethernet.get_source(),
+                    dst_mac: ethernet.get_destination(),
                     src_ip: Some(ipv6.get_source().into()),
                     dst_ip: Some(ipv6.get_destination().into()),
-                    src_port,
-                    dst_port,
+                    src_port: transport.src_port,
+                    dst_port: transport.dst_port,
+                    ip_protocol: Some(ipv6.get_next_header().0),
+                    tcp_flags: transport.tcp_flags,
                     len: ipv6.payload().len() as u32,
+                    arp: None,
+                    app: transport.app,
                 })
             }
             EtherTypes::Arp => {
                 let arp = ArpPacket::new(ethernet.payload())?;
                 Some(PacketInfo {
-                    protocol: ethernet.get_ethertype().to_string(),
-                    src_mac: ethernet.get_source().to_string(),
-                    dst_mac: ethernet.get_destination().to_string(),
+                    protocol: Protocol::Ether(ethernet.get_ethertype()),
+                    src_mac: ethernet.get_source(),
+                    dst_mac: ethernet.get_destination(),
                     src_ip: Some(arp.get_sender_proto_addr().into()),
                     dst_ip: Some(arp.get_target_proto_addr().into()),
                     src_port: None,
                     dst_port: None,
+                    ip_protocol: None,
+                    tcp_flags: None,
                     len: arp.payload().len() as u32,
+                    arp: Some(ArpInfo::from(&arp)),
+                    app: None,
                 })
             }
             _ => Some(PacketInfo {
-                protocol: ethernet.get_ethertype().to_string(),
-                src_mac: ethernet.get_source().to_string(),
-                dst_mac: ethernet.get_destination().to_string(),
+                protocol: Protocol::Ether(ethernet.get_ethertype()),
+                src_mac: ethernet.get_source(),
+                dst_mac: ethernet.get_destination(),
                 src_ip: None,
                 dst_ip: None,
                 src_port: None,
                 dst_port: None,
+                ip_protocol: None,
+                tcp_flags: None,
                 len: ethernet.payload().len() as u32,
+                arp: None,
+                app: None,
             }),
         }
     }
+
+    /// It returns the fields of the packet as a JSON object, for structured logs. They are the
+    /// metadata sent to the gRPC server, the ARP and application layers being objects as well.
+    ///
+    /// Returns:
+    ///
+    /// A JSON object with a `packet` field holding the fields of the packet.
+    pub fn fields(&self) -> serde_json::Value {
+        serde_json::json!({ "packet": PacketMetadata::from(self) })
+    }
+
+    /// It returns the address of the source of the packet, its IP address or, for packets
+    /// without one, its MAC address.
+    pub fn source(&self) -> Address {
+        match self.src_ip {
+            Some(ip) => Address::Ip(ip),
+            None => Address::Mac(self.src_mac),
+        }
+    }
+
+    /// It returns the address of the destination of the packet, its IP address or, for packets
+    /// without one, its MAC address.
+    pub fn destination(&self) -> Address {
+        match self.dst_ip {
+            Some(ip) => Address::Ip(ip),
+            None => Address::Mac(self.dst_mac),
+        }
+    }
+
+    /// It parses the transport layer of an IP packet and dissects its application layer.
+    ///
+    /// Arguments:
+    ///
+    /// * `protocol`: The protocol of the IP payload.
+    /// * `payload`: The IP payload.
+    ///
+    /// Returns:
+    ///
+    /// The decoded transport layer, or `None` if the transport header is malformed.
+    fn transport(protocol: IpNextHeaderProtocol, payload: &[u8]) -> Option<TransportInfo> {
+        match protocol {
+            IpNextHeaderProtocols::Udp => {
+                let udp = UdpPacket::new(payload)?;
+                let (src, dst) = (udp.get_source(), udp.get_destination());
+
+                Some(TransportInfo {
+                    src_port: Some(src),
+                    dst_port: Some(dst),
+                    tcp_flags: None,
+                    app: dissector::dissect(Transport::Udp, src, dst, udp.payload()),
+                })
+            }
+            IpNextHeaderProtocols::Tcp => {
+                let tcp = TcpPacket::new(payload)?;
+                let (src, dst) = (tcp.get_source(), tcp.get_destination());
+
+                Some(TransportInfo {
+                    src_port: Some(src),
+                    dst_port: Some(dst),
+                    tcp_flags: Some(tcp.get_flags()),
+                    app: dissector::dissect(Transport::Tcp, src, dst, tcp.payload()),
+                })
+            }
+            _ => Some(TransportInfo::default()),
+        }
+    }
+}
+
+/// It hashes the connection a frame belongs to, the same way in both of its directions, so that
+/// its packets can be processed in order by the same worker. Frames which are not IP frames are
+/// hashed by their MAC addresses.
+///
+/// Arguments:
+///
+/// * `packet`: &[u8] - The packet to hash
+///
+/// Returns:
+///
+/// The hash of the connection of the packet.
+pub(crate) fn flow_hash(packet: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    let Some(ethernet) = EthernetPacket::new(packet) else {
+        return 0;
+    };
+    // Both TCP and UDP start with the source and destination ports.
+    let ports = |protocol: IpNextHeaderProtocol, transport: &[u8]| match protocol {
+        IpNextHeaderProtocols::Tcp | IpNextHeaderProtocols::Udp if transport.len() >= 4 => (
+            u16::from_be_bytes([transport[0], transport[1]]),
+            u16::from_be_bytes([transport[2], transport[3]]),
+        ),
+        _ => (0, 0),
+    };
+
+    let (src, dst, protocol, ports): (IpAddr, IpAddr, _, _) = match ethernet.get_ethertype() {
+        EtherTypes::Ipv4 => {
+            let Some(ipv4) = Ipv4Packet::new(ethernet.payload()) else {
+                return 0;
+            };
+            let protocol = ipv4.get_next_level_protocol();
+            (
+                ipv4.get_source().into(),
+                ipv4.get_destination().into(),
+                protocol,
+                ports(protocol, ipv4.payload()),
+            )
+        }
+        EtherTypes::Ipv6 => {
+            let Some(ipv6) = Ipv6Packet::new(ethernet.payload()) else {
+                return 0;
+            };
+            let protocol = ipv6.get_next_header();
+            (
+                ipv6.get_source().into(),
+                ipv6.get_destination().into(),
+                protocol,
+                ports(protocol, ipv6.payload()),
+            )
+        }
+        _ => {
+            let (src, dst) = (ethernet.get_source(), ethernet.get_destination());
+            (src.min(dst), src.max(dst)).hash(&mut hasher);
+            return hasher.finish();
+        }
+    };
+
+    let (src, dst) = ((src, ports.0), (dst, ports.1));
+    (protocol.0, src.min(dst), src.max(dst)).hash(&mut hasher);
+    hasher.finish()
+}
+
+/// It tells if an IPv4 packet is a fragment, the first one included, whose payload is only a part
+/// of the segment it carries.
+///
+/// Arguments:
+///
+/// * `ipv4`: The IPv4 packet.
+///
+/// Returns:
+///
+/// Whether the packet is a fragment.
+pub(crate) fn is_fragment(ipv4: &Ipv4Packet) -> bool {
+    ipv4.get_fragment_offset() != 0 || ipv4.get_flags() & Ipv4Flags::MoreFragments != 0
+}
+
+/// It finds where the payload of the transport layer of a packet starts, which is right after the
+/// IP header for protocols other than TCP and UDP, and for IPv4 fragments other than the first.
+///
+/// Arguments:
+///
+/// * `packet`: &[u8] - The packet to parse
+///
+/// Returns:
+///
+/// The offset of the transport layer payload, or `None` if the packet is not an IP packet or if its
+/// headers are malformed or cut by the capture.
+pub(crate) fn payload_offset(packet: &[u8]) -> Option<usize> {
+    let ethernet = EthernetPacket::new(packet)?;
+    let ip_offset = EthernetPacket::minimum_packet_size();
+
+    let (protocol, transport_offset) = match ethernet.get_ethertype() {
+        EtherTypes::Ipv4 => {
+            let ipv4 = Ipv4Packet::new(ethernet.payload())?;
+            let header_len = ipv4.get_header_length() as usize * 4;
+            if header_len < Ipv4Packet::minimum_packet_size() {
+                return None;
+            }
+            // The other fragments of a packet only hold the rest of its payload.
+            let protocol = match ipv4.get_fragment_offset() {
+                0 => ipv4.get_next_level_protocol(),
+                _ => IpNextHeaderProtocols::Reserved,
+            };
+
+            (protocol, ip_offset + header_len)
+        }
+        EtherTypes::Ipv6 => {
+            let ipv6 = Ipv6Packet::new(ethernet.payload())?;
+
+            (
+                ipv6.get_next_header(),
+                ip_offset + Ipv6Packet::minimum_packet_size(),
+            )
+        }
+        _ => return None,
+    };
+    let transport = packet.get(transport_offset..)?;
+
+    let header_len = match protocol {
+        IpNextHeaderProtocols::Tcp => {
+            let header_len = TcpPacket::new(transport)?.get_data_offset() as usize * 4;
+            if header_len < TcpPacket::minimum_packet_size() {
+                return None;
+            }
+            header_len
+        }
+        IpNextHeaderProtocols::Udp => UdpPacket::minimum_packet_size(),
+        _ => 0,
+    };
+    let offset = transport_offset + header_len;
+    (offset <= packet.len()).then_some(offset)
+}
+
+/// `Protocol` is the protocol of a packet: the protocol of the IP payload for IP packets, the
+/// EtherType for the other frames.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Protocol {
+    Ip(IpNextHeaderProtocol),
+    Ether(EtherType),
+}
+
+impl std::fmt::Display for Protocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Protocol::Ip(protocol) => write!(f, "{}", protocol),
+            Protocol::Ether(ethertype) => write!(f, "{}", ethertype),
+        }
+    }
+}
+
+/// `Address` identifies an endpoint of a packet, by its IP address or, for packets without one, by
+/// its MAC address.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Address {
+    Ip(IpAddr),
+    Mac(MacAddr),
+}
+
+impl std::fmt::Display for Address {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Address::Ip(ip) => write!(f, "{}", ip),
+            Address::Mac(mac) => write!(f, "{}", mac),
+        }
+    }
+}
+
+/// `TransportInfo` contains the fields decoded from the transport layer of an IP packet.
+#[derive(Default)]
+struct TransportInfo {
+    src_port: Option<u16>,
+    dst_port: Option<u16>,
+    tcp_flags: Option<u16>,
+    app: Option<Application>,
 }
 
 impl std::fmt::Display for PacketInfo {
@@ -142,28 +390,50 @@ impl std::fmt::Display for PacketInfo {
             f,
             "[{}] {}{} {} {}{} ({} bytes) ",
             self.protocol,
-            if let Some(src_ip) = self.src_ip {
-                src_ip.to_string()
-            } else {
-                self.src_mac.to_string()
-            },
+            self.source(),
             if let Some(src_port) = self.src_port {
                 format!("{}{}", ":".red(), src_port)
             } else {
                 "".to_string()
             },
             "->".blue(),
-            if let Some(dst_ip) = self.dst_ip {
-                dst_ip.to_string()
-            } else {
-                self.dst_mac.to_string()
-            },
+            self.destination(),
             if let Some(dst_port) = self.dst_port {
                 format!("{}{}", ":".red(), dst_port)
             } else {
                 "".to_string()
             },
             self.len,
-        )
+        )?;
+        if let Some(ref arp) = self.arp {
+            write!(f, "{} ", arp)?;
+        }
+        if let Some(ref app) = self.app {
+            write!(f, "{} ", app)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_are_structured_and_without_colors() {
+        colored::control::set_override(true);
+        let mut frame = vec![0xff; 6];
+        frame.extend_from_slice(&[0x02, 0, 0, 0, 0, 0x01, 0x08, 0x06]);
+        frame.extend_from_slice(&[0, 1, 0x08, 0x00, 6, 4, 0, 1]);
+        frame.extend_from_slice(&[0x02, 0, 0, 0, 0, 0x01, 192, 168, 1, 10]);
+        frame.extend_from_slice(&[0, 0, 0, 0, 0, 0, 192, 168, 1, 1]);
+        let info = PacketInfo::from(&frame).unwrap();
+        let fields = info.fields();
+
+        assert!(info.to_string().contains('\x1b'));
+        assert!(!fields.to_string().contains('\x1b'));
+        assert_eq!(fields["packet"]["arp"]["sender_ip"], "192.168.1.10");
+        assert_eq!(fields["packet"]["arp"]["target_ip"], "192.168.1.1");
+        assert!(fields["packet"]["dns"].is_null());
     }
 }
@@ -0,0 +1,843 @@
+use crate::anonymizer::Anonymizer;
+use crate::blacklist::{Blacklist, Connections};
+use crate::counters::{self, Counters};
+use crate::error::Error;
+use crate::exporter::Exporter;
+use crate::flow::{FlowRecord, FlowTable};
+use crate::hexdump::HexDump;
+use crate::limits::Limits;
+use crate::output::{self, Output};
+use crate::packet::{self, PacketInfo};
+use crate::packet_streaming::{Packet, PacketData, PacketHeader, PacketMetadata};
+use crate::privacy::Redactor;
+use crate::sniffer::{Sniffer, CAPTURE_TIMEOUT};
+use crate::source::PacketSource;
+use bytes::Bytes;
+use clap::ValueEnum;
+use futures_util::Stream;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// `StreamMode` tells what is sent to the gRPC server: the raw packets, their parsed metadata or
+/// both.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum StreamMode {
+    Raw,
+    Metadata,
+    Both,
+}
+
+/// `Observer` is told about the packets of a capture as the pipeline delivers them, in capture
+/// order, on the thread of the collector. Applications embedding the sniffer implement it to
+/// account, log or show the packets, such as the `sniffer` binary for its metrics, its logs and its
+/// terminal UI. The capture waits for the collector, so its methods must not block.
+pub trait Observer: Send {
+    /// It tells whether the observer wants the hex dump of the packets, when the sniffer has a
+    /// hex dump formatter.
+    fn dumps(&self) -> bool {
+        false
+    }
+
+    /// It is told about a packet delivered.
+    ///
+    /// Arguments:
+    ///
+    /// * `header`: The pcap header of the packet as captured.
+    /// * `info`: The parsed packet, or `None` if it could not be parsed.
+    /// * `data`: The bytes of the packet, as saved and forwarded.
+    /// * `dump`: The hex dump of the packet, if wanted.
+    fn packet(
+        &mut self,
+        header: &pcap::PacketHeader,
+        info: Option<&PacketInfo>,
+        data: &Bytes,
+        dump: Option<&str>,
+    );
+
+    /// It is told about a packet ignored by a rule of the blacklist.
+    fn ignored(&mut self, _info: &PacketInfo, _rule: &str) {}
+
+    /// It is told that the capture ended, once all its packets are delivered.
+    fn end(&mut self) {}
+}
+
+/// Interval between two reads of the statistics of the capture, and between two flushes of the
+/// packet output.
+const PCAP_STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Maximum time spent processing the packets already captured once a stop is requested.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Number of packets waiting for each worker.
+const WORKER_QUEUE: usize = 1024;
+
+/// Maximum number of packets captured and not yet delivered. The capture waits for the pipeline
+/// above it.
+const MAX_IN_FLIGHT: usize = 8192;
+
+/// Number of packets waiting to be sent to the gRPC server.
+const SEND_QUEUE: usize = 1024;
+
+/// Interval between two attempts at handing a packet to a worker whose queue is full.
+const QUEUE_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Maximum number of workers used by default.
+const DEFAULT_WORKERS: usize = 4;
+
+/// `Pipeline` processes the packets of a capture on several threads: capture threads read the
+/// packets and hand them to workers, which parse, filter, redact and anonymize them, and a
+/// collector puts them back in capture order before saving, writing and sending them.
+///
+/// The packets of a connection are all handed to the same worker, so that the blacklist sees them
+/// in order. The number of packets between the capture and the collector is bounded, the capture
+/// waiting for the pipeline once it is reached, which the counters account as stalls.
+///
+/// Properties:
+///
+/// * `threads`: The threads of the pipeline.
+/// * `packets`: The packets to send to the gRPC server, in capture order.
+/// * `error`: The error of the source which ended the capture, if any.
+/// * `stop`: The handle asking the capture to stop, taken from the sniffer.
+pub struct Pipeline {
+    threads: Vec<JoinHandle<()>>,
+    packets: Option<tokio::sync::mpsc::Receiver<Packet>>,
+    error: Arc<Mutex<Option<pcap::Error>>>,
+    stop: Arc<AtomicBool>,
+}
+
+/// `Captured` is a packet read by a capture thread.
+///
+/// Properties:
+///
+/// * `seq`: The position of the packet in the capture.
+/// * `header`: The pcap header of the packet.
+/// * `data`: The bytes of the packet, shared with the packet once processed when it is left
+///   untouched.
+struct Captured {
+    seq: u64,
+    header: pcap::PacketHeader,
+    data: Bytes,
+}
+
+/// `Verdict` is the outcome of processing a packet: the packet to deliver, or the parsed packet
+/// and the rule of the blacklist which ignored it.
+enum Verdict {
+    Kept(Box<Processed>),
+    Ignored(Box<PacketInfo>, String),
+}
+
+/// `Processed` is a packet ready to be saved, written and sent.
+///
+/// Properties:
+///
+/// * `original`: The pcap header of the packet as captured.
+/// * `header`: The pcap header of the packet as saved and forwarded.
+/// * `data`: The bytes of the packet as saved and forwarded.
+/// * `info`: The parsed packet, or `None` if it could not be parsed.
+/// * `dump`: The hex dump of the packet, if needed.
+/// * `message`: The message sent to the gRPC server, if streaming.
+struct Processed {
+    original: pcap::PacketHeader,
+    header: pcap::PacketHeader,
+    data: Bytes,
+    info: Option<PacketInfo>,
+    dump: Option<String>,
+    message: Option<Packet>,
+}
+
+/// `Dispatcher` holds what the capture threads share to hand their packets to the workers.
+///
+/// Properties:
+///
+/// * `limits`: The conditions which end the capture.
+/// * `queues`: The queues of the workers.
+/// * `credits`: The credits of the packets the pipeline can still take.
+/// * `seq`: The number of packets captured, giving the position of the next one.
+/// * `reserved`: The number of packets captured or being read, checked against the count limit.
+/// * `bytes`: The number of bytes on the wire captured.
+/// * `ended`: Whether a stop condition was reached, or a source failed.
+/// * `error`: The first error of a source, such as a lost network interface.
+/// * `counters`: The counters of the capture.
+/// * `stop`: The handle asking the capture to stop.
+struct Dispatcher {
+    limits: Limits,
+    queues: Vec<SyncSender<Captured>>,
+    credits: Mutex<Receiver<()>>,
+    seq: AtomicU64,
+    reserved: AtomicU64,
+    bytes: AtomicU64,
+    ended: AtomicBool,
+    error: Arc<Mutex<Option<pcap::Error>>>,
+    counters: Arc<Counters>,
+    stop: Arc<AtomicBool>,
+}
+
+/// `Worker` holds what parsing, filtering, redacting and anonymizing a packet needs.
+///
+/// Properties:
+///
+/// * `blacklist`: The blacklist shared by the workers and the terminal UI, only locked for writing
+///   when its rules are edited.
+/// * `connections`: The connections of the worker which matched a host rule of the blacklist.
+/// * `anonymizer`: The anonymizer of the worker, each worker caching its own addresses.
+/// * `redactor`: The redactor removing payloads.
+/// * `hexdump`: The formatter dumping the bytes of each packet, if enabled.
+/// * `dumps`: Whether the dump of the packets is shown, by the packet output or an observer.
+/// * `stream_mode`: What is sent to the gRPC server, or `None` if not streaming.
+/// * `counters`: The counters of the capture.
+#[derive(Clone)]
+struct Worker {
+    blacklist: Option<Arc<RwLock<Blacklist>>>,
+    connections: Connections,
+    anonymizer: Option<Anonymizer>,
+    redactor: Option<Redactor>,
+    hexdump: Option<HexDump>,
+    dumps: bool,
+    stream_mode: Option<StreamMode>,
+    counters: Arc<Counters>,
+}
+
+/// `Collector` holds what the packets are delivered to, in capture order.
+///
+/// Properties:
+///
+/// * `savefile`: The file the packets are saved to.
+/// * `output`: Where the description of each packet is written.
+/// * `flows`: The flow table grouping packets into flows, if enabled.
+/// * `exporter`: The exporter sending expired flows to a collector.
+/// * `observers`: What is told about the packets delivered.
+/// * `sender`: The queue of the packets sent to the gRPC server, if streaming.
+/// * `live`: Whether the packets are captured on a network interface, whose flows expire in
+///   wall-clock time rather than in the time of the packets.
+/// * `counters`: The counters of the capture.
+struct Collector {
+    savefile: Option<pcap::Savefile>,
+    output: Option<Output>,
+    flows: Option<FlowTable>,
+    exporter: Option<Exporter>,
+    observers: Vec<Box<dyn Observer>>,
+    sender: Option<tokio::sync::mpsc::Sender<Packet>>,
+    live: bool,
+    counters: Arc<Counters>,
+}
+
+impl Pipeline {
+    /// It starts the threads of the pipeline.
+    ///
+    /// Arguments:
+    ///
+    /// * `core`: The sources of the packets and what the packets go through.
+    /// * `workers`: The number of workers, at most 4 by default.
+    /// * `stream_mode`: What is sent to the gRPC server, or `None` if not streaming.
+    /// * `observers`: What is told about the packets delivered.
+    ///
+    /// Returns:
+    ///
+    /// A new instance of the Pipeline struct.
+    pub fn start(
+        core: Sniffer,
+        workers: Option<usize>,
+        stream_mode: Option<StreamMode>,
+        observers: Vec<Box<dyn Observer>>,
+    ) -> Self {
+        let Sniffer {
+            sources,
+            savefile,
+            output,
+            hexdump,
+            blacklist,
+            flows,
+            exporter,
+            redactor,
+            anonymizer,
+            limits,
+            counters,
+            stop,
+        } = core;
+        let count = workers.unwrap_or_else(|| {
+            std::thread::available_parallelism()
+                .map_or(1, |count| count.get())
+                .min(DEFAULT_WORKERS)
+        });
+        let count = count.max(1);
+
+        let worker = Worker {
+            blacklist,
+            connections: Connections::default(),
+            anonymizer,
+            redactor,
+            hexdump,
+            dumps: output.is_some() || observers.iter().any(|observer| observer.dumps()),
+            stream_mode,
+            counters: counters.clone(),
+        };
+        let (results, received) = mpsc::sync_channel(count * WORKER_QUEUE);
+        let mut queues = Vec::new();
+        let mut threads = Vec::new();
+        for index in 0..count {
+            let (queue, packets) = mpsc::sync_channel(WORKER_QUEUE);
+            let (worker, results) = (worker.clone(), results.clone());
+
+            queues.push(queue);
+            threads.push(spawn(format!("worker-{}", index), &stop, move || {
+                worker.run(packets, results)
+            }));
+        }
+        drop(results);
+
+        let (credits, available) = mpsc::sync_channel(MAX_IN_FLIGHT);
+        for _ in 0..MAX_IN_FLIGHT {
+            credits.send(()).ok();
+        }
+        let live = sources.iter().any(|source| source.live());
+        let error = Arc::new(Mutex::new(None));
+        let dispatcher = Arc::new(Dispatcher {
+            limits,
+            queues,
+            credits: Mutex::new(available),
+            seq: AtomicU64::new(0),
+            reserved: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            ended: AtomicBool::new(false),
+            error: error.clone(),
+            counters: counters.clone(),
+            stop: stop.clone(),
+        });
+        for (index, source) in sources.into_iter().enumerate() {
+            let dispatcher = dispatcher.clone();
+            threads.push(spawn(format!("capture-{}", index), &stop, move || {
+                dispatcher.run(source)
+            }));
+        }
+
+        let (sender, packets) = match stream_mode.is_some() {
+            true => {
+                let (sender, packets) = tokio::sync::mpsc::channel(SEND_QUEUE);
+                (Some(sender), Some(packets))
+            }
+            false => (None, None),
+        };
+        let collector = Collector {
+            savefile,
+            output,
+            flows,
+            exporter,
+            observers,
+            sender,
+            live,
+            counters,
+        };
+        threads.push(spawn("collector".to_string(), &stop, move || {
+            collector.run(received, credits)
+        }));
+
+        Pipeline {
+            threads,
+            packets,
+            error,
+            stop,
+        }
+    }
+
+    /// It returns the handle asking the capture to stop once set, the packets pending on network
+    /// interfaces being drained first.
+    pub fn stopper(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    /// It returns the packets to send to the gRPC server, in capture order.
+    pub fn packets(&mut self) -> impl Stream<Item = Packet> {
+        let mut packets = self.packets.take();
+
+        async_stream::stream! {
+            if let Some(ref mut packets) = packets {
+                while let Some(packet) = packets.recv().await {
+                    yield packet;
+                }
+            }
+        }
+    }
+
+    /// It waits for the capture to end and for its packets to be delivered.
+    ///
+    /// Returns:
+    ///
+    /// An internal error if a thread of the pipeline panicked, or the error of the source which
+    /// ended the capture, if it did not end normally.
+    pub fn join(self) -> Result<(), Error> {
+        let mut panicked = None;
+        // All the threads are joined, those left ending once a panicking thread asked for a stop.
+        for thread in self.threads {
+            let name = thread.thread().name().unwrap_or("pipeline").to_string();
+            if let Err(panic) = thread.join() {
+                let message = match panic.downcast_ref::<&str>() {
+                    Some(message) => message.to_string(),
+                    None => panic.downcast_ref::<String>().cloned().unwrap_or_default(),
+                };
+                panicked.get_or_insert_with(|| {
+                    Error::Internal(format!("The {} thread panicked: {}", name, message))
+                });
+            }
+        }
+        if let Some(err) = panicked {
+            return Err(err);
+        }
+        match self.error.lock().unwrap().take() {
+            Some(err) => Err(Error::Capture(err)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// It starts a named thread of the pipeline, which asks the capture to stop if it panics, so that
+/// the other threads end as well.
+///
+/// Arguments:
+///
+/// * `name`: The name of the thread.
+/// * `stop`: The handle asking the capture to stop.
+/// * `run`: What the thread runs.
+fn spawn(
+    name: String,
+    stop: &Arc<AtomicBool>,
+    run: impl FnOnce() + Send + 'static,
+) -> JoinHandle<()> {
+    let stop = stop.clone();
+
+    std::thread::Builder::new()
+        .name(name)
+        .spawn(move || {
+            if let Err(panic) = std::panic::catch_unwind(AssertUnwindSafe(run)) {
+                stop.store(true, Ordering::SeqCst);
+                std::panic::resume_unwind(panic);
+            }
+        })
+        .expect("Could not start a thread of the pipeline")
+}
+
+impl Dispatcher {
+    /// It reads the packets of a source until it ends, a stop condition is reached or a stop is
+    /// requested, and hands each of them to the worker of its connection.
+    ///
+    /// Arguments:
+    ///
+    /// * `source`: The source of the packets.
+    fn run(&self, mut source: Box<dyn PacketSource>) {
+        let mut last_stats = Instant::now();
+        // When the stop was requested, and the time of capture after which packets are left out.
+        let mut stopping: Option<(Instant, Duration)> = None;
+        let live = source.live();
+
+        'capture: loop {
+            if self.must_end(live, &mut stopping) {
+                break;
+            }
+            // The packet is counted before it is read, so that the capture threads together never
+            // read more packets than the count limit.
+            let captured = self.reserved.fetch_add(1, Ordering::SeqCst);
+            if let Some(reason) = self
+                .limits
+                .reached(captured, self.bytes.load(Ordering::SeqCst))
+            {
+                self.reserved.fetch_sub(1, Ordering::SeqCst);
+                // The other capture threads stop without logging it again.
+                if !self.ended.swap(true, Ordering::SeqCst) {
+                    log::info!("Capture stopped: {}", reason);
+                }
+                break;
+            }
+            if last_stats.elapsed() >= PCAP_STATS_INTERVAL {
+                last_stats = Instant::now();
+                // Sources other than network interfaces have no statistics.
+                if let Ok(stats) = source.stats() {
+                    self.counters.set_pcap_stats(&stats);
+                }
+            }
+
+            let packet = match source.next_packet() {
+                Ok(packet) => packet,
+                Err(err) => {
+                    self.reserved.fetch_sub(1, Ordering::SeqCst);
+                    match err {
+                        // Once stopping, a timeout means that no packet is left to drain.
+                        pcap::Error::TimeoutExpired if stopping.is_none() => continue,
+                        pcap::Error::TimeoutExpired | pcap::Error::NoMorePackets => break,
+                        // The other capture threads stop as well.
+                        err => {
+                            self.ended.store(true, Ordering::SeqCst);
+                            self.error.lock().unwrap().get_or_insert(err);
+                            break;
+                        }
+                    }
+                }
+            };
+            // Only the packets captured before the stop are drained, and the ones after it are
+            // neither counted nor delivered.
+            if let Some((_, requested)) = stopping {
+                if output::since_epoch(packet.header) > requested {
+                    break;
+                }
+            }
+            // The packet is counted before it is handed over, so that the capture never goes over
+            // its number of bytes.
+            let len = packet.header.len as u64;
+            let bytes = self.bytes.fetch_add(len, Ordering::SeqCst) + len;
+            if let Some(reason) = self.limits.exceeded(bytes) {
+                self.bytes.fetch_sub(len, Ordering::SeqCst);
+                self.reserved.fetch_sub(1, Ordering::SeqCst);
+                if !self.ended.swap(true, Ordering::SeqCst) {
+                    log::info!("Capture stopped: {}", reason);
+                }
+                break;
+            }
+            counters::inc(&self.counters.captured);
+            self.counters
+                .captured_bytes
+                .fetch_add(packet.header.len as u64, Ordering::Relaxed);
+
+            let stalled = Instant::now();
+            let mut stall = false;
+            // The credit is taken before the position of the packet, so that the collector
+            // never waits for a packet whose capture thread waits for a credit.
+            {
+                let credits = self.credits.lock().unwrap();
+                if credits.try_recv().is_err() {
+                    stall = true;
+                    loop {
+                        match credits.recv_timeout(CAPTURE_TIMEOUT) {
+                            Ok(()) => break,
+                            Err(RecvTimeoutError::Timeout)
+                                if !self.must_end(live, &mut stopping) => {}
+                            Err(_) => break 'capture,
+                        }
+                    }
+                }
+            }
+            let queue =
+                &self.queues[(packet::flow_hash(packet.data) % self.queues.len() as u64) as usize];
+            let mut captured_packet = Captured {
+                seq: self.seq.fetch_add(1, Ordering::SeqCst),
+                header: *packet.header,
+                // The only copy of the packet, out of the buffer of the capture.
+                data: Bytes::copy_from_slice(packet.data),
+            };
+            self.counters
+                .pipeline_queued
+                .fetch_add(1, Ordering::Relaxed);
+            loop {
+                match queue.try_send(captured_packet) {
+                    Ok(()) => break,
+                    // The packet is given up, the collector delivering the ones after it anyway.
+                    Err(TrySendError::Full(_)) if self.must_end(live, &mut stopping) => {
+                        self.counters
+                            .pipeline_queued
+                            .fetch_sub(1, Ordering::Relaxed);
+                        break 'capture;
+                    }
+                    Err(TrySendError::Full(packet)) => {
+                        stall = true;
+                        captured_packet = packet;
+                        std::thread::sleep(QUEUE_POLL_INTERVAL);
+                    }
+                    Err(TrySendError::Disconnected(_)) => break 'capture,
+                }
+            }
+            if stall {
+                counters::inc(&self.counters.pipeline_stalls);
+                self.counters
+                    .pipeline_stall_micros
+                    .fetch_add(stalled.elapsed().as_micros() as u64, Ordering::Relaxed);
+            }
+        }
+
+        if let Ok(stats) = source.stats() {
+            self.counters.set_pcap_stats(&stats);
+        }
+    }
+
+    /// It tells if a capture thread must end, because the capture ended or a stop was requested.
+    /// A network interface is drained first, until the drain timeout.
+    ///
+    /// Arguments:
+    ///
+    /// * `live`: Whether the source is a network interface, with pending packets to drain.
+    /// * `stopping`: When the stop was requested, and the time of capture after which packets are
+    ///   left out, set once it is noticed.
+    ///
+    /// Returns:
+    ///
+    /// Whether the capture thread must end.
+    fn must_end(&self, live: bool, stopping: &mut Option<(Instant, Duration)>) -> bool {
+        if self.ended.load(Ordering::SeqCst) {
+            return true;
+        }
+        if stopping.is_none() && self.stop.load(Ordering::SeqCst) {
+            // A file has no pending packets to drain.
+            if !live {
+                return true;
+            }
+            let requested = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            *stopping = Some((Instant::now(), requested));
+        }
+        stopping.is_some_and(|(since, _)| since.elapsed() >= DRAIN_TIMEOUT)
+    }
+}
+
+impl Worker {
+    /// It processes the packets of its queue until the capture ends.
+    ///
+    /// Arguments:
+    ///
+    /// * `packets`: The queue of the worker.
+    /// * `results`: The queue of the collector.
+    fn run(mut self, packets: Receiver<Captured>, results: SyncSender<(u64, Verdict)>) {
+        for packet in packets {
+            let seq = packet.seq;
+            if results.send((seq, self.process(packet))).is_err() {
+                break;
+            }
+        }
+    }
+
+    /// It parses a packet, checks it against the blacklist, then redacts and anonymizes it.
+    ///
+    /// Arguments:
+    ///
+    /// * `captured`: The packet read by a capture thread.
+    ///
+    /// Returns:
+    ///
+    /// The packet ready to be delivered, or the parsed packet if the blacklist ignores it.
+    fn process(&mut self, captured: Captured) -> Verdict {
+        let packet = pcap::Packet::new(&captured.header, &captured.data);
+
+        let mut info = PacketInfo::from(&packet);
+        let ts = output::since_epoch(&captured.header);
+        match info {
+            Some(ref mut parsed) => {
+                counters::inc(&self.counters.parsed);
+                if let Some(ref blacklist) = self.blacklist {
+                    let rule =
+                        blacklist
+                            .read()
+                            .unwrap()
+                            .contains(parsed, &mut self.connections, ts);
+                    if let Some(rule) = rule {
+                        counters::inc(&self.counters.blacklisted);
+                        return Verdict::Ignored(Box::new(info.unwrap()), rule);
+                    }
+                }
+                if let Some(ref mut anonymizer) = self.anonymizer {
+                    anonymizer.anonymize_info(parsed);
+                }
+            }
+            None => counters::inc(&self.counters.unparseable),
+        }
+
+        let (header, mut data) = match self.redactor {
+            Some(ref redactor) => redactor.apply(&packet),
+            None => (captured.header, Cow::Borrowed(packet.data)),
+        };
+        if let Some(ref mut anonymizer) = self.anonymizer {
+            anonymizer.anonymize_packet(data.to_mut());
+        }
+        // Packets left untouched, or only truncated, share the bytes of the captured packet.
+        let data = match data {
+            Cow::Borrowed(data) => captured.data.slice_ref(data),
+            Cow::Owned(data) => Bytes::from(data),
+        };
+
+        // The dump shows the bytes as saved and forwarded, once redacted and anonymized.
+        let dump = match self.hexdump {
+            Some(ref hexdump) if self.dumps => Some(hexdump.dump(&data)),
+            _ => None,
+        };
+        let message = self.stream_mode.map(|mode| Packet {
+            header: Some(PacketHeader {
+                ts_sec: header.ts.tv_sec as u32,
+                ts_usec: header.ts.tv_usec as u32,
+                caplen: header.caplen,
+                len: header.len,
+            }),
+            data: (mode != StreamMode::Metadata).then(|| PacketData { data: data.clone() }),
+            metadata: match mode {
+                StreamMode::Raw => None,
+                _ => info.as_ref().map(PacketMetadata::from),
+            },
+        });
+
+        Verdict::Kept(Box::new(Processed {
+            original: captured.header,
+            header,
+            data,
+            info,
+            dump,
+            message,
+        }))
+    }
+}
+
+impl Collector {
+    /// It delivers the processed packets in capture order until the workers are done, then
+    /// flushes what it delivered them to.
+    ///
+    /// Arguments:
+    ///
+    /// * `results`: The packets processed by the workers, with their position in the capture.
+    /// * `credits`: The credits given back to the capture for each packet delivered.
+    fn run(mut self, results: Receiver<(u64, Verdict)>, credits: SyncSender<()>) {
+        let mut pending = BTreeMap::new();
+        let mut next = 0;
+        let mut last_flush = Instant::now();
+
+        loop {
+            match results.recv_timeout(PCAP_STATS_INTERVAL) {
+                Ok((seq, verdict)) => {
+                    pending.insert(seq, verdict);
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            while let Some(verdict) = pending.remove(&next) {
+                next += 1;
+                credits.try_send(()).ok();
+                self.counters
+                    .pipeline_queued
+                    .fetch_sub(1, Ordering::Relaxed);
+                self.deliver(verdict);
+            }
+            // The flows of a quiet network interface expire without waiting for a packet.
+            if self.live {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default();
+                self.sweep(now);
+            }
+
+            if last_flush.elapsed() >= PCAP_STATS_INTERVAL {
+                last_flush = Instant::now();
+                // Packets trickling in are not held back until the buffer fills up.
+                if let Some(ref mut output) = self.output {
+                    output.flush().ok();
+                }
+            }
+        }
+
+        // The packets after one given up by a stopping capture are still delivered, in order.
+        for (_, verdict) in pending {
+            self.counters
+                .pipeline_queued
+                .fetch_sub(1, Ordering::Relaxed);
+            self.deliver(verdict);
+        }
+        self.finish();
+    }
+
+    /// It tells the observers about a packet, then writes, saves and sends it. The observers are
+    /// only told here, so that the workers never wait for each other.
+    fn deliver(&mut self, verdict: Verdict) {
+        let packet = match verdict {
+            Verdict::Kept(packet) => packet,
+            Verdict::Ignored(info, rule) => {
+                for observer in &mut self.observers {
+                    observer.ignored(&info, &rule);
+                }
+                return;
+            }
+        };
+        let info = packet.info.as_ref();
+        let dump = packet.dump.as_deref();
+
+        if let (Some(info), Some(ref mut flows)) = (info, &mut self.flows) {
+            let ts = output::since_epoch(&packet.original);
+            let records = flows.update(info, ts, packet.original.len);
+            report_flows(&records, &mut self.exporter);
+            if !self.live {
+                self.sweep(ts);
+            }
+        }
+
+        for observer in &mut self.observers {
+            observer.packet(&packet.original, info, &packet.data, dump);
+        }
+        if let Some(ref mut output) = self.output {
+            if let Err(err) = output.write(&packet.original, info, dump) {
+                log::warn!("Could not write the packet output: {}", err);
+                self.output = None;
+            }
+        }
+
+        if let Some(ref mut savefile) = self.savefile {
+            savefile.write(&pcap::Packet::new(&packet.header, &packet.data));
+            counters::inc(&self.counters.saved);
+        }
+
+        if let (Some(message), Some(ref sender)) = (packet.message, &self.sender) {
+            match sender.blocking_send(message) {
+                Ok(()) => counters::inc(&self.counters.sent),
+                // The gRPC stream is gone, which its client reports.
+                Err(_) => self.sender = None,
+            }
+        }
+    }
+
+    /// It reports the flows expired at a time of the capture.
+    ///
+    /// Arguments:
+    ///
+    /// * `now`: The current time, since the UNIX epoch.
+    fn sweep(&mut self, now: Duration) {
+        if let Some(ref mut flows) = self.flows {
+            report_flows(&flows.sweep(now), &mut self.exporter);
+        }
+    }
+
+    /// It flushes the savefile, the packet output and the flows, then tells the observers that the
+    /// capture ended.
+    fn finish(mut self) {
+        if let Some(ref mut savefile) = self.savefile {
+            if let Err(err) = savefile.flush() {
+                log::warn!("Could not flush the savefile: {}", err);
+            }
+        }
+        if let Some(ref mut output) = self.output {
+            if let Err(err) = output.flush() {
+                log::warn!("Could not flush the packet output: {}", err);
+            }
+        }
+        if let Some(ref mut flows) = self.flows {
+            report_flows(&flows.flush(), &mut self.exporter);
+        }
+        for observer in &mut self.observers {
+            observer.end();
+        }
+    }
+}
+
+/// It logs expired flow records and sends them to the flow collector, if any.
+///
+/// Arguments:
+///
+/// * `records`: The expired flow records.
+/// * `exporter`: The exporter sending flows to a collector.
+fn report_flows(records: &[FlowRecord], exporter: &mut Option<Exporter>) {
+    if records.is_empty() {
+        return;
+    }
+    for record in records {
+        log::info!("FLOW: {}", record);
+    }
+    if let Some(ref mut exporter) = exporter {
+        if let Err(err) = exporter.export(records) {
+            log::warn!("Could not export flows: {}", err);
+        }
+    }
+}
@@ -0,0 +1,396 @@
+use crate::checksum;
+use crate::packet;
+use clap::ValueEnum;
+use pnet::packet::ethernet::{EtherTypes, EthernetPacket, MutableEthernetPacket};
+use pnet::packet::ip::IpNextHeaderProtocols;
+use pnet::packet::ipv4::MutableIpv4Packet;
+use pnet::packet::ipv6::{Ipv6Packet, MutableIpv6Packet};
+use pnet::packet::udp::MutableUdpPacket;
+use pnet::packet::{MutablePacket, Packet};
+use std::borrow::Cow;
+
+/// `RedactMode` tells how the payload of a packet is redacted: cut off, or overwritten with
+/// zeros.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum RedactMode {
+    Truncate,
+    Zero,
+}
+
+/// `Redactor` removes the payload of packets before they are saved or forwarded, keeping their
+/// headers intact.
+///
+/// Properties:
+///
+/// * `mode`: How the transport layer payload is removed, if it is.
+/// * `snaplen`: The maximum number of bytes kept from each packet.
+#[derive(Clone)]
+pub struct Redactor {
+    mode: Option<RedactMode>,
+    snaplen: Option<usize>,
+}
+
+impl Redactor {
+    /// It creates a redactor if at least one of its options is set.
+    ///
+    /// Arguments:
+    ///
+    /// * `mode`: How the transport layer payload is removed, if it is.
+    /// * `snaplen`: The maximum number of bytes kept from each packet.
+    ///
+    /// Returns:
+    ///
+    /// A new instance of the Redactor struct, or `None` if packets are kept as is.
+    pub fn new(mode: Option<RedactMode>, snaplen: Option<usize>) -> Option<Self> {
+        (mode.is_some() || snaplen.is_some()).then_some(Redactor { mode, snaplen })
+    }
+
+    /// It redacts a packet.
+    ///
+    /// Truncated packets are cut after their transport header, their IP and UDP lengths and their
+    /// checksums being rewritten for the packet they have become. Zeroed packets keep their length
+    /// and get their checksums recomputed. Frames whose payload can't be found, such as frames
+    /// which are not IP packets, VLAN-tagged frames or malformed headers, are cut or zeroed right
+    /// after their Ethernet header, except ARP packets, which have no payload. The snapshot length
+    /// then cuts packets the way a capture would, their captured length being set to the number
+    /// of bytes kept while their original length is left as is.
+    ///
+    /// Arguments:
+    ///
+    /// * `packet`: The captured packet.
+    ///
+    /// Returns:
+    ///
+    /// The header and the data of the redacted packet.
+    pub fn apply<'a>(&self, packet: &pcap::Packet<'a>) -> (pcap::PacketHeader, Cow<'a, [u8]>) {
+        let mut header = *packet.header;
+        let mut data = Cow::Borrowed(packet.data);
+
+        let payload_offset = packet::payload_offset(packet.data);
+        let offset = payload_offset.unwrap_or_else(|| header_len(packet.data));
+        match self.mode {
+            Some(RedactMode::Truncate) if offset < packet.data.len() => {
+                let mut truncated = packet.data[..offset].to_vec();
+                if payload_offset.is_some() {
+                    fix_lengths(&mut truncated);
+                    checksum::fix_checksums(&mut truncated);
+                }
+                header.len = truncated.len() as u32;
+                data = Cow::Owned(truncated);
+            }
+            Some(RedactMode::Zero) if offset < packet.data.len() => {
+                let mut zeroed = packet.data.to_vec();
+                zeroed[offset..].fill(0);
+                // The checksum of a segment cut by the capture cannot be computed.
+                if payload_offset.is_some() && header.caplen == header.len {
+                    checksum::fix_checksums(&mut zeroed);
+                }
+                data = Cow::Owned(zeroed);
+            }
+            _ => {}
+        }
+
+        if let Some(snaplen) = self.snaplen {
+            if data.len() > snaplen {
+                data = match data {
+                    Cow::Borrowed(data) => Cow::Borrowed(&data[..snaplen]),
+                    Cow::Owned(mut data) => {
+                        data.truncate(snaplen);
+                        Cow::Owned(data)
+                    }
+                };
+            }
+        }
+
+        header.caplen = data.len() as u32;
+        (header, data)
+    }
+}
+
+/// It returns the length of the headers of a frame whose payload can't be found: its Ethernet
+/// header, followed by the whole packet for ARP packets.
+fn header_len(frame: &[u8]) -> usize {
+    let ethernet_len = EthernetPacket::minimum_packet_size();
+    let len = match EthernetPacket::new(frame).map(|ethernet| ethernet.get_ethertype()) {
+        Some(EtherTypes::Arp) => match frame.get(ethernet_len..) {
+            // The hardware and protocol address lengths follow the hardware and protocol types.
+            Some(arp) if arp.len() >= 6 => {
+                ethernet_len + 8 + 2 * (arp[4] as usize + arp[5] as usize)
+            }
+            _ => ethernet_len,
+        },
+        _ => ethernet_len,
+    };
+    len.min(frame.len())
+}
+
+/// It sets the IP and UDP lengths of a frame to its size, once its payload was cut.
+///
+/// Arguments:
+///
+/// * `frame`: The Ethernet frame to fix.
+fn fix_lengths(frame: &mut [u8]) {
+    let Some(mut ethernet) = MutableEthernetPacket::new(frame) else {
+        return;
+    };
+
+    match ethernet.get_ethertype() {
+        EtherTypes::Ipv4 => {
+            let Some(mut ipv4) = MutableIpv4Packet::new(ethernet.payload_mut()) else {
+                return;
+            };
+            let len = ipv4.packet().len();
+            ipv4.set_total_length(len as u16);
+            // The UDP length of a fragmented datagram covers all of its fragments.
+            if ipv4.get_next_level_protocol() == IpNextHeaderProtocols::Udp
+                && !packet::is_fragment(&ipv4.to_immutable())
+            {
+                fix_udp_length(ipv4.payload_mut());
+            }
+        }
+        EtherTypes::Ipv6 => {
+            let Some(mut ipv6) = MutableIpv6Packet::new(ethernet.payload_mut()) else {
+                return;
+            };
+            let len = ipv6.packet().len() - Ipv6Packet::minimum_packet_size();
+            ipv6.set_payload_length(len as u16);
+            if ipv6.get_next_header() == IpNextHeaderProtocols::Udp {
+                fix_udp_length(ipv6.payload_mut());
+            }
+        }
+        _ => {}
+    }
+}
+
+/// It sets the length of a UDP datagram to the size of the IP payload holding it.
+fn fix_udp_length(payload: &mut [u8]) {
+    let len = payload.len();
+    if let Some(mut datagram) = MutableUdpPacket::new(payload) {
+        datagram.set_length(len as u16);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pnet::packet::ethernet::EtherType;
+    use pnet::packet::ip::IpNextHeaderProtocol;
+    use pnet::packet::ipv4::{self, Ipv4Packet};
+    use pnet::packet::tcp::{self, TcpPacket};
+    use pnet::packet::udp::{self, UdpPacket};
+
+    const PAYLOAD: &[u8] = b"secret payload";
+
+    fn ethernet(ethertype: EtherType, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0; 14];
+        frame[..6].copy_from_slice(&[0x02, 0, 0, 0, 0, 0x02]);
+        frame[6..12].copy_from_slice(&[0x02, 0, 0, 0, 0, 0x01]);
+        frame[12..14].copy_from_slice(&ethertype.0.to_be_bytes());
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    fn ipv4(protocol: IpNextHeaderProtocol, segment: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x45, 0, 0, 0, 0, 1, 0, 0, 64, protocol.0, 0, 0];
+        packet.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2]);
+        packet[2..4].copy_from_slice(&((20 + segment.len()) as u16).to_be_bytes());
+        packet.extend_from_slice(segment);
+        packet
+    }
+
+    fn ipv6(protocol: IpNextHeaderProtocol, segment: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x60, 0, 0, 0, 0, 0, protocol.0, 64];
+        packet[4..6].copy_from_slice(&(segment.len() as u16).to_be_bytes());
+        packet.extend_from_slice(&[0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        packet.extend_from_slice(&[0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2]);
+        packet.extend_from_slice(segment);
+        packet
+    }
+
+    fn udp(payload: &[u8]) -> Vec<u8> {
+        let mut datagram = vec![0x9c, 0x40, 0, 53, 0, 0, 0, 1];
+        datagram[4..6].copy_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+        datagram.extend_from_slice(payload);
+        datagram
+    }
+
+    fn tcp(payload: &[u8]) -> Vec<u8> {
+        let mut segment = vec![
+            0x9c, 0x40, 0, 80, 0, 0, 0, 1, 0, 0, 0, 0, 0x50, 0x18, 0xff, 0xff,
+        ];
+        segment.extend_from_slice(&[0, 1, 0, 0]);
+        segment.extend_from_slice(payload);
+        segment
+    }
+
+    fn redact(mode: RedactMode, frame: &[u8]) -> (pcap::PacketHeader, Vec<u8>) {
+        let header = pcap::PacketHeader {
+            ts: libc::timeval {
+                tv_sec: 0,
+                tv_usec: 0,
+            },
+            caplen: frame.len() as u32,
+            len: frame.len() as u32,
+        };
+        let redactor = Redactor::new(Some(mode), None).unwrap();
+        let (header, data) = redactor.apply(&pcap::Packet::new(&header, frame));
+        (header, data.into_owned())
+    }
+
+    fn contains(data: &[u8], needle: &[u8]) -> bool {
+        data.windows(needle.len()).any(|window| window == needle)
+    }
+
+    #[test]
+    fn truncated_ipv4_udp_gets_its_lengths_and_checksums_fixed() {
+        let mut frame = ethernet(
+            EtherTypes::Ipv4,
+            &ipv4(IpNextHeaderProtocols::Udp, &udp(PAYLOAD)),
+        );
+        checksum::fix_checksums(&mut frame);
+        let (header, data) = redact(RedactMode::Truncate, &frame);
+
+        assert_eq!(data.len(), 14 + 20 + 8);
+        assert_eq!((header.caplen, header.len), (42, 42));
+        let ip = Ipv4Packet::new(&data[14..]).unwrap();
+        assert_eq!(ip.get_total_length(), 28);
+        assert_eq!(ip.get_checksum(), ipv4::checksum(&ip));
+        let datagram = UdpPacket::new(ip.payload()).unwrap();
+        assert_eq!(datagram.get_length(), 8);
+        let expected = udp::ipv4_checksum(&datagram, &ip.get_source(), &ip.get_destination());
+        assert_eq!(datagram.get_checksum(), expected);
+    }
+
+    #[test]
+    fn truncated_ipv6_tcp_gets_its_lengths_and_checksums_fixed() {
+        let mut frame = ethernet(
+            EtherTypes::Ipv6,
+            &ipv6(IpNextHeaderProtocols::Tcp, &tcp(PAYLOAD)),
+        );
+        checksum::fix_checksums(&mut frame);
+        let (_, data) = redact(RedactMode::Truncate, &frame);
+
+        assert_eq!(data.len(), 14 + 40 + 20);
+        let ip = Ipv6Packet::new(&data[14..]).unwrap();
+        assert_eq!(ip.get_payload_length(), 20);
+        let segment = TcpPacket::new(ip.payload()).unwrap();
+        let expected = tcp::ipv6_checksum(&segment, &ip.get_source(), &ip.get_destination());
+        assert_eq!(segment.get_checksum(), expected);
+    }
+
+    #[test]
+    fn zeroed_ipv6_udp_keeps_its_length() {
+        let mut frame = ethernet(
+            EtherTypes::Ipv6,
+            &ipv6(IpNextHeaderProtocols::Udp, &udp(PAYLOAD)),
+        );
+        checksum::fix_checksums(&mut frame);
+        let (header, data) = redact(RedactMode::Zero, &frame);
+
+        assert_eq!(data.len(), frame.len());
+        assert_eq!(header.len, frame.len() as u32);
+        assert!(data[14 + 40 + 8..].iter().all(|byte| *byte == 0));
+        let ip = Ipv6Packet::new(&data[14..]).unwrap();
+        let datagram = UdpPacket::new(ip.payload()).unwrap();
+        let expected = udp::ipv6_checksum(&datagram, &ip.get_source(), &ip.get_destination());
+        assert_eq!(datagram.get_checksum(), expected);
+    }
+
+    #[test]
+    fn vlan_tagged_frames_are_cut_after_their_ethernet_header() {
+        let mut tagged = vec![0, 10, 0x08, 0x00];
+        tagged.extend(ipv4(IpNextHeaderProtocols::Udp, &udp(PAYLOAD)));
+        let frame = ethernet(EtherType(0x8100), &tagged);
+
+        let (header, data) = redact(RedactMode::Truncate, &frame);
+        assert_eq!(data, frame[..14]);
+        assert_eq!((header.caplen, header.len), (14, 14));
+
+        let (_, data) = redact(RedactMode::Zero, &frame);
+        assert_eq!(data.len(), frame.len());
+        assert!(!contains(&data, PAYLOAD));
+    }
+
+    #[test]
+    fn arp_packets_are_kept_whole() {
+        let mut arp = vec![0, 1, 0x08, 0x00, 6, 4, 0, 1];
+        arp.extend_from_slice(&[0x02, 0, 0, 0, 0, 0x01, 10, 0, 0, 1]);
+        arp.extend_from_slice(&[0, 0, 0, 0, 0, 0, 10, 0, 0, 2]);
+        let mut frame = ethernet(EtherTypes::Arp, &arp);
+
+        assert_eq!(redact(RedactMode::Truncate, &frame).1, frame);
+        // The padding of short frames is cut.
+        frame.extend_from_slice(&[0xaa; 18]);
+        assert_eq!(redact(RedactMode::Truncate, &frame).1, frame[..42]);
+    }
+
+    #[test]
+    fn truncated_headers_keep_no_payload() {
+        // A TCP header claiming more options than the frame holds.
+        let mut segment = tcp(PAYLOAD);
+        segment[12] = 0xf0;
+        let frame = ethernet(
+            EtherTypes::Ipv4,
+            &ipv4(IpNextHeaderProtocols::Tcp, &segment),
+        );
+
+        let (_, data) = redact(RedactMode::Truncate, &frame);
+        assert_eq!(data, frame[..14]);
+        let (_, data) = redact(RedactMode::Zero, &frame);
+        assert!(!contains(&data, PAYLOAD));
+
+        // A UDP header cut in the middle.
+        let packet = ipv4(IpNextHeaderProtocols::Udp, &udp(PAYLOAD));
+        let frame = ethernet(EtherTypes::Ipv4, &packet[..24]);
+        assert_eq!(redact(RedactMode::Truncate, &frame).1, frame[..14]);
+
+        let frame = ethernet(EtherType(0x88b5), PAYLOAD);
+        assert_eq!(redact(RedactMode::Truncate, &frame).1, frame[..14]);
+    }
+
+    #[test]
+    fn ipv4_fragments_keep_no_payload() {
+        let mut packet = ipv4(IpNextHeaderProtocols::Tcp, &tcp(PAYLOAD));
+        // A fragment other than the first, whose payload only looks like a TCP header.
+        packet[6..8].copy_from_slice(&100u16.to_be_bytes());
+        let frame = ethernet(EtherTypes::Ipv4, &packet);
+
+        let (_, data) = redact(RedactMode::Truncate, &frame);
+        assert_eq!(data.len(), 14 + 20);
+        let (_, data) = redact(RedactMode::Zero, &frame);
+        assert_eq!(data.len(), frame.len());
+        assert!(data[14 + 20..].iter().all(|byte| *byte == 0));
+        let ip = Ipv4Packet::new(&data[14..]).unwrap();
+        assert_eq!(ip.get_checksum(), ipv4::checksum(&ip));
+
+        // The first fragment, whose segment goes on in the next fragments.
+        let mut packet = ipv4(IpNextHeaderProtocols::Tcp, &tcp(PAYLOAD));
+        packet[6] = 0x20;
+        let frame = ethernet(EtherTypes::Ipv4, &packet);
+
+        for mode in [RedactMode::Truncate, RedactMode::Zero] {
+            let (_, data) = redact(mode, &frame);
+            assert!(!contains(&data, PAYLOAD));
+            let ip = Ipv4Packet::new(&data[14..]).unwrap();
+            assert_eq!(ip.get_checksum(), ipv4::checksum(&ip));
+            // The TCP checksum, which covers the whole segment, is left as captured.
+            assert_eq!(data[14 + 20 + 16..14 + 20 + 18], [0, 1]);
+        }
+    }
+
+    #[test]
+    fn ipv6_fragments_keep_their_checksums() {
+        let mut fragment = vec![IpNextHeaderProtocols::Udp.0, 0, 0, 0, 0, 0, 0, 1];
+        fragment.extend(udp(PAYLOAD));
+        let frame = ethernet(
+            EtherTypes::Ipv6,
+            &ipv6(IpNextHeaderProtocols::Ipv6Frag, &fragment),
+        );
+
+        let (_, data) = redact(RedactMode::Zero, &frame);
+        assert_eq!(data.len(), frame.len());
+        assert!(!contains(&data, PAYLOAD));
+        // The payload is zeroed from the fragment header on, and no UDP checksum is written in it.
+        assert!(data[14 + 40..].iter().all(|byte| *byte == 0));
+    }
+}
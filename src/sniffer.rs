@@ -1,104 +1,214 @@
-use crate::args::Args;
-use crate::logger;
-use std::error::Error;
-
-#[derive(serde::Deserialize)]
-pub struct Blacklist {
-    pub from: Vec<String>,
-    pub into: Vec<String>,
-}
+use crate::anonymizer::Anonymizer;
+use crate::blacklist::Blacklist;
+use crate::counters::Counters;
+use crate::error::Error;
+use crate::exporter::Exporter;
+use crate::flow::FlowTable;
+use crate::hexdump::HexDump;
+use crate::limits::Limits;
+use crate::output::Output;
+use crate::privacy::Redactor;
+use crate::source::PacketSource;
+use std::io;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Time after which reading a live capture gives up when no packet arrives, so that the capture
+/// loop can notice a stop request.
+pub const CAPTURE_TIMEOUT: Duration = Duration::from_millis(100);
 
-/// `Sniffer` is a struct that contains a `capture` field of type `pcap::Capture<dyn pcap::Activated>`
-/// and a `savefile` field of type `Option<pcap::Savefile>`.
+/// `Sniffer` is a struct that contains a `sources` field of type `Vec<Box<dyn PacketSource>>` and a
+/// `savefile` field of type `Option<pcap::Savefile>`.
 ///
-/// The `capture` field is a `pcap::Capture` object that is created by the
-/// `pcap::Capture::from_device()` function.
+/// The `sources` field holds a libpcap capture, a pcap file, or the AF_PACKET sockets of a fanout
+/// group sharing the network interface, created by the `source::open_file()` and
+/// `source::open_interface()` functions.
 ///
 /// Properties:
 ///
-/// * `capture`: This is the pcap::Capture object that we'll use to capture packets.
+/// * `sources`: These are the sources we'll read packets from, each on its own thread.
 /// * `savefile`: This is the file that the sniffer will save the packets to.
+/// * `output`: This is where the description of each packet is written.
+/// * `hexdump`: This is the formatter dumping the bytes of each packet, if enabled.
+/// * `blacklist`: This is the blacklist used to filter packets, shared with the terminal UI.
+/// * `flows`: This is the flow table grouping packets into flows, if enabled.
+/// * `exporter`: This is the exporter sending expired flows to a collector.
+/// * `redactor`: This is the redactor removing payloads before packets are saved or forwarded.
+/// * `anonymizer`: This is the anonymizer rewriting addresses before packets are saved or forwarded.
+/// * `limits`: These are the conditions which end the capture.
+/// * `counters`: These are the counters of the capture, updated by its pipeline.
+/// * `stop`: This is set to ask the capture to stop, draining the packets pending on network
+///   interfaces first. It is shared with the terminal UI and the signal handler of the binary.
 pub struct Sniffer {
-    pub capture: pcap::Capture<dyn pcap::Activated>,
+    pub sources: Vec<Box<dyn PacketSource>>,
     pub savefile: Option<pcap::Savefile>,
-    pub blacklist: Option<Blacklist>,
+    pub output: Option<Output>,
+    pub hexdump: Option<HexDump>,
+    pub blacklist: Option<Arc<RwLock<Blacklist>>>,
+    pub flows: Option<FlowTable>,
+    pub exporter: Option<Exporter>,
+    pub redactor: Option<Redactor>,
+    pub anonymizer: Option<Anonymizer>,
+    pub limits: Limits,
+    pub counters: Arc<Counters>,
+    pub stop: Arc<AtomicBool>,
+}
+
+/// `SnifferBuilder` assembles a `Sniffer` piece by piece. It leaves the logger, the signals and the
+/// panic hook of the process alone, which the `sniffer` binary sets up from its options.
+///
+/// Properties:
+///
+/// * `sources`: The sources of the packets.
+/// * `write`: The path of the file the packets are saved to.
+/// * `output`: Where the description of each packet is written.
+/// * `hexdump`: The formatter dumping the bytes of each packet.
+/// * `blacklist`: The blacklist used to filter packets.
+/// * `flows`: The flow table grouping packets into flows.
+/// * `exporter`: The exporter sending expired flows to a collector, which needs a flow table.
+/// * `redactor`: The redactor removing payloads.
+/// * `anonymizer`: The anonymizer rewriting addresses.
+/// * `limits`: The conditions which end the capture, none by default.
+/// * `counters`: The counters of the capture, new ones by default.
+#[derive(Default)]
+pub struct SnifferBuilder {
+    sources: Vec<Box<dyn PacketSource>>,
+    write: Option<String>,
+    output: Option<Output>,
+    hexdump: Option<HexDump>,
+    blacklist: Option<Blacklist>,
+    flows: Option<FlowTable>,
+    exporter: Option<Exporter>,
+    redactor: Option<Redactor>,
+    anonymizer: Option<Anonymizer>,
+    limits: Option<Limits>,
+    counters: Option<Arc<Counters>>,
 }
 
 impl Sniffer {
-    /// It initializes the logger, sets the panic hook, sets the SIGINT handler, checks if the user
-    /// wants to read from a file or a network interface, and then creates a pcap capture object
-    ///
-    /// Arguments:
-    ///
-    /// * `args`: &Args
-    ///
-    /// Returns:
-    ///
-    /// A new instance of the Sniffer struct.
-    pub fn new(args: &Args) -> Result<Self, Box<dyn Error>> {
-        logger::set_stack("sniffer");
-        logger::set_verbosity(args.verbosity);
-        logger::set_logfile(&args.logfile)?;
-        logger::init();
-
-        std::panic::set_hook(Box::new(|err| {
-            if let Some(msg) = err.payload().downcast_ref::<&str>() {
-                logger::error(msg);
-            } else if let Some(msg) = err.payload().downcast_ref::<String>() {
-                logger::error(msg);
-            } else {
-                logger::error(err);
-            }
-            std::process::exit(84);
-        }));
-
-        ctrlc::set_handler(|| {
-            print!("\r");
-            logger::warn("Sniffer killed by ^C");
-            std::process::exit(84);
-        })
-        .ok();
+    /// It returns a builder assembling a sniffer piece by piece.
+    pub fn builder() -> SnifferBuilder {
+        SnifferBuilder::default()
+    }
 
-        if args.read.is_some() && args.interface.is_some() {
-            panic!("You can't both read packets from an input file and a network interface");
-        }
+    /// It returns the handle asking the capture of this sniffer to stop once set.
+    pub fn stopper(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+}
 
-        let dev = match args.interface {
-            Some(ref dev) => pcap::Device::from(dev.as_str()),
-            None => pcap::Device::lookup()?.unwrap(),
-        };
+impl SnifferBuilder {
+    /// It adds a source of packets, read on its own thread.
+    pub fn source(mut self, source: impl PacketSource + 'static) -> Self {
+        self.sources.push(Box::new(source));
+        self
+    }
 
-        let capture: pcap::Capture<dyn pcap::Activated> = match args.read {
-            Some(ref input) => pcap::Capture::from(pcap::Capture::from_file(input)?),
-            None => pcap::Capture::from(
-                pcap::Capture::from_device(dev)?
-                    .immediate_mode(true)
-                    .open()?,
-            ),
-        };
+    /// It adds sources of packets, such as the sockets of an AF_PACKET fanout group.
+    pub fn sources(mut self, sources: Vec<Box<dyn PacketSource>>) -> Self {
+        self.sources.extend(sources);
+        self
+    }
 
-        let savefile = match args.write {
-            Some(ref file) => Some(capture.savefile(file)?),
-            None => None,
-        };
+    /// It saves the packets to a pcap file, with the link type of the first source.
+    pub fn write(mut self, path: &str) -> Self {
+        self.write = Some(path.to_string());
+        self
+    }
+
+    /// It writes a description of each packet.
+    pub fn output(mut self, output: Output) -> Self {
+        self.output = Some(output);
+        self
+    }
+
+    /// It dumps the bytes of each packet under its description.
+    pub fn hexdump(mut self, hexdump: HexDump) -> Self {
+        self.hexdump = Some(hexdump);
+        self
+    }
+
+    /// It ignores the packets matching the rules of a blacklist.
+    pub fn blacklist(mut self, blacklist: Blacklist) -> Self {
+        self.blacklist = Some(blacklist);
+        self
+    }
+
+    /// It groups the packets into flows.
+    pub fn flows(mut self, flows: FlowTable) -> Self {
+        self.flows = Some(flows);
+        self
+    }
 
-        let blacklist: Option<Blacklist> = match args.blacklist {
-            Some(ref file) => {
-                let data = std::fs::read_to_string(file)?;
-                let conf: Blacklist = match toml::from_str(&data) {
-                    Ok(blacklist) => blacklist,
-                    Err(err) => panic!("Could not parse the blacklist file: {}", err),
-                };
+    /// It sends the expired flows to a collector.
+    pub fn exporter(mut self, exporter: Exporter) -> Self {
+        self.exporter = Some(exporter);
+        self
+    }
+
+    /// It removes the payloads of the packets before they are saved or forwarded.
+    pub fn redactor(mut self, redactor: Redactor) -> Self {
+        self.redactor = Some(redactor);
+        self
+    }
 
-                Some(conf)
-            }
+    /// It rewrites the addresses of the packets before they are saved or forwarded.
+    pub fn anonymizer(mut self, anonymizer: Anonymizer) -> Self {
+        self.anonymizer = Some(anonymizer);
+        self
+    }
+
+    /// It ends the capture once one of its conditions is reached.
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = Some(limits);
+        self
+    }
+
+    /// It updates counters read by the application while the capture runs.
+    pub fn counters(mut self, counters: Arc<Counters>) -> Self {
+        self.counters = Some(counters);
+        self
+    }
+
+    /// It builds the sniffer, opening its savefile.
+    ///
+    /// Returns:
+    ///
+    /// A new instance of the Sniffer struct, or an error if it has no source of packets or if
+    /// its savefile could not be opened.
+    pub fn build(self) -> Result<Sniffer, Error> {
+        let Some(first) = self.sources.first() else {
+            return Err(Error::Config(
+                "The sniffer has no source of packets".to_string(),
+            ));
+        };
+        let savefile = match self.write {
+            Some(ref file) => Some(
+                first
+                    .savefile(file)
+                    .map_err(|err| Error::Io(io::Error::other(err.to_string())))?,
+            ),
             None => None,
         };
 
-        Ok(Self {
-            capture,
+        Ok(Sniffer {
+            sources: self.sources,
             savefile,
-            blacklist,
+            output: self.output,
+            hexdump: self.hexdump,
+            blacklist: self
+                .blacklist
+                .map(|blacklist| Arc::new(RwLock::new(blacklist))),
+            flows: self.flows,
+            exporter: self.exporter,
+            redactor: self.redactor,
+            anonymizer: self.anonymizer,
+            limits: self
+                .limits
+                .unwrap_or_else(|| Limits::new(None, None, None, None)),
+            counters: self.counters.unwrap_or_default(),
+            stop: Arc::new(AtomicBool::new(false)),
         })
     }
 }
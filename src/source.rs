@@ -0,0 +1,255 @@
+#[cfg(all(target_os = "linux", feature = "af-packet"))]
+use crate::afpacket::{self, Ring};
+use crate::error::Error;
+use crate::sniffer::CAPTURE_TIMEOUT;
+use clap::ValueEnum;
+use std::collections::VecDeque;
+use std::io;
+
+/// `Backend` is the way a network interface is captured.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum Backend {
+    Pcap,
+    AfPacket,
+}
+
+/// `PacketSource` is where a capture thread of the pipeline reads packets from: a network
+/// interface, a pcap file, or packets held in memory.
+pub trait PacketSource: Send {
+    /// It reads the next packet of the source.
+    ///
+    /// Returns:
+    ///
+    /// The packet, `TimeoutExpired` if none arrived in time, or `NoMorePackets` once the source
+    /// ends.
+    fn next_packet(&mut self) -> Result<pcap::Packet<'_>, pcap::Error>;
+
+    /// It reads the statistics of the source, which only network interfaces have.
+    fn stats(&mut self) -> Result<pcap::Stat, pcap::Error> {
+        Err(pcap::Error::PcapError(
+            "The source has no statistics".to_string(),
+        ))
+    }
+
+    /// It tells whether the source reads a network interface, whose pending packets are drained
+    /// once a stop is requested.
+    fn live(&self) -> bool {
+        false
+    }
+
+    /// It opens a pcap file to save the packets of the source to, with their link type.
+    ///
+    /// Arguments:
+    ///
+    /// * `path`: The path of the file.
+    fn savefile(&self, path: &str) -> Result<pcap::Savefile, pcap::Error> {
+        pcap::Capture::dead(pcap::Linktype::ETHERNET)?.savefile(path)
+    }
+}
+
+/// `PcapLive` captures a network interface with libpcap.
+pub struct PcapLive(pcap::Capture<pcap::Active>);
+
+/// `PcapFile` reads the packets of a pcap file.
+pub struct PcapFile(pcap::Capture<pcap::Offline>);
+
+/// `Memory` replays packets held in memory, such as crafted frames.
+///
+/// Properties:
+///
+/// * `packets`: The packets left to read, with their pcap header.
+/// * `current`: The last packet read.
+pub struct Memory {
+    packets: VecDeque<(pcap::PacketHeader, Vec<u8>)>,
+    current: Option<(pcap::PacketHeader, Vec<u8>)>,
+}
+
+/// It opens a pcap file to read the packets of.
+///
+/// Arguments:
+///
+/// * `path`: The path of the file.
+///
+/// Returns:
+///
+/// The source reading the file.
+pub fn open_file(path: &str) -> Result<Vec<Box<dyn PacketSource>>, Error> {
+    // A file which can't be read is an I/O error, not a capture error like a lost interface.
+    let file = PcapFile::open(path).map_err(|err| {
+        Error::Io(io::Error::other(format!(
+            "Could not read the input file {}: {}",
+            path, err
+        )))
+    })?;
+    Ok(vec![Box::new(file)])
+}
+
+/// It opens a network interface to capture with the chosen backend.
+///
+/// Arguments:
+///
+/// * `interface`: The name of the network interface, or `None` for the default one.
+/// * `backend`: The way the network interface is captured.
+/// * `fanout`: The number of AF_PACKET sockets sharing the network interface.
+/// * `fanout_group`: The identifier of the AF_PACKET fanout group, the process identifier by
+///   default.
+///
+/// Returns:
+///
+/// The sources, several only for the sockets of an AF_PACKET fanout group.
+#[cfg_attr(
+    not(all(target_os = "linux", feature = "af-packet")),
+    allow(unused_variables)
+)]
+pub fn open_interface(
+    interface: Option<&str>,
+    backend: Backend,
+    fanout: usize,
+    fanout_group: Option<u16>,
+) -> Result<Vec<Box<dyn PacketSource>>, Error> {
+    let dev = match interface {
+        Some(dev) => pcap::Device::from(dev),
+        None => pcap::Device::lookup()?.ok_or_else(|| {
+            Error::Capture(pcap::Error::PcapError(
+                "No network interface to capture".to_string(),
+            ))
+        })?,
+    };
+
+    match backend {
+        Backend::Pcap => Ok(vec![Box::new(PcapLive::open(dev)?)]),
+        #[cfg(all(target_os = "linux", feature = "af-packet"))]
+        Backend::AfPacket => Ok(afpacket::open(&dev.name, fanout, fanout_group)
+            .map_err(|err| Error::Capture(pcap::Error::PcapError(err.to_string())))?
+            .into_iter()
+            .map(|ring| Box::new(ring) as Box<dyn PacketSource>)
+            .collect()),
+        #[cfg(not(all(target_os = "linux", feature = "af-packet")))]
+        Backend::AfPacket => Err(Error::Config(
+            "The af-packet backend needs a sniffer built on Linux with the af-packet feature"
+                .to_string(),
+        )),
+    }
+}
+
+impl PcapLive {
+    /// It opens a network interface in immediate mode, reads giving up after the capture timeout
+    /// so that a stop request is noticed.
+    ///
+    /// Arguments:
+    ///
+    /// * `dev`: The network interface.
+    pub fn open(dev: pcap::Device) -> Result<Self, pcap::Error> {
+        let capture = pcap::Capture::from_device(dev)?
+            .immediate_mode(true)
+            .timeout(CAPTURE_TIMEOUT.as_millis() as i32)
+            .open()?;
+        Ok(PcapLive(capture))
+    }
+}
+
+impl PacketSource for PcapLive {
+    fn next_packet(&mut self) -> Result<pcap::Packet<'_>, pcap::Error> {
+        self.0.next_packet()
+    }
+
+    fn stats(&mut self) -> Result<pcap::Stat, pcap::Error> {
+        self.0.stats()
+    }
+
+    fn live(&self) -> bool {
+        true
+    }
+
+    fn savefile(&self, path: &str) -> Result<pcap::Savefile, pcap::Error> {
+        self.0.savefile(path)
+    }
+}
+
+impl PcapFile {
+    /// It opens a pcap file.
+    ///
+    /// Arguments:
+    ///
+    /// * `path`: The path of the file.
+    pub fn open(path: &str) -> Result<Self, pcap::Error> {
+        Ok(PcapFile(pcap::Capture::from_file(path)?))
+    }
+}
+
+impl PacketSource for PcapFile {
+    fn next_packet(&mut self) -> Result<pcap::Packet<'_>, pcap::Error> {
+        self.0.next_packet()
+    }
+
+    fn savefile(&self, path: &str) -> Result<pcap::Savefile, pcap::Error> {
+        self.0.savefile(path)
+    }
+}
+
+impl Memory {
+    /// It creates a source replaying packets in their order.
+    ///
+    /// Arguments:
+    ///
+    /// * `packets`: The packets, with their pcap header.
+    ///
+    /// Returns:
+    ///
+    /// A new instance of the Memory struct.
+    pub fn new(packets: impl IntoIterator<Item = (pcap::PacketHeader, Vec<u8>)>) -> Self {
+        Memory {
+            packets: packets.into_iter().collect(),
+            current: None,
+        }
+    }
+
+    /// It creates a source replaying Ethernet frames, captured whole one microsecond apart from
+    /// the epoch.
+    ///
+    /// Arguments:
+    ///
+    /// * `frames`: The bytes of the frames.
+    ///
+    /// Returns:
+    ///
+    /// A new instance of the Memory struct.
+    pub fn from_frames(frames: impl IntoIterator<Item = Vec<u8>>) -> Self {
+        Self::new(frames.into_iter().enumerate().map(|(index, frame)| {
+            let header = pcap::PacketHeader {
+                ts: libc::timeval {
+                    tv_sec: (index / 1_000_000) as libc::time_t,
+                    tv_usec: (index % 1_000_000) as libc::suseconds_t,
+                },
+                caplen: frame.len() as u32,
+                len: frame.len() as u32,
+            };
+            (header, frame)
+        }))
+    }
+}
+
+impl PacketSource for Memory {
+    fn next_packet(&mut self) -> Result<pcap::Packet<'_>, pcap::Error> {
+        self.current = self.packets.pop_front();
+        match self.current {
+            Some((ref header, ref data)) => Ok(pcap::Packet::new(header, data)),
+            None => Err(pcap::Error::NoMorePackets),
+        }
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "af-packet"))]
+impl PacketSource for Ring {
+    fn next_packet(&mut self) -> Result<pcap::Packet<'_>, pcap::Error> {
+        Ring::next_packet(self)
+    }
+
+    fn stats(&mut self) -> Result<pcap::Stat, pcap::Error> {
+        Ring::stats(self)
+    }
+
+    fn live(&self) -> bool {
+        true
+    }
+}
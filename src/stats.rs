@@ -0,0 +1,147 @@
+use crate::logger;
+use crate::metrics::METRICS;
+use lazy_static::lazy_static;
+use sniffer::packet::{Address, PacketInfo};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Number of top talkers shown in a summary.
+const TOP_TALKERS: usize = 5;
+
+/// Maximum number of talkers remembered between two summaries. The smallest ones are forgotten
+/// once it is reached.
+const MAX_TALKERS: usize = 65536;
+
+lazy_static! {
+    static ref STATS: Mutex<Stats> = Mutex::new(Stats {
+        talkers: HashMap::new(),
+        packets: 0,
+        bytes: 0,
+        last_summary: Instant::now(),
+    });
+}
+
+/// `Stats` contains what a summary needs on top of the metrics.
+///
+/// Properties:
+///
+/// * `talkers`: The number of bytes sent by each host since the last summary.
+/// * `packets`: The number of packets captured at the last summary.
+/// * `bytes`: The number of bytes captured at the last summary.
+/// * `last_summary`: The time of the last summary, or of the start of the capture.
+struct Stats {
+    talkers: HashMap<Address, u64>,
+    packets: u64,
+    bytes: u64,
+    last_summary: Instant,
+}
+
+/// It marks the start of the capture, from which the first summary is computed.
+pub fn init() {
+    STATS.lock().unwrap().last_summary = Instant::now();
+}
+
+/// It accounts the bytes sent by the source of a packet, identified by its IP address or, for
+/// packets without one, by its MAC address.
+///
+/// Arguments:
+///
+/// * `info`: The parsed packet.
+/// * `len`: The length of the packet on the wire.
+pub fn add_talker(info: &PacketInfo, len: u32) {
+    let mut stats = STATS.lock().unwrap();
+    let talker = info.source();
+
+    if !stats.talkers.contains_key(&talker) && stats.talkers.len() >= MAX_TALKERS {
+        let mut talkers: Vec<(Address, u64)> = stats.talkers.drain().collect();
+        talkers.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        talkers.truncate(MAX_TALKERS / 2);
+        stats.talkers.extend(talkers);
+    }
+    *stats.talkers.entry(talker).or_insert(0) += len as u64;
+}
+
+/// It logs a summary every interval.
+///
+/// Arguments:
+///
+/// * `interval`: The time between two summaries.
+pub async fn run(interval: Duration) {
+    let mut ticks = tokio::time::interval(interval);
+
+    // The first tick completes immediately.
+    ticks.tick().await;
+    loop {
+        ticks.tick().await;
+        summary();
+    }
+}
+
+/// It logs the state of the capture: the traffic since the last summary, the totals, the
+/// statistics of libpcap, the blacklist hits per rule and the hosts which sent the most bytes
+/// since the last summary.
+pub fn summary() {
+    let mut stats = STATS.lock().unwrap();
+    let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+
+    let packets = load(&METRICS.counters.captured);
+    let bytes = load(&METRICS.counters.captured_bytes);
+    let elapsed = stats.last_summary.elapsed();
+
+    logger::info(format!(
+        "STATS: {} packets ({}), +{} packets ({}) in {:.0}s, pcap recv {} drop {} ifdrop {}, {} unparseable, {} blacklisted, {} sent",
+        packets,
+        format_bytes(bytes),
+        packets - stats.packets,
+        format_bytes(bytes - stats.bytes),
+        elapsed.as_secs_f64(),
+        load(&METRICS.counters.pcap_received),
+        load(&METRICS.counters.pcap_dropped),
+        load(&METRICS.counters.pcap_if_dropped),
+        load(&METRICS.counters.unparseable),
+        load(&METRICS.counters.blacklisted),
+        load(&METRICS.counters.sent),
+    ));
+
+    let hits = METRICS.blacklist_hits();
+    if !hits.is_empty() {
+        let hits: Vec<String> = hits
+            .iter()
+            .map(|(rule, hits)| format!("{}: {}", rule, hits))
+            .collect();
+        logger::info(format!("STATS: blacklist hits {}", hits.join(", ")));
+    }
+
+    let mut talkers: Vec<(Address, u64)> = stats.talkers.drain().collect();
+    if !talkers.is_empty() {
+        talkers.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        let talkers: Vec<String> = talkers
+            .iter()
+            .take(TOP_TALKERS)
+            .map(|(talker, bytes)| format!("{} ({})", talker, format_bytes(*bytes)))
+            .collect();
+        logger::info(format!("STATS: top talkers {}", talkers.join(", ")));
+    }
+
+    stats.packets = packets;
+    stats.bytes = bytes;
+    stats.last_summary = Instant::now();
+}
+
+/// It formats a number of bytes with a binary unit.
+pub fn format_bytes(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+
+    while value >= 1024.0 && unit < units.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{} {}", bytes, units[0]),
+        _ => format!("{:.1} {}", value, units[unit]),
+    }
+}
@@ -0,0 +1,578 @@
+use crate::logger;
+use crate::metrics::METRICS;
+use crate::stats::format_bytes;
+use bytes::Bytes;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::execute;
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use ratatui::prelude::*;
+use ratatui::widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph, Sparkline};
+use sniffer::output;
+use sniffer::packet::{PacketInfo, Protocol};
+use sniffer::{Blacklist, HexDump, Observer};
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Stdout};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Number of packets kept in the packet list.
+const MAX_PACKETS: usize = 1000;
+
+/// Number of bytes of each packet kept for its details.
+const MAX_DUMP: usize = 512;
+
+/// Maximum number of talkers remembered. The smallest ones are forgotten once it is reached.
+const MAX_TALKERS: usize = 65536;
+
+/// Number of top talkers and protocols shown.
+const TOP: usize = 10;
+
+/// Number of per second rates kept for the rate graph.
+const RATE_HISTORY: usize = 300;
+
+/// Time between two refreshes of the screen.
+const TICK: Duration = Duration::from_millis(250);
+
+/// Number of packets skipped by the page keys.
+const PAGE: u64 = 20;
+
+/// Whether the terminal is in raw mode and on the alternate screen.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// `Tui` feeds the terminal UI with the packets of the capture.
+///
+/// Properties:
+///
+/// * `state`: The packets and the breakdowns shared with the thread drawing the terminal UI.
+pub struct Tui {
+    state: Arc<Mutex<State>>,
+}
+
+/// `State` is what the capture loop feeds to the terminal UI.
+///
+/// Properties:
+///
+/// * `rows`: The last packets, the oldest first.
+/// * `next`: The sequence number of the next packet.
+/// * `protocols`: The number of packets and of bytes on the wire of each protocol.
+/// * `talkers`: The number of bytes sent by each host.
+/// * `ended`: Whether the capture ended.
+#[derive(Default)]
+struct State {
+    rows: VecDeque<Row>,
+    next: u64,
+    protocols: HashMap<Protocol, (u64, u64)>,
+    talkers: HashMap<String, u64>,
+    ended: bool,
+}
+
+/// `Row` is a packet of the packet list.
+///
+/// Properties:
+///
+/// * `seq`: The sequence number of the packet.
+/// * `summary`: The line of the packet in the list.
+/// * `details`: The description of the packet in the details pane.
+/// * `source`: The address of the source, used to blacklist it.
+/// * `destination`: The address of the destination, used to blacklist it.
+/// * `data`: The first bytes of the packet, as saved and forwarded.
+struct Row {
+    seq: u64,
+    summary: String,
+    details: String,
+    source: Option<String>,
+    destination: Option<String>,
+    data: Bytes,
+}
+
+/// `Mode` tells what the keys act on.
+enum Mode {
+    Packets,
+    Blacklist(usize),
+    Input(String),
+}
+
+/// `App` is the terminal UI itself, owned by its thread.
+///
+/// Properties:
+///
+/// * `state`: The packets and the breakdowns fed by the capture loop.
+/// * `blacklist`: The blacklist used by the capture loop, edited from the terminal UI.
+/// * `stop`: The handle asking the capture to stop, set when the operator quits.
+/// * `selected`: The sequence number of the selected packet, or `None` to follow the last one.
+/// * `mode`: What the keys act on.
+/// * `status`: The outcome of the last action.
+/// * `rates`: The number of packets captured each second, the oldest first.
+/// * `byte_rate`: The number of bytes captured during the last second.
+/// * `last_sample`: The counters of the capture at the last sample, and its time.
+struct App {
+    state: Arc<Mutex<State>>,
+    blacklist: Arc<RwLock<Blacklist>>,
+    stop: Arc<AtomicBool>,
+    selected: Option<u64>,
+    mode: Mode,
+    status: String,
+    rates: VecDeque<u64>,
+    byte_rate: u64,
+    last_sample: (u64, u64, Instant),
+}
+
+/// It takes over the terminal and starts the thread drawing the terminal UI. Logs are only
+/// written in the log file until it is closed.
+///
+/// Arguments:
+///
+/// * `blacklist`: The blacklist used by the capture loop, edited from the terminal UI.
+/// * `stop`: The handle asking the capture to stop.
+///
+/// Returns:
+///
+/// The feed of the terminal UI and its thread, which ends when the operator quits it or when a
+/// stop is requested.
+pub fn start(
+    blacklist: Arc<RwLock<Blacklist>>,
+    stop: Arc<AtomicBool>,
+) -> io::Result<(Tui, JoinHandle<()>)> {
+    enable_raw_mode()?;
+    ACTIVE.store(true, Ordering::SeqCst);
+    execute!(io::stdout(), EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+    logger::set_console(false);
+
+    // The terminal must be usable again to read the panic message.
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        restore();
+        hook(info);
+    }));
+
+    let state = Arc::new(Mutex::new(State::default()));
+    let mut app = App {
+        state: state.clone(),
+        blacklist,
+        stop,
+        selected: None,
+        mode: Mode::Packets,
+        status: String::new(),
+        rates: VecDeque::with_capacity(RATE_HISTORY),
+        byte_rate: 0,
+        last_sample: (0, 0, Instant::now()),
+    };
+    let thread = std::thread::spawn(move || {
+        let result = app.run(&mut terminal);
+        restore();
+        if let Err(err) = result {
+            logger::error(format!("The terminal UI failed: {}", err));
+        }
+    });
+
+    Ok((Tui { state }, thread))
+}
+
+/// It gives the terminal back, leaving the alternate screen, if the terminal UI holds it.
+pub fn restore() {
+    if ACTIVE.swap(false, Ordering::SeqCst) {
+        disable_raw_mode().ok();
+        execute!(io::stdout(), LeaveAlternateScreen).ok();
+        logger::set_console(true);
+    }
+}
+
+impl Tui {
+    /// It adds a packet to the packet list and to the breakdowns.
+    ///
+    /// Arguments:
+    ///
+    /// * `header`: The pcap header of the packet.
+    /// * `info`: The parsed packet, or `None` if it could not be parsed.
+    /// * `data`: The bytes of the packet, as saved and forwarded.
+    pub fn add(&self, header: &pcap::PacketHeader, info: Option<&PacketInfo>, data: &Bytes) {
+        let time = output::timestamp(header);
+        let mut row = Row {
+            seq: 0,
+            summary: output::text(time, header, info),
+            details: output::verbose(time, header, info),
+            source: info.map(|info| info.source().to_string()),
+            destination: info.map(|info| info.destination().to_string()),
+            data: data.slice(..data.len().min(MAX_DUMP)),
+        };
+
+        let mut state = self.state.lock().unwrap();
+        if let (Some(info), Some(source)) = (info, &row.source) {
+            let protocol = state.protocols.entry(info.protocol).or_default();
+            protocol.0 += 1;
+            protocol.1 += header.len as u64;
+
+            if !state.talkers.contains_key(source) && state.talkers.len() >= MAX_TALKERS {
+                let mut talkers: Vec<(String, u64)> = state.talkers.drain().collect();
+                talkers.sort_by_key(|(_, bytes)| std::cmp::Reverse(*bytes));
+                talkers.truncate(MAX_TALKERS / 2);
+                state.talkers.extend(talkers);
+            }
+            *state.talkers.entry(source.clone()).or_insert(0) += header.len as u64;
+        }
+
+        row.seq = state.next;
+        state.next += 1;
+        state.rows.push_back(row);
+        if state.rows.len() > MAX_PACKETS {
+            state.rows.pop_front();
+        }
+    }
+
+    /// It tells the terminal UI that the capture ended, the packets staying on screen until the
+    /// operator quits it.
+    pub fn end(&self) {
+        self.state.lock().unwrap().ended = true;
+    }
+}
+
+impl Observer for Tui {
+    fn packet(
+        &mut self,
+        header: &pcap::PacketHeader,
+        info: Option<&PacketInfo>,
+        data: &Bytes,
+        _dump: Option<&str>,
+    ) {
+        self.add(header, info, data);
+    }
+
+    fn end(&mut self) {
+        Tui::end(self);
+    }
+}
+
+impl App {
+    /// It draws the terminal UI and handles the keys until the operator quits or a stop is
+    /// requested.
+    fn run(&mut self, terminal: &mut Terminal<CrosstermBackend<Stdout>>) -> io::Result<()> {
+        while !self.stop.load(Ordering::SeqCst) {
+            self.sample();
+            terminal.draw(|frame| self.draw(frame))?;
+
+            if !event::poll(TICK)? {
+                continue;
+            }
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press && !self.key(key) {
+                    self.stop.store(true, Ordering::SeqCst);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// It samples the rates of the capture, once a second.
+    fn sample(&mut self) {
+        let (packets, bytes, time) = self.last_sample;
+        if time.elapsed() < Duration::from_secs(1) {
+            return;
+        }
+        let captured = METRICS.counters.captured.load(Ordering::Relaxed);
+        let captured_bytes = METRICS.counters.captured_bytes.load(Ordering::Relaxed);
+
+        if self.rates.len() == RATE_HISTORY {
+            self.rates.pop_front();
+        }
+        self.rates.push_back(captured - packets);
+        self.byte_rate = captured_bytes - bytes;
+        self.last_sample = (captured, captured_bytes, Instant::now());
+    }
+
+    /// It handles a key.
+    ///
+    /// Returns:
+    ///
+    /// Whether the terminal UI goes on, `false` once the operator quits it.
+    fn key(&mut self, key: KeyEvent) -> bool {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return false;
+        }
+
+        match self.mode {
+            Mode::Packets => match key.code {
+                KeyCode::Char('q') => return false,
+                KeyCode::Up | KeyCode::Char('k') => self.select(-1),
+                KeyCode::Down | KeyCode::Char('j') => self.select(1),
+                KeyCode::PageUp => self.select(-(PAGE as i64)),
+                KeyCode::PageDown => self.select(PAGE as i64),
+                KeyCode::Home => self.select(i64::MIN),
+                KeyCode::End => self.selected = None,
+                KeyCode::Char('f') => self.block(true),
+                KeyCode::Char('t') => self.block(false),
+                KeyCode::Char('b') => self.mode = Mode::Blacklist(0),
+                _ => {}
+            },
+            Mode::Blacklist(ref mut selected) => match key.code {
+                KeyCode::Esc | KeyCode::Char('b') => self.mode = Mode::Packets,
+                KeyCode::Up | KeyCode::Char('k') => *selected = selected.saturating_sub(1),
+                KeyCode::Down | KeyCode::Char('j') => *selected += 1,
+                KeyCode::Char('a') => self.mode = Mode::Input(String::new()),
+                KeyCode::Char('d') | KeyCode::Delete => {
+                    let mut blacklist = self.blacklist.write().unwrap();
+                    if let Some(rule) = blacklist.rules().get(*selected) {
+                        self.status = match blacklist.remove(rule) {
+                            Ok(()) => format!("Removed {}", rule),
+                            Err(err) => err,
+                        };
+                    }
+                }
+                _ => {}
+            },
+            Mode::Input(ref mut rule) => match key.code {
+                KeyCode::Esc => self.mode = Mode::Blacklist(0),
+                KeyCode::Backspace => {
+                    rule.pop();
+                }
+                KeyCode::Char(char) => rule.push(char),
+                KeyCode::Enter => {
+                    self.status = match self.blacklist.write().unwrap().add(rule) {
+                        Ok(()) => format!("Added {}", rule.trim()),
+                        Err(err) => err,
+                    };
+                    self.mode = Mode::Blacklist(0);
+                }
+                _ => {}
+            },
+        }
+        true
+    }
+
+    /// It moves the selection in the packet list, following the last packet once it is reached.
+    ///
+    /// Arguments:
+    ///
+    /// * `offset`: The number of packets to move by, negative to go up.
+    fn select(&mut self, offset: i64) {
+        let state = self.state.lock().unwrap();
+        let (Some(first), Some(last)) = (state.rows.front(), state.rows.back()) else {
+            return;
+        };
+        let current = self.selected.unwrap_or(last.seq).max(first.seq) as i64;
+        let selected = current.saturating_add(offset).max(first.seq as i64) as u64;
+
+        self.selected = (selected < last.seq).then_some(selected);
+    }
+
+    /// It blacklists the source or the destination of the selected packet.
+    ///
+    /// Arguments:
+    ///
+    /// * `source`: Whether the source is blacklisted, instead of the destination.
+    fn block(&mut self, source: bool) {
+        let state = self.state.lock().unwrap();
+        let address = self.row(&state).and_then(|row| match source {
+            true => row.source.clone(),
+            false => row.destination.clone(),
+        });
+        drop(state);
+
+        let Some(address) = address else {
+            self.status = "No address to blacklist".to_string();
+            return;
+        };
+        let rule = format!("{} {}", if source { "from" } else { "into" }, address);
+        self.status = match self.blacklist.write().unwrap().add(&rule) {
+            Ok(()) => format!("Added {}", rule),
+            Err(err) => err,
+        };
+    }
+
+    /// It returns the selected packet, if it is still in the packet list.
+    fn row<'a>(&self, state: &'a State) -> Option<&'a Row> {
+        let first = state.rows.front()?.seq;
+        match self.selected {
+            Some(seq) => state.rows.get(seq.saturating_sub(first) as usize),
+            None => state.rows.back(),
+        }
+    }
+
+    /// It draws the terminal UI: the packet list and the details of the selected packet on the
+    /// left, the rates, the top talkers and the protocol breakdown on the right.
+    fn draw(&mut self, frame: &mut Frame) {
+        let state = self.state.lock().unwrap();
+        let [header, body, footer] = split(frame.size(), Direction::Vertical, [1, 0, 1]);
+        let [left, right] = split(body, Direction::Horizontal, [0, 48]);
+        let [packets, details] = split(left, Direction::Vertical, [0, 14]);
+        let [rates, talkers, protocols] = split(right, Direction::Vertical, [8, 0, TOP as u16 + 2]);
+
+        let load = |counter: &std::sync::atomic::AtomicU64| counter.load(Ordering::Relaxed);
+        let title = format!(
+            " Sniffer | {} packets | {} pkt/s | {}/s | {} blacklisted | {} unparseable{}",
+            load(&METRICS.counters.captured),
+            self.rates.back().unwrap_or(&0),
+            format_bytes(self.byte_rate),
+            load(&METRICS.counters.blacklisted),
+            load(&METRICS.counters.unparseable),
+            if state.ended { " | capture ended" } else { "" },
+        );
+        frame.render_widget(
+            Paragraph::new(title).style(Style::new().add_modifier(Modifier::REVERSED)),
+            header,
+        );
+
+        let items: Vec<ListItem> = state
+            .rows
+            .iter()
+            .map(|row| ListItem::new(row.summary.as_str()))
+            .collect();
+        let selected = self.row(&state).map(|row| row.seq);
+        let mut list = ListState::default();
+        list.select(selected.map(|seq| (seq - state.rows[0].seq) as usize));
+        let title = match self.selected {
+            Some(_) => " Packets ",
+            None => " Packets (following) ",
+        };
+        frame.render_stateful_widget(
+            List::new(items)
+                .block(Block::default().borders(Borders::ALL).title(title))
+                .highlight_style(Style::new().add_modifier(Modifier::REVERSED)),
+            packets,
+            &mut list,
+        );
+
+        let text = match self.row(&state) {
+            Some(row) => format!(
+                "{}\n{}",
+                row.details,
+                HexDump::new(false, None).dump(&row.data)
+            ),
+            None => String::new(),
+        };
+        frame.render_widget(
+            Paragraph::new(text).block(Block::default().borders(Borders::ALL).title(" Details ")),
+            details,
+        );
+
+        let width = rates.width.saturating_sub(2) as usize;
+        let history: Vec<u64> = self.rates.iter().rev().take(width).rev().copied().collect();
+        frame.render_widget(
+            Sparkline::default()
+                .block(Block::default().borders(Borders::ALL).title(" Packets/s "))
+                .data(&history)
+                .style(Style::new().fg(Color::Green)),
+            rates,
+        );
+
+        let mut top: Vec<(&String, &u64)> = state.talkers.iter().collect();
+        top.sort_by_key(|(_, bytes)| std::cmp::Reverse(**bytes));
+        let items: Vec<ListItem> = top
+            .iter()
+            .take(TOP)
+            .map(|(talker, bytes)| {
+                ListItem::new(format!("{:<34} {}", talker, format_bytes(**bytes)))
+            })
+            .collect();
+        frame.render_widget(
+            List::new(items).block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(" Top talkers "),
+            ),
+            talkers,
+        );
+
+        let mut top: Vec<(&Protocol, &(u64, u64))> = state.protocols.iter().collect();
+        top.sort_by_key(|(_, (_, bytes))| std::cmp::Reverse(*bytes));
+        let items: Vec<ListItem> = top
+            .iter()
+            .take(TOP)
+            .map(|(protocol, (packets, bytes))| {
+                ListItem::new(format!(
+                    "{:<16} {:>10} pkts {:>12}",
+                    protocol.to_string(),
+                    packets,
+                    format_bytes(*bytes)
+                ))
+            })
+            .collect();
+        frame.render_widget(
+            List::new(items).block(Block::default().borders(Borders::ALL).title(" Protocols ")),
+            protocols,
+        );
+        drop(state);
+
+        let help = match self.mode {
+            Mode::Packets => "q quit | ↑↓ PgUp PgDn Home End select | f/t blacklist source/destination | b blacklist",
+            Mode::Blacklist(_) => "Esc back | ↑↓ select | a add | d remove",
+            Mode::Input(_) => "Enter add | Esc cancel",
+        };
+        let line = match self.status.is_empty() {
+            true => help.to_string(),
+            false => format!("{} | {}", self.status, help),
+        };
+        frame.render_widget(Paragraph::new(line), footer);
+
+        match self.mode {
+            Mode::Packets => {}
+            Mode::Blacklist(ref mut selected) => {
+                let rules = self.blacklist.read().unwrap().rules();
+                *selected = (*selected).min(rules.len().saturating_sub(1));
+                let items: Vec<ListItem> = rules.into_iter().map(ListItem::new).collect();
+                let mut list = ListState::default();
+                list.select(Some(*selected));
+
+                let area = centered(frame.size(), 60, 20);
+                frame.render_widget(Clear, area);
+                frame.render_stateful_widget(
+                    List::new(items)
+                        .block(Block::default().borders(Borders::ALL).title(" Blacklist "))
+                        .highlight_style(Style::new().add_modifier(Modifier::REVERSED)),
+                    area,
+                    &mut list,
+                );
+            }
+            Mode::Input(ref rule) => {
+                let area = centered(frame.size(), 60, 4);
+                frame.render_widget(Clear, area);
+                frame.render_widget(
+                    Paragraph::new(format!("{}_\nfrom|into ADDRESS, domain|host NAME", rule))
+                        .block(
+                            Block::default()
+                                .borders(Borders::ALL)
+                                .title(" New blacklist rule "),
+                        ),
+                    area,
+                );
+            }
+        }
+    }
+}
+
+/// It splits an area in parts of fixed sizes, the parts of size `0` sharing what is left.
+///
+/// Arguments:
+///
+/// * `area`: The area to split.
+/// * `direction`: Whether the parts are stacked or side by side.
+/// * `sizes`: The sizes of the parts.
+fn split<const N: usize>(area: Rect, direction: Direction, sizes: [u16; N]) -> [Rect; N] {
+    let constraints = sizes.map(|size| match size {
+        0 => Constraint::Min(0),
+        size => Constraint::Length(size),
+    });
+    let parts = Layout::default()
+        .direction(direction)
+        .constraints(constraints)
+        .split(area);
+
+    std::array::from_fn(|index| parts[index])
+}
+
+/// It returns an area of a given size centered in another one, for popups.
+fn centered(area: Rect, width: u16, height: u16) -> Rect {
+    let width = width.min(area.width);
+    let height = height.min(area.height);
+
+    Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    )
+}
@@ -1,10 +1,5 @@
-#[allow(clippy::all)]
-mod packet_streaming {
-    tonic::include_proto!("packet_streaming");
-}
-
-use packet_streaming::packet_streaming_server::{PacketStreaming, PacketStreamingServer};
-use packet_streaming::*;
+use sniffer::packet_streaming::packet_streaming_server::{PacketStreaming, PacketStreamingServer};
+use sniffer::packet_streaming::*;
 
 use futures_util::StreamExt;
 use std::error::Error;
@@ -22,7 +17,11 @@ impl PacketStreaming for PacketService {
         while let Some(packet) = stream.next().await {
             let packet = packet.unwrap();
             let packet_header = packet.header.unwrap();
-            let packet_data = packet.data.unwrap();
+
+            // Packets streamed as metadata only have no data to save.
+            let Some(packet_data) = packet.data else {
+                continue;
+            };
 
             savefile.write(&pcap::Packet::new(
                 &pcap::PacketHeader {
@@ -0,0 +1,222 @@
+use bytes::Bytes;
+use futures_util::StreamExt;
+use sniffer::packet_streaming::Packet;
+use sniffer::source::Memory;
+use sniffer::{
+    Anonymizer, Blacklist, Counters, Limits, Observer, PacketInfo, PacketSource, Pipeline,
+    RedactMode, Redactor, Sniffer, StreamMode,
+};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The payload of the datagrams, which must not reach the gRPC server once redacted.
+const PAYLOAD: &[u8] = b"a secret payload";
+
+/// It builds an Ethernet frame carrying a UDP datagram from a host of 10.0.0.0/24 to 10.0.0.100.
+fn udp(host: u8, port: u16) -> Vec<u8> {
+    let mut frame = vec![0x02, 0, 0, 0, 0, 100, 0x02, 0, 0, 0, 0, host, 0x08, 0x00];
+    frame.extend_from_slice(&[0x45, 0]);
+    frame.extend_from_slice(&((20 + 8 + PAYLOAD.len()) as u16).to_be_bytes());
+    frame.extend_from_slice(&[0, 1, 0, 0, 64, 17, 0, 0, 10, 0, 0, host, 10, 0, 0, 100]);
+    frame.extend_from_slice(&port.to_be_bytes());
+    frame.extend_from_slice(&9999u16.to_be_bytes());
+    frame.extend_from_slice(&((8 + PAYLOAD.len()) as u16).to_be_bytes());
+    frame.extend_from_slice(&[0, 0]);
+    frame.extend_from_slice(PAYLOAD);
+    frame
+}
+
+/// It runs a sniffer through a pipeline of several workers, and returns the packets it streams.
+async fn run(sniffer: Sniffer) -> Vec<Packet> {
+    let mut pipeline = Pipeline::start(sniffer, Some(4), Some(StreamMode::Both), Vec::new());
+    let packets = pipeline.packets().collect().await;
+    pipeline.join().unwrap();
+    packets
+}
+
+#[tokio::test]
+async fn packets_are_delivered_in_capture_order_without_the_blacklisted_ones() {
+    let frames = (0..600u16).map(|index| udp(1 + (index % 3) as u8, 1000 + index));
+    let mut blacklist = Blacklist::default();
+    blacklist.add("from 10.0.0.3").unwrap();
+    let sniffer = Sniffer::builder()
+        .source(Memory::from_frames(frames))
+        .blacklist(blacklist)
+        .build()
+        .unwrap();
+
+    let ports: Vec<u32> = run(sniffer)
+        .await
+        .iter()
+        .map(|packet| packet.metadata.as_ref().unwrap().src_port.unwrap())
+        .collect();
+
+    let expected: Vec<u32> = (0..600)
+        .filter(|index| index % 3 != 2)
+        .map(|index| 1000 + index)
+        .collect();
+    assert_eq!(ports, expected);
+}
+
+#[tokio::test]
+async fn delivered_packets_are_redacted_and_anonymized() {
+    let sniffer = Sniffer::builder()
+        .source(Memory::from_frames((1..=8).map(|host| udp(host, 5000))))
+        .redactor(Redactor::new(Some(RedactMode::Zero), None).unwrap())
+        .anonymizer(Anonymizer::new(&[7; 32]))
+        .build()
+        .unwrap();
+
+    let packets = run(sniffer).await;
+
+    assert_eq!(packets.len(), 8);
+    for (host, packet) in (1..=8).zip(&packets) {
+        let data = &packet.data.as_ref().unwrap().data;
+        let metadata = packet.metadata.as_ref().unwrap();
+        assert_eq!(data.len(), udp(host, 5000).len());
+
+        // The payload is zeroed, the headers are kept.
+        assert!(data[data.len() - PAYLOAD.len()..]
+            .iter()
+            .all(|byte| *byte == 0));
+        assert_eq!(metadata.src_port, Some(5000));
+        assert_eq!(metadata.dst_port, Some(9999));
+
+        // The addresses are anonymized the same way in the bytes and in the metadata.
+        let src_ip = format!("{}.{}.{}.{}", data[26], data[27], data[28], data[29]);
+        assert_ne!(src_ip, format!("10.0.0.{}", host));
+        assert_eq!(metadata.src_ip.as_deref(), Some(src_ip.as_str()));
+        let src_mac = data[6..12]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<Vec<_>>()
+            .join(":");
+        assert_ne!(src_mac, format!("02:00:00:00:00:{:02x}", host));
+        assert_eq!(metadata.src_mac, src_mac);
+    }
+}
+
+#[tokio::test]
+async fn the_capture_stops_before_the_packet_going_over_the_byte_limit() {
+    let len = udp(1, 1000).len() as u64;
+    let sniffer = Sniffer::builder()
+        .source(Memory::from_frames(
+            (0..100).map(|index| udp(1, 1000 + index)),
+        ))
+        .limits(Limits::new(None, Some(len * 10 + len / 2), None, None))
+        .build()
+        .unwrap();
+
+    assert_eq!(run(sniffer).await.len(), 10);
+
+    let sniffer = Sniffer::builder()
+        .source(Memory::from_frames(
+            (0..100).map(|index| udp(1, 1000 + index)),
+        ))
+        .limits(Limits::new(Some(7), None, None, None))
+        .build()
+        .unwrap();
+
+    assert_eq!(run(sniffer).await.len(), 7);
+}
+
+/// A network interface on which no packet ever arrives.
+struct Quiet;
+
+impl PacketSource for Quiet {
+    fn next_packet(&mut self) -> Result<pcap::Packet<'_>, pcap::Error> {
+        std::thread::sleep(Duration::from_millis(10));
+        Err(pcap::Error::TimeoutExpired)
+    }
+
+    fn live(&self) -> bool {
+        true
+    }
+}
+
+/// A source whose capture thread panics.
+struct Broken;
+
+impl PacketSource for Broken {
+    fn next_packet(&mut self) -> Result<pcap::Packet<'_>, pcap::Error> {
+        panic!("broken source");
+    }
+}
+
+#[test]
+fn a_panicking_thread_stops_the_pipeline_with_an_internal_error() {
+    let sniffer = Sniffer::builder()
+        .source(Quiet)
+        .source(Broken)
+        .build()
+        .unwrap();
+
+    let err = Pipeline::start(sniffer, Some(2), None, Vec::new())
+        .join()
+        .unwrap_err();
+
+    assert_eq!(err.exit_code(), 70);
+    assert!(err.to_string().contains("broken source"));
+}
+
+/// What an observer was told: the source ports of the packets delivered, the rules of the ignored
+/// ones, and whether the capture ended.
+#[derive(Default)]
+struct Seen {
+    ports: Vec<u16>,
+    rules: Vec<String>,
+    ended: bool,
+}
+
+/// An observer noting what it is told.
+struct Ports(Arc<Mutex<Seen>>);
+
+impl Observer for Ports {
+    fn packet(
+        &mut self,
+        _header: &pcap::PacketHeader,
+        info: Option<&PacketInfo>,
+        _data: &Bytes,
+        _dump: Option<&str>,
+    ) {
+        let port = info.and_then(|info| info.src_port).unwrap();
+        self.0.lock().unwrap().ports.push(port);
+    }
+
+    fn ignored(&mut self, _info: &PacketInfo, rule: &str) {
+        self.0.lock().unwrap().rules.push(rule.to_string());
+    }
+
+    fn end(&mut self) {
+        self.0.lock().unwrap().ended = true;
+    }
+}
+
+#[test]
+fn observers_and_counters_are_told_about_the_packets() {
+    let mut blacklist = Blacklist::default();
+    blacklist.add("from 10.0.0.2").unwrap();
+    let counters = Arc::new(Counters::default());
+    let sniffer = Sniffer::builder()
+        .source(Memory::from_frames(
+            (1..=4).map(|host| udp(host, 1000 + host as u16)),
+        ))
+        .blacklist(blacklist)
+        .counters(counters.clone())
+        .build()
+        .unwrap();
+    let seen = Arc::new(Mutex::new(Seen::default()));
+
+    Pipeline::start(sniffer, Some(2), None, vec![Box::new(Ports(seen.clone()))])
+        .join()
+        .unwrap();
+
+    let seen = seen.lock().unwrap();
+    assert_eq!(seen.ports, [1001, 1003, 1004]);
+    assert_eq!(seen.rules, ["from 10.0.0.2"]);
+    assert!(seen.ended);
+    assert_eq!(counters.captured.load(Ordering::Relaxed), 4);
+    assert_eq!(counters.parsed.load(Ordering::Relaxed), 4);
+    assert_eq!(counters.blacklisted.load(Ordering::Relaxed), 1);
+}